@@ -0,0 +1,30 @@
+use icfpc2024::icfp::surface::compile_source;
+use std::{
+    env, fs,
+    io::{stdin, Read},
+    process,
+};
+
+// compile a program of the surface language, from a file or stdin, into ICFP
+fn main() {
+    let text = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("Error: cannot read {}: {}", path, e);
+            process::exit(1);
+        }),
+        None => {
+            let mut buffer = String::new();
+            stdin().read_to_string(&mut buffer).unwrap();
+            buffer
+        }
+    };
+    let result = match compile_source(&text) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    println!("{}", result.to_string());
+}
@@ -0,0 +1,24 @@
+use icfpc2024::icfp::{decompiler::decompile_to_string, parser::Parser, tokenizer::Tokenizer};
+use std::{io::stdin, process};
+
+fn main() {
+    let text = {
+        let mut buffer = String::new();
+        stdin().read_line(&mut buffer).unwrap();
+        buffer
+    };
+    let text = text.trim();
+    let mut tokenizer = Tokenizer::new(text);
+    let result = tokenizer
+        .tokenize()
+        .and_then(|tokens| Parser::new(&tokens).parse());
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+
+    println!("{}", decompile_to_string(&result));
+}
@@ -1,8 +1,6 @@
 use dotenv::dotenv;
 use icfpc2024::icfp::{
-    evaluator::Evaluator,
-    parser::{Node, Parser},
-    tokenizer::Tokenizer,
+    evaluator::evaluate_string,
     util::{convert_string, deconvert_string},
 };
 use reqwest;
@@ -51,14 +49,10 @@ async fn main() {
             Ok(res) => {
                 let body = res.text().await.unwrap();
                 eprintln!("body: {}", body);
-                let tokens = Tokenizer::new(&body).tokenize();
-                let node = Parser::new(&tokens).parse();
-                let result = Evaluator::new(node).evaluate();
-                let text = match result {
-                    Node::String(text) => text,
-                    _ => panic!("Result is not a string"),
-                };
-                std::fs::write(format!("{}/{}.txt", SAVE_DIR, i), text).unwrap();
+                match evaluate_string(&body) {
+                    Ok(text) => std::fs::write(format!("{}/{}.txt", SAVE_DIR, i), text).unwrap(),
+                    Err(e) => eprintln!("Error: {} (id: {})", e, i),
+                }
             }
             Err(e) => {
                 eprintln!("Error: {:?}", e);
@@ -1,29 +1,250 @@
-use icfpc2024::icfp::evaluator::Evaluator;
+use icfpc2024::icfp::debugger::{Breakpoint, Debugger};
+use icfpc2024::icfp::error::EvalError;
+use icfpc2024::icfp::evaluator::{Evaluator, Strategy, BETA_REDUCTION_LIMIT};
 use icfpc2024::icfp::parser::{Node, Parser};
+use icfpc2024::icfp::profiler::Profile;
 use icfpc2024::icfp::tokenizer::Tokenizer;
-use std::io::stdin;
+use std::{
+    env,
+    io::{stdin, stdout, Write},
+    process,
+};
+
+fn run(
+    text: &str,
+    strategy: Strategy,
+    limit: bool,
+    profile: bool,
+    strict: bool,
+) -> Result<(Node, Option<Profile>), EvalError> {
+    let mut tokenizer = if strict {
+        Tokenizer::strict(text)
+    } else {
+        Tokenizer::new(text)
+    };
+    let result = tokenizer.tokenize()?;
+    let mut parser = Parser::new(&result);
+    let result = parser.parse()?;
+    for diagnostic in parser.diagnostics() {
+        eprintln!("{}", diagnostic);
+    }
+    let mut evaluator = Evaluator::with_strategy(result, strategy);
+    if limit {
+        evaluator = evaluator.with_beta_limit(BETA_REDUCTION_LIMIT);
+    }
+    if profile {
+        evaluator = evaluator.with_profiler();
+    }
+    let result = evaluator.evaluate();
+    let (count, beta_reductions) = (evaluator.eval_count(), evaluator.beta_reductions());
+    match strategy {
+        Strategy::Bytecode => eprintln!(
+            "Executed {} instructions, {} beta reductions",
+            count, beta_reductions
+        ),
+        _ => eprintln!(
+            "Evaluated {} nodes, {} beta reductions",
+            count, beta_reductions
+        ),
+    }
+    Ok((result?, evaluator.take_profile()))
+}
+
+const DEBUG_HELP: &str = "\
+s, step [n]       reduce the next n redexes
+c, continue       reduce until a breakpoint or the end
+b, back [n]       undo the last n steps
+break op <op>     stop before reducing an operator, ? for if
+break lambda <n>  stop before applying the lambda binding variable n
+delete op <op>    remove a breakpoint, likewise for lambda
+p, print [depth]  show the whole term, the redex is marked with >
+r, redex [depth]  show the redex
+t, term           print the term as ICFP source
+q, quit";
+
+fn parse_breakpoint(args: &[&str]) -> Option<Breakpoint> {
+    match args {
+        ["op", operator] => Some(Breakpoint::Operator(operator.to_string())),
+        ["lambda", id] => id.parse().ok().map(Breakpoint::Lambda),
+        _ => None,
+    }
+}
+
+fn debug(text: &str) -> Result<(), EvalError> {
+    let tokens = Tokenizer::new(text).tokenize()?;
+    let node = Parser::new(&tokens).parse()?;
+    let mut debugger = Debugger::new(node);
+    print!("{}", debugger.render(6));
+    let mut last = String::new();
+    loop {
+        print!("(debug) ");
+        stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap() == 0 {
+            return Ok(());
+        }
+        // an empty line repeats the previous command
+        if !line.trim().is_empty() {
+            last = line.trim().to_string();
+        }
+        let args: Vec<&str> = last.split_whitespace().collect();
+        let count = |index: usize, default: usize| {
+            args.get(index)
+                .and_then(|arg| arg.parse().ok())
+                .unwrap_or(default)
+        };
+        match args.first().copied().unwrap_or("") {
+            "s" | "step" => {
+                for _ in 0..count(1, 1) {
+                    match debugger.step() {
+                        Ok(Some(step)) => {
+                            println!("{} => {}", step.redex.to_string(), step.result.to_string())
+                        }
+                        Ok(None) => {
+                            println!("finished");
+                            break;
+                        }
+                        Err(e) => {
+                            println!("Error: {}", e);
+                            break;
+                        }
+                    }
+                }
+            }
+            "c" | "continue" => match debugger.run() {
+                Ok(Some(breakpoint)) => {
+                    println!("breakpoint {:?}", breakpoint);
+                    print!("{}", debugger.render_redex(2));
+                }
+                Ok(None) => println!("finished"),
+                Err(e) => println!("Error: {}", e),
+            },
+            "b" | "back" => {
+                for _ in 0..count(1, 1) {
+                    if !debugger.back() {
+                        println!("no more history");
+                        break;
+                    }
+                }
+            }
+            "break" => match parse_breakpoint(&args[1..]) {
+                Some(breakpoint) => debugger.add_breakpoint(breakpoint),
+                None => println!("usage: break op <op> | break lambda <n>"),
+            },
+            "delete" => match parse_breakpoint(&args[1..]) {
+                Some(breakpoint) if debugger.remove_breakpoint(&breakpoint) => {}
+                _ => println!("no such breakpoint"),
+            },
+            "p" | "print" => print!("{}", debugger.render(count(1, 6))),
+            "r" | "redex" => print!("{}", debugger.render_redex(count(1, 6))),
+            "t" | "term" => println!("{}", debugger.term().to_string()),
+            "q" | "quit" => return Ok(()),
+            _ => println!("{}", DEBUG_HELP),
+        }
+        println!(
+            "step {}, {} beta reductions",
+            debugger.steps(),
+            debugger.beta_reductions()
+        );
+    }
+}
+
+fn check(text: &str, strict: bool) -> bool {
+    let tokenizer = if strict {
+        Tokenizer::strict(text).tokenize()
+    } else {
+        Tokenizer::new(text).tokenize()
+    };
+    let tokens = match tokenizer {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return false;
+        }
+    };
+    let mut parser = Parser::new(&tokens).with_recovery();
+    let node = parser.parse().unwrap();
+    for diagnostic in parser.diagnostics() {
+        eprintln!("{}", diagnostic);
+    }
+    node.dump_tree(0);
+    parser.diagnostics().iter().all(|d| !d.is_error())
+}
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    // --profile prints a report per lambda, --folded <path> writes stacks for flamegraph tools
+    let folded = args
+        .iter()
+        .position(|arg| arg == "--folded")
+        .and_then(|index| args.get(index + 1));
+    let profile = folded.is_some() || args.iter().any(|arg| arg == "--profile");
+    let strategy = if args.iter().any(|arg| arg == "--vm") {
+        if profile {
+            eprintln!("Error: --profile needs an environment machine, it cannot be used with --vm");
+            process::exit(1);
+        }
+        Strategy::Bytecode
+    } else if args.iter().any(|arg| arg == "--lazy") {
+        Strategy::CallByNeed
+    } else if args.iter().any(|arg| arg == "--name") {
+        Strategy::CallByName
+    } else if profile {
+        // the profiler needs the environment machine, count like the official evaluator
+        eprintln!("Profiling with call-by-name evaluation, pass --lazy for call-by-need");
+        Strategy::CallByName
+    } else {
+        Strategy::Substitution
+    };
+    // abort like the official evaluator once the beta reduction budget is exhausted
+    let limit = env::args().any(|arg| arg == "--limit");
+    // reject unknown indicators and malformed token bodies instead of reading past them
+    let strict = env::args().any(|arg| arg == "--strict");
     let text = {
         let mut buffer = String::new();
         stdin().read_line(&mut buffer).unwrap();
         buffer
     };
     let text = text.trim();
-    let mut tokenizer = Tokenizer::new(text);
-    let result = tokenizer.tokenize();
-    let mut parser = Parser::new(&result);
-    let result = parser.parse();
-    let mut evaluator = Evaluator::new(result);
-    let result = evaluator.evaluate();
+    // parse with placeholders for missing operands and show the tree instead of evaluating
+    if env::args().any(|arg| arg == "--check") {
+        if !check(text, strict) {
+            process::exit(1);
+        }
+        return;
+    }
+    // the program is read from the first line, the following lines are debugger commands
+    if env::args().any(|arg| arg == "--debug") {
+        if let Err(e) = debug(text) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+    let (result, profile) = match run(text, strategy, limit, profile, strict) {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(profile) = profile {
+        eprint!("{}", profile.report());
+        if let Some(path) = folded {
+            std::fs::write(path, profile.folded()).unwrap();
+        }
+    }
 
-    match result {
+    match &result {
         Node::String(s) => println!("{}", s),
         Node::Integer(n) => println!("{}", n),
         _ => {
-            println!("=== unexpected result ===");
-            result.dump_tree(0);
-            panic!("Unexpected result: {:?}", result)
+            let e = EvalError::UnexpectedResult {
+                expected: "String or Integer".to_string(),
+                actual: result.name(),
+            };
+            eprintln!("Error: {}", e);
+            process::exit(1);
         }
     }
 }
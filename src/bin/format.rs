@@ -0,0 +1,45 @@
+use icfpc2024::icfp::{
+    formatter::{strip_comments, Formatter},
+    parser::Parser,
+    tokenizer::Tokenizer,
+};
+use std::{
+    env,
+    io::{stdin, Read},
+    process,
+};
+
+// --compact keeps subtrees that fit on one line, --comments decodes strings and integers;
+// formatted input is read back too, --strip prints it as plain ICFP again
+fn main() {
+    let text = {
+        let mut buffer = String::new();
+        stdin().read_to_string(&mut buffer).unwrap();
+        buffer
+    };
+    let text = strip_comments(&text);
+    if env::args().any(|arg| arg == "--strip") {
+        println!("{}", text);
+        return;
+    }
+    let mut tokenizer = Tokenizer::new(&text);
+    let result = tokenizer
+        .tokenize()
+        .and_then(|tokens| Parser::new(&tokens).parse());
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let mut formatter = Formatter::new();
+    if env::args().any(|arg| arg == "--compact") {
+        formatter = formatter.compact();
+    }
+    if env::args().any(|arg| arg == "--comments") {
+        formatter = formatter.with_comments();
+    }
+
+    println!("{}", formatter.format(&result));
+}
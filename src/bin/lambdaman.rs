@@ -0,0 +1,37 @@
+use icfpc2024::icfp::lambdaman::{encode, parse_solution};
+use std::{
+    env, fs,
+    io::{stdin, Read},
+    process,
+};
+
+// turn a lambdaman answer, "solve lambdamanN PATH" from a file or stdin, into the shortest
+// ICFP program found that evaluates to it
+fn main() {
+    let text = match env::args().nth(1) {
+        Some(path) => fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("Error: cannot read {}: {}", path, e);
+            process::exit(1);
+        }),
+        None => {
+            let mut buffer = String::new();
+            stdin().read_to_string(&mut buffer).unwrap();
+            buffer
+        }
+    };
+    let Some((problem, path)) = parse_solution(&text) else {
+        eprintln!("Error: expected solve lambdamanN followed by the moves");
+        process::exit(1);
+    };
+    match encode(problem, path) {
+        Ok(node) => {
+            let program = node.to_string();
+            eprintln!("{} characters, {} moves", program.len(), path.len());
+            println!("{}", program);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
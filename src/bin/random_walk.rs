@@ -0,0 +1,64 @@
+use icfpc2024::icfp::random_walk::{Grid, Searcher};
+use std::{env, fs, process, time::Duration};
+
+// search for a pseudo-random walk that solves lambdaman problem N, read from the given
+// grid file or problems/lambdaman/N.txt, and print the ICFP program generating it;
+// --time <seconds> bounds the search, which gives up after a minute by default
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let time_limit = match args.iter().position(|arg| arg == "--time") {
+        Some(index) => {
+            let seconds = args.get(index + 1).and_then(|seconds| seconds.parse().ok());
+            let Some(seconds) = seconds else {
+                eprintln!("Usage: random_walk <problem> [grid] [--time <seconds>]");
+                process::exit(1);
+            };
+            args.drain(index..index + 2);
+            Some(Duration::from_secs(seconds))
+        }
+        None => None,
+    };
+    let Some(problem) = args
+        .get(1)
+        .and_then(|problem| problem.parse::<usize>().ok())
+    else {
+        eprintln!("Usage: random_walk <problem> [grid] [--time <seconds>]");
+        process::exit(1);
+    };
+    let path = args
+        .get(2)
+        .cloned()
+        .unwrap_or_else(|| format!("problems/lambdaman/{}.txt", problem));
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Error: cannot read {}: {}", path, e);
+        process::exit(1);
+    });
+    let Some(grid) = Grid::parse(&text) else {
+        eprintln!("Error: {} is not a lambdaman grid", path);
+        process::exit(1);
+    };
+    let mut searcher = Searcher::new(&grid);
+    if let Some(time_limit) = time_limit {
+        searcher = searcher.with_time_limit(time_limit);
+    }
+    let Some(walk) = searcher.search() else {
+        eprintln!("Error: no walk eats every pill");
+        process::exit(1);
+    };
+    match walk.check(problem) {
+        Ok(beta_reductions) => {
+            let program = walk.program(problem).to_string();
+            eprintln!(
+                "{} characters, {:?}, about {} beta reductions",
+                program.len(),
+                walk,
+                beta_reductions
+            );
+            println!("{}", program);
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+}
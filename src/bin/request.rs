@@ -1,10 +1,5 @@
 use dotenv::dotenv;
-use icfpc2024::icfp::{
-    evaluator::Evaluator,
-    parser::{Node, Parser},
-    tokenizer::Tokenizer,
-    util::deconvert_string,
-};
+use icfpc2024::icfp::{evaluator::evaluate_string, util::deconvert_string};
 use reqwest;
 use std::{env, io::stdin};
 
@@ -46,13 +41,10 @@ async fn main() {
             eprintln!("=== Response Start ===");
             eprintln!("{}", body);
             eprintln!("=== Response End ===");
-            let tokens = Tokenizer::new(&body).tokenize();
-            let node = Parser::new(&tokens).parse();
-            let result = Evaluator::new(node).evaluate();
             eprintln!("=== Result Start ===");
-            match result {
-                Node::String(s) => println!("{}", s),
-                _ => panic!("Unexpected result: {:?}", result),
+            match evaluate_string(&body) {
+                Ok(s) => println!("{}", s),
+                Err(e) => eprintln!("Error: {}", e),
             }
             eprintln!("=== Result End ===");
         }
@@ -1,5 +1,5 @@
 use icfpc2024::icfp::tokenizer::Tokenizer;
-use std::io::stdin;
+use std::{io::stdin, process};
 
 fn main() {
     let text = {
@@ -9,7 +9,13 @@ fn main() {
     };
     let text = text.trim();
     let mut tokenizer = Tokenizer::new(text);
-    let result = tokenizer.tokenize();
+    let result = match tokenizer.tokenize() {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
 
     for token in result {
         println!("{:?}", token);
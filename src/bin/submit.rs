@@ -1,10 +1,5 @@
 use dotenv::dotenv;
-use icfpc2024::icfp::{
-    evaluator::Evaluator,
-    parser::{Node, Parser},
-    tokenizer::Tokenizer,
-    util::deconvert_string,
-};
+use icfpc2024::icfp::{evaluator::evaluate_string, util::deconvert_string};
 use reqwest;
 use std::env;
 use std::time;
@@ -56,14 +51,10 @@ async fn main() {
         match res {
             Ok(res) => {
                 let body = res.text().await.unwrap();
-                let tokens = Tokenizer::new(&body).tokenize();
-                let node = Parser::new(&tokens).parse();
-                let result = Evaluator::new(node).evaluate();
-                let text = match result {
-                    Node::String(text) => text,
-                    _ => panic!("Result is not a string"),
-                };
-                println!("{}", text);
+                match evaluate_string(&body) {
+                    Ok(text) => println!("{}", text),
+                    Err(e) => eprintln!("Error: {} (id: {})", e, id),
+                }
             }
             Err(e) => {
                 eprintln!("Error: {:?}", e);
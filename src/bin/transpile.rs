@@ -1,7 +1,14 @@
-use icfpc2024::icfp::{parser::Parser, tokenizer::Tokenizer, transpiler::Transpiler};
-use std::io::stdin;
+use icfpc2024::icfp::{
+    parser::Parser,
+    tokenizer::Tokenizer,
+    transpiler::{javascript::JavaScriptTranspiler, rust::RustTranspiler, Transpiler},
+};
+use std::{env, io::stdin, process};
 
+// print a lisp program, with --rust a standalone rust source file depending on num_bigint,
+// or with --javascript an ES module exporting `evaluate`
 fn main() {
+    let flags: Vec<String> = env::args().skip(1).collect();
     let text = {
         let mut buffer = String::new();
         stdin().read_line(&mut buffer).unwrap();
@@ -9,11 +16,23 @@ fn main() {
     };
     let text = text.trim();
     let mut tokenizer = Tokenizer::new(text);
-    let result = tokenizer.tokenize();
-    let mut parser = Parser::new(&result);
-    let result = parser.parse();
-    let transpiler = Transpiler::new(result);
-    let result = transpiler.transpile();
+    let result = tokenizer
+        .tokenize()
+        .and_then(|tokens| Parser::new(&tokens).parse());
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let result = if flags.iter().any(|flag| flag == "--rust") {
+        RustTranspiler::new(result).transpile()
+    } else if flags.iter().any(|flag| flag == "--javascript") {
+        JavaScriptTranspiler::new(result).transpile()
+    } else {
+        Transpiler::new(result).transpile_program()
+    };
 
     println!("{}", result);
 }
@@ -1,6 +1,21 @@
+pub mod arena;
+pub mod builder;
+pub mod bytecode;
+pub mod compressor;
+pub mod debugger;
+pub mod decompiler;
+pub mod error;
 pub mod evaluator;
+pub mod formatter;
+pub mod lambdaman;
+pub mod lazy_evaluator;
+pub mod optimizer;
 pub mod parser;
+pub mod profiler;
+pub mod random_walk;
+pub mod surface;
 pub mod tokenizer;
 pub mod transpiler;
 pub mod util;
+pub mod vm;
 pub mod builtin;
@@ -0,0 +1,677 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use super::error::EvalError;
+use super::parser::Node;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+    StringToInt,
+    IntToString,
+}
+
+impl UnaryOp {
+    pub fn parse(operator: &str) -> Option<UnaryOp> {
+        match operator {
+            "-" => Some(UnaryOp::Negate),
+            "!" => Some(UnaryOp::Not),
+            "#" => Some(UnaryOp::StringToInt),
+            "$" => Some(UnaryOp::IntToString),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnaryOp::Negate => "-",
+            UnaryOp::Not => "!",
+            UnaryOp::StringToInt => "#",
+            UnaryOp::IntToString => "$",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    LessThan,
+    GreaterThan,
+    Equal,
+    Or,
+    And,
+    Concat,
+    Take,
+    Drop,
+    Apply,
+    ApplyStrict,
+    ApplyLazy,
+}
+
+impl BinaryOp {
+    pub fn parse(operator: &str) -> Option<BinaryOp> {
+        match operator {
+            "+" => Some(BinaryOp::Add),
+            "-" => Some(BinaryOp::Subtract),
+            "*" => Some(BinaryOp::Multiply),
+            "/" => Some(BinaryOp::Divide),
+            "%" => Some(BinaryOp::Modulo),
+            "<" => Some(BinaryOp::LessThan),
+            ">" => Some(BinaryOp::GreaterThan),
+            "=" => Some(BinaryOp::Equal),
+            "|" => Some(BinaryOp::Or),
+            "&" => Some(BinaryOp::And),
+            "." => Some(BinaryOp::Concat),
+            "T" => Some(BinaryOp::Take),
+            "D" => Some(BinaryOp::Drop),
+            "$" => Some(BinaryOp::Apply),
+            "!" => Some(BinaryOp::ApplyStrict),
+            "~" => Some(BinaryOp::ApplyLazy),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::LessThan => "<",
+            BinaryOp::GreaterThan => ">",
+            BinaryOp::Equal => "=",
+            BinaryOp::Or => "|",
+            BinaryOp::And => "&",
+            BinaryOp::Concat => ".",
+            BinaryOp::Take => "T",
+            BinaryOp::Drop => "D",
+            BinaryOp::Apply => "$",
+            BinaryOp::ApplyStrict => "!",
+            BinaryOp::ApplyLazy => "~",
+        }
+    }
+
+    pub fn is_application(&self) -> bool {
+        matches!(
+            self,
+            BinaryOp::Apply | BinaryOp::ApplyStrict | BinaryOp::ApplyLazy
+        )
+    }
+}
+
+// an index into an Arena; equal terms of the same arena always get the same id
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// variables are indices into the variable table of the arena, not the ids of the program
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Term {
+    Integer(BigInt),
+    String(String),
+    Boolean(bool),
+    Variable(usize),
+    Unary(UnaryOp, NodeId),
+    Binary(BinaryOp, NodeId, NodeId),
+    If(NodeId, NodeId, NodeId),
+    Lambda(usize, NodeId),
+}
+
+impl Term {
+    pub fn children(&self) -> Vec<NodeId> {
+        match self {
+            Term::Unary(_, operand) | Term::Lambda(_, operand) => vec![*operand],
+            Term::Binary(_, left, right) => vec![*left, *right],
+            Term::If(condition, then_branch, else_branch) => {
+                vec![*condition, *then_branch, *else_branch]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+// hash-consed terms: every distinct term is stored once and shared by id.
+// Terms nothing refers to any more are reclaimed by `collect`, which the owner runs whenever
+// `needs_collection` says so: once the bytes interned since the last collection exceed both the
+// threshold and the bytes that survived it. The arena therefore holds at most max(threshold,
+// live) bytes of garbage on top of its live terms, and each collection is paid for by as many
+// bytes of allocation as it has to visit. The variable table is kept, it only grows by the
+// binders renamed to avoid capture.
+pub struct Arena {
+    // None for the slots of collected terms, which are reused by later terms
+    terms: Vec<Option<Rc<Term>>>,
+    vacant: Vec<NodeId>,
+    ids: HashMap<Rc<Term>, NodeId>,
+    free: HashMap<NodeId, Rc<HashSet<usize>>>,
+    variables: Vec<BigInt>,
+    variable_ids: HashMap<BigInt, usize>,
+    // larger than every variable id seen so far, used for fresh names
+    next_variable: BigInt,
+    // approximate sizes in bytes
+    allocated: usize,
+    live: usize,
+    threshold: usize,
+}
+
+// collections start once this many bytes are interned
+pub const COLLECTION_THRESHOLD: usize = 64 << 20;
+
+impl Default for Arena {
+    fn default() -> Arena {
+        Arena::with_collection_threshold(COLLECTION_THRESHOLD)
+    }
+}
+
+impl Arena {
+    pub fn new() -> Arena {
+        Arena::default()
+    }
+
+    pub fn with_collection_threshold(threshold: usize) -> Arena {
+        Arena {
+            terms: vec![],
+            vacant: vec![],
+            ids: HashMap::new(),
+            free: HashMap::new(),
+            variables: vec![],
+            variable_ids: HashMap::new(),
+            next_variable: BigInt::from(0),
+            allocated: 0,
+            live: 0,
+            threshold,
+        }
+    }
+
+    // the number of terms currently stored
+    pub fn len(&self) -> usize {
+        self.terms.len() - self.vacant.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // terms are reference counted, so holding one does not borrow the arena
+    pub fn get(&self, id: NodeId) -> Rc<Term> {
+        self.terms[id.index()]
+            .clone()
+            .expect("the term has been collected")
+    }
+
+    pub fn intern(&mut self, term: Term) -> NodeId {
+        if let Some(&id) = self.ids.get(&term) {
+            return id;
+        }
+        self.allocated += size(&term);
+        let term = Rc::new(term);
+        let id = match self.vacant.pop() {
+            Some(id) => {
+                self.terms[id.index()] = Some(term.clone());
+                id
+            }
+            None => {
+                self.terms.push(Some(term.clone()));
+                NodeId(self.terms.len() as u32 - 1)
+            }
+        };
+        self.ids.insert(term, id);
+        id
+    }
+
+    pub fn needs_collection(&self) -> bool {
+        self.allocated > self.threshold.max(self.live)
+    }
+
+    // remove every term that cannot be reached from `roots`; the ids of removed terms are
+    // handed out again, so ids kept anywhere else become invalid
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = NodeId>) {
+        let mut marked = vec![false; self.terms.len()];
+        let mut stack: Vec<NodeId> = roots.into_iter().collect();
+        while let Some(id) = stack.pop() {
+            if !marked[id.index()] {
+                marked[id.index()] = true;
+                stack.extend(self.get(id).children());
+            }
+        }
+        self.live = 0;
+        for (index, slot) in self.terms.iter_mut().enumerate() {
+            match slot {
+                Some(term) if marked[index] => self.live += size(term),
+                Some(_) => {
+                    let id = NodeId(index as u32);
+                    self.ids.remove(&slot.take().unwrap());
+                    self.free.remove(&id);
+                    self.vacant.push(id);
+                }
+                None => {}
+            }
+        }
+        self.allocated = 0;
+    }
+
+    // the index of a variable id of the program
+    pub fn variable(&mut self, id: &BigInt) -> usize {
+        if let Some(&index) = self.variable_ids.get(id) {
+            return index;
+        }
+        if *id >= self.next_variable {
+            self.next_variable = id + 1;
+        }
+        self.variables.push(id.clone());
+        self.variable_ids
+            .insert(id.clone(), self.variables.len() - 1);
+        self.variables.len() - 1
+    }
+
+    pub fn variable_id(&self, index: usize) -> &BigInt {
+        &self.variables[index]
+    }
+
+    // a variable whose id is not used anywhere in the arena
+    fn fresh_variable(&mut self) -> usize {
+        let id = self.next_variable.clone();
+        self.variable(&id)
+    }
+
+    pub fn name(&self, id: NodeId) -> String {
+        match self.get(id).as_ref() {
+            Term::Integer(_) => "Integer".to_string(),
+            Term::String(_) => "String".to_string(),
+            Term::Boolean(_) => "Boolean".to_string(),
+            Term::Variable(_) => "Variable".to_string(),
+            Term::Unary(operator, _) => format!("UnaryOperator({})", operator.as_str()),
+            Term::Binary(operator, _, _) => format!("BinaryOperator({})", operator.as_str()),
+            Term::If(_, _, _) => "If".to_string(),
+            Term::Lambda(arity, _) => format!("Lambda({})", self.variable_id(*arity)),
+        }
+    }
+
+    pub fn from_node(&mut self, node: &Node) -> Result<NodeId, EvalError> {
+        // children are interned before their parent, so walk in post-order
+        let mut stack = vec![(node, false)];
+        let mut ids = vec![];
+        while let Some((node, visited)) = stack.pop() {
+            let children: Vec<&Node> = match node {
+                Node::UnaryOperator(_, operand) | Node::Lambda(_, operand) => vec![operand],
+                Node::BinaryOperator(_, left, right) => vec![left, right],
+                Node::If(condition, then_branch, else_branch) => {
+                    vec![condition, then_branch, else_branch]
+                }
+                _ => vec![],
+            };
+            if !visited && !children.is_empty() {
+                stack.push((node, true));
+                stack.extend(children.into_iter().rev().map(|child| (child, false)));
+                continue;
+            }
+            let mut pop = || ids.pop().unwrap();
+            let term = match node {
+                Node::Integer(value) => Term::Integer(value.clone()),
+                Node::String(value) => Term::String(value.clone()),
+                Node::Boolean(value) => Term::Boolean(*value),
+                Node::Variable(index) => Term::Variable(self.variable(index)),
+                Node::UnaryOperator(operator, _) => match UnaryOp::parse(operator) {
+                    Some(operator) => Term::Unary(operator, pop()),
+                    None => {
+                        return Err(EvalError::UnknownUnaryOperator {
+                            operator: operator.clone(),
+                        })
+                    }
+                },
+                Node::BinaryOperator(operator, _, _) => match BinaryOp::parse(operator) {
+                    Some(operator) => {
+                        let right = pop();
+                        Term::Binary(operator, pop(), right)
+                    }
+                    None => {
+                        return Err(EvalError::UnknownBinaryOperator {
+                            operator: operator.clone(),
+                        })
+                    }
+                },
+                Node::If(_, _, _) => {
+                    let else_branch = pop();
+                    let then_branch = pop();
+                    Term::If(pop(), then_branch, else_branch)
+                }
+                Node::Lambda(arity, _) => {
+                    let body = pop();
+                    Term::Lambda(self.variable(arity), body)
+                }
+            };
+            let id = self.intern(term);
+            ids.push(id);
+        }
+        Ok(ids.pop().unwrap())
+    }
+
+    pub fn to_node(&self, id: NodeId) -> Node {
+        let mut stack = vec![(id, false)];
+        let mut nodes: Vec<Node> = vec![];
+        while let Some((id, visited)) = stack.pop() {
+            let term = self.get(id);
+            let children = term.children();
+            if !visited && !children.is_empty() {
+                stack.push((id, true));
+                stack.extend(children.into_iter().rev().map(|child| (child, false)));
+                continue;
+            }
+            let mut pop = || Box::new(nodes.pop().unwrap());
+            let node = match term.as_ref() {
+                Term::Integer(value) => Node::Integer(value.clone()),
+                Term::String(value) => Node::String(value.clone()),
+                Term::Boolean(value) => Node::Boolean(*value),
+                Term::Variable(index) => Node::Variable(self.variable_id(*index).clone()),
+                Term::Unary(operator, _) => {
+                    Node::UnaryOperator(operator.as_str().to_string(), pop())
+                }
+                Term::Binary(operator, _, _) => {
+                    let right = pop();
+                    Node::BinaryOperator(operator.as_str().to_string(), pop(), right)
+                }
+                Term::If(_, _, _) => {
+                    let else_branch = pop();
+                    let then_branch = pop();
+                    Node::If(pop(), then_branch, else_branch)
+                }
+                Term::Lambda(arity, _) => Node::Lambda(self.variable_id(*arity).clone(), pop()),
+            };
+            nodes.push(node);
+        }
+        nodes.pop().unwrap()
+    }
+
+    // memoized per id, so shared subterms are only visited once
+    pub fn free_variables(&mut self, id: NodeId) -> Rc<HashSet<usize>> {
+        let mut stack = vec![id];
+        while let Some(&id) = stack.last() {
+            if self.free.contains_key(&id) {
+                stack.pop();
+                continue;
+            }
+            let term = self.get(id);
+            let missing: Vec<NodeId> = term
+                .children()
+                .into_iter()
+                .filter(|child| !self.free.contains_key(child))
+                .collect();
+            if !missing.is_empty() {
+                stack.extend(missing);
+                continue;
+            }
+            stack.pop();
+            let free = match term.as_ref() {
+                Term::Variable(index) => Rc::new(HashSet::from([*index])),
+                Term::Lambda(arity, body) => {
+                    let body = &self.free[body];
+                    if body.contains(arity) {
+                        let mut free = body.as_ref().clone();
+                        free.remove(arity);
+                        Rc::new(free)
+                    } else {
+                        body.clone()
+                    }
+                }
+                term => {
+                    let children: Vec<&Rc<HashSet<usize>>> = term
+                        .children()
+                        .iter()
+                        .map(|child| &self.free[child])
+                        .filter(|free| !free.is_empty())
+                        .collect();
+                    match children.as_slice() {
+                        [] => Rc::new(HashSet::new()),
+                        [only] => (*only).clone(),
+                        _ => Rc::new(
+                            children
+                                .iter()
+                                .flat_map(|free| free.iter().copied())
+                                .collect(),
+                        ),
+                    }
+                }
+            };
+            self.free.insert(id, free);
+        }
+        self.free[&id].clone()
+    }
+
+    // capture-avoiding substitution of `value` for the free occurrences of `variable`;
+    // subterms without those occurrences are shared with `body` instead of rebuilt
+    pub fn substitute(&mut self, body: NodeId, variable: usize, value: NodeId) -> NodeId {
+        let value_free = self.free_variables(value);
+        let mut memo: HashMap<(NodeId, Rc<Substitution>), NodeId> = HashMap::new();
+        let mut tasks = vec![Task::Visit(
+            body,
+            Rc::new(Substitution(vec![(variable, value)])),
+        )];
+        let mut results: Vec<NodeId> = vec![];
+        while let Some(task) = tasks.pop() {
+            let (id, substitution, binder) = match task {
+                Task::Visit(id, substitution) => {
+                    let free = self.free_variables(id);
+                    if !substitution.0.iter().any(|(from, _)| free.contains(from)) {
+                        results.push(id);
+                        continue;
+                    }
+                    if let Some(&result) = memo.get(&(id, substitution.clone())) {
+                        results.push(result);
+                        continue;
+                    }
+                    let term = self.get(id);
+                    match term.as_ref() {
+                        Term::Variable(index) => {
+                            results.push(substitution.get(*index).unwrap());
+                            continue;
+                        }
+                        Term::Lambda(arity, body) => {
+                            let mut inner = substitution.as_ref().clone();
+                            inner.remove(*arity);
+                            let mut binder = *arity;
+                            if value_free.contains(arity)
+                                && inner.get(variable).is_some()
+                                && self.free_variables(*body).contains(&variable)
+                            {
+                                // the binder would capture a free variable of the value
+                                binder = self.fresh_variable();
+                                let renamed = self.intern(Term::Variable(binder));
+                                inner.0.push((*arity, renamed));
+                            }
+                            tasks.push(Task::Build(id, substitution, binder));
+                            tasks.push(Task::Visit(*body, Rc::new(inner)));
+                        }
+                        term => {
+                            tasks.push(Task::Build(id, substitution.clone(), 0));
+                            for child in term.children().into_iter().rev() {
+                                tasks.push(Task::Visit(child, substitution.clone()));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                Task::Build(id, substitution, binder) => (id, substitution, binder),
+            };
+            let term = self.get(id);
+            let children = results.split_off(results.len() - term.children().len());
+            let result = self.intern(match term.as_ref() {
+                Term::Unary(operator, _) => Term::Unary(*operator, children[0]),
+                Term::Binary(operator, _, _) => Term::Binary(*operator, children[0], children[1]),
+                Term::If(_, _, _) => Term::If(children[0], children[1], children[2]),
+                Term::Lambda(_, _) => Term::Lambda(binder, children[0]),
+                _ => unreachable!("leaves are never rebuilt"),
+            });
+            memo.insert((id, substitution), result);
+            results.push(result);
+        }
+        results.pop().unwrap()
+    }
+}
+
+// the bytes a term takes in the arena, including the map entry pointing back at it
+fn size(term: &Term) -> usize {
+    let heap = match term {
+        Term::Integer(value) => value.bits() as usize / 8,
+        Term::String(value) => value.len(),
+        _ => 0,
+    };
+    std::mem::size_of::<Term>() + 2 * std::mem::size_of::<Option<Rc<Term>>>() + heap
+}
+
+// variables to replace, together with their replacements
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+struct Substitution(Vec<(usize, NodeId)>);
+
+impl Substitution {
+    fn get(&self, variable: usize) -> Option<NodeId> {
+        self.0
+            .iter()
+            .find(|(from, _)| *from == variable)
+            .map(|(_, to)| *to)
+    }
+
+    fn remove(&mut self, variable: usize) {
+        self.0.retain(|(from, _)| *from != variable);
+    }
+}
+
+enum Task {
+    Visit(NodeId, Rc<Substitution>),
+    // rebuild the term from its substituted children; the lambda binder may have been renamed
+    Build(NodeId, Rc<Substitution>, usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = [
+            "B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK",
+            "? B> I# I$ S9%3 S./",
+            "U$ B~ L# B! L$ v$ U- v# I#",
+        ];
+        for input in inputs {
+            let node = parse(input);
+            let mut arena = Arena::new();
+            let id = arena.from_node(&node).unwrap();
+            assert_eq!(arena.to_node(id), node);
+        }
+    }
+
+    #[test]
+    fn test_hash_consing() {
+        let mut arena = Arena::new();
+        // the two additions are the same term and are stored once
+        let id = arena.from_node(&parse("B* B+ I# I$ B+ I# I$")).unwrap();
+        assert_eq!(arena.len(), 4);
+        let Term::Binary(BinaryOp::Multiply, left, right) = *arena.get(id) else {
+            panic!("expected a multiplication");
+        };
+        assert_eq!(left, right);
+        assert_eq!(arena.from_node(&parse("B+ I# I$")).unwrap(), left);
+    }
+
+    #[test]
+    fn test_unknown_operator() {
+        let mut arena = Arena::new();
+        assert_eq!(
+            arena.from_node(&parse("B+ I# U@ I$")),
+            Err(EvalError::UnknownUnaryOperator {
+                operator: "@".to_string()
+            })
+        );
+        assert_eq!(
+            arena.from_node(&parse("B@ I# I$")),
+            Err(EvalError::UnknownBinaryOperator {
+                operator: "@".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_free_variables() {
+        let mut arena = Arena::new();
+        let id = arena.from_node(&parse("B$ L# B+ v# v$ v#")).unwrap();
+        let free: HashSet<BigInt> = arena
+            .free_variables(id)
+            .iter()
+            .map(|&index| arena.variable_id(index).clone())
+            .collect();
+        assert_eq!(free, HashSet::from([BigInt::from(2), BigInt::from(3)]));
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut arena = Arena::new();
+        let two = arena.variable(&BigInt::from(2));
+        let body = arena.from_node(&parse("B+ v# B* v$ I#")).unwrap();
+        let value = arena.from_node(&parse("I$")).unwrap();
+        let result = arena.substitute(body, two, value);
+        assert_eq!(arena.to_node(result), parse("B+ I$ B* v$ I#"));
+        // the untouched right operand is shared, not rebuilt
+        let (Term::Binary(_, _, before), Term::Binary(_, _, after)) = (
+            arena.get(body).as_ref().clone(),
+            arena.get(result).as_ref().clone(),
+        ) else {
+            panic!("expected additions");
+        };
+        assert_eq!(before, after);
+
+        // (λ1. v2)[v2 := v1] must rename the binder instead of capturing v1
+        let body = arena.from_node(&parse("L\" v#")).unwrap();
+        let value = arena.from_node(&Node::Variable(BigInt::from(1))).unwrap();
+        let result = arena.substitute(body, two, value);
+        match &arena.to_node(result) {
+            Node::Lambda(binder, body) => {
+                assert_ne!(*binder, BigInt::from(1));
+                assert_eq!(**body, Node::Variable(BigInt::from(1)));
+            }
+            result => panic!("expected a lambda, got {:?}", result),
+        }
+
+        // a shadowing binder stops the substitution
+        let body = arena.from_node(&parse("B. v# L# v#")).unwrap();
+        let value = arena.from_node(&parse("S#")).unwrap();
+        let result = arena.substitute(body, two, value);
+        assert_eq!(arena.to_node(result), parse("B. S# L# v#"));
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut arena = Arena::with_collection_threshold(0);
+        let kept = arena.from_node(&parse("B+ I# I$")).unwrap();
+        let dropped = arena.from_node(&parse("B. S# S$")).unwrap();
+        assert_eq!(arena.len(), 6);
+        assert!(arena.needs_collection());
+        arena.collect([kept]);
+        assert_eq!(arena.len(), 3);
+        assert!(!arena.needs_collection());
+        assert_eq!(arena.to_node(kept), parse("B+ I# I$"));
+        // the slots of collected terms are reused, and hash consing still finds the kept ones
+        let again = arena.from_node(&parse("B* B+ I# I$ I%")).unwrap();
+        assert_eq!(arena.len(), 5);
+        assert!(again.index() <= dropped.index());
+        assert_eq!(arena.from_node(&parse("B+ I# I$")).unwrap(), kept);
+    }
+}
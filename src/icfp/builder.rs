@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use super::error::EvalError;
+use super::parser::Node;
+
+// the identity of a variable made by the builder: every lambda gets its own, so terms built
+// apart can be nested in each other without capturing variables; `build` numbers them
+#[derive(Debug, Clone)]
+pub struct Binder(Rc<()>);
+
+impl Binder {
+    fn new() -> Binder {
+        Binder(Rc::new(()))
+    }
+}
+
+impl PartialEq for Binder {
+    fn eq(&self, other: &Binder) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Binder {}
+
+impl Hash for Binder {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Integer(BigInt),
+    String(String),
+    Boolean(bool),
+    Variable(Binder),
+    Unary(String, Box<Term>),
+    Binary(String, Box<Term>, Box<Term>),
+    If(Box<Term>, Box<Term>, Box<Term>),
+    Lambda(Binder, Box<Term>),
+}
+
+// drop children with a work stack, the derived drop recurses once per nesting level
+impl Drop for Term {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        take_children(self, &mut stack);
+        while let Some(mut term) = stack.pop() {
+            take_children(&mut term, &mut stack);
+        }
+    }
+}
+
+fn take_children(term: &mut Term, stack: &mut Vec<Term>) {
+    let mut take = |child: &mut Box<Term>| {
+        stack.push(std::mem::replace(child.as_mut(), Term::Boolean(false)));
+    };
+    match term {
+        Term::Unary(_, operand) | Term::Lambda(_, operand) => take(operand),
+        Term::Binary(_, left, right) => {
+            take(left);
+            take(right);
+        }
+        Term::If(condition, then_branch, else_branch) => {
+            take(condition);
+            take(then_branch);
+            take(else_branch);
+        }
+        _ => {}
+    }
+}
+
+impl Term {
+    pub fn apply(self, argument: Term) -> Term {
+        app(self, argument)
+    }
+
+    fn children(&self) -> Vec<&Term> {
+        match self {
+            Term::Unary(_, operand) | Term::Lambda(_, operand) => vec![operand],
+            Term::Binary(_, left, right) => vec![left, right],
+            Term::If(condition, then_branch, else_branch) => {
+                vec![condition, then_branch, else_branch]
+            }
+            _ => vec![],
+        }
+    }
+
+    // number each binder after the count of lambdas around it, so a binder never shadows a
+    // variable used under it, then give the 94 one-character ids to the levels whose
+    // variables are used most; panics on a variable used outside of its lambda
+    pub fn build(&self) -> Node {
+        let mut node = self.build_levels();
+        let mut uses: Vec<usize> = vec![];
+        for_each_variable(&mut node, |variable, used| {
+            let level: usize = (&*variable).try_into().unwrap();
+            if uses.len() <= level {
+                uses.resize(level + 1, 0);
+            }
+            uses[level] += used as usize;
+        });
+        if uses.len() > 94 {
+            let mut levels: Vec<usize> = (0..uses.len()).collect();
+            levels.sort_by_key(|&level| std::cmp::Reverse(uses[level]));
+            // the short levels keep their order among themselves, and so do the long ones
+            let (short, long) = levels.split_at_mut(94);
+            short.sort();
+            long.sort();
+            let mut ids = vec![0; uses.len()];
+            for (id, level) in levels.into_iter().enumerate() {
+                ids[level] = id;
+            }
+            for_each_variable(&mut node, |variable, _| {
+                let level: usize = (&*variable).try_into().unwrap();
+                *variable = BigInt::from(ids[level]);
+            });
+        }
+        node
+    }
+
+    // the node with every binder numbered by its nesting level, walking with a work stack
+    fn build_levels(&self) -> Node {
+        // a term is visited a second time once its children are built
+        let mut stack = vec![(self, false)];
+        let mut levels: HashMap<&Binder, usize> = HashMap::new();
+        let mut nodes: Vec<Node> = vec![];
+        while let Some((term, visited)) = stack.pop() {
+            if !visited {
+                if let Term::Lambda(binder, _) = term {
+                    levels.insert(binder, levels.len());
+                }
+                stack.push((term, true));
+                stack.extend(
+                    term.children()
+                        .into_iter()
+                        .rev()
+                        .map(|child| (child, false)),
+                );
+                continue;
+            }
+            let mut pop = || Box::new(nodes.pop().unwrap());
+            let node = match term {
+                Term::Integer(value) => Node::Integer(value.clone()),
+                Term::String(value) => Node::String(value.clone()),
+                Term::Boolean(value) => Node::Boolean(*value),
+                Term::Variable(binder) => match levels.get(binder) {
+                    Some(level) => Node::Variable(BigInt::from(*level)),
+                    None => panic!("variable used outside of its lambda"),
+                },
+                Term::Unary(operator, _) => Node::UnaryOperator(operator.clone(), pop()),
+                Term::Binary(operator, _, _) => {
+                    let right = pop();
+                    Node::BinaryOperator(operator.clone(), pop(), right)
+                }
+                Term::If(_, _, _) => {
+                    let else_branch = pop();
+                    let then_branch = pop();
+                    Node::If(pop(), then_branch, else_branch)
+                }
+                Term::Lambda(binder, _) => {
+                    let level = levels.remove(binder).unwrap();
+                    Node::Lambda(BigInt::from(level), pop())
+                }
+            };
+            nodes.push(node);
+        }
+        nodes.pop().unwrap()
+    }
+}
+
+// call `f` with the id of every binder and variable, and whether it is a use
+fn for_each_variable(node: &mut Node, mut f: impl FnMut(&mut BigInt, bool)) {
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Variable(variable) => f(variable, true),
+            Node::Lambda(variable, body) => {
+                f(variable, false);
+                stack.push(body);
+            }
+            Node::UnaryOperator(_, operand) => stack.push(operand),
+            Node::BinaryOperator(_, left, right) => {
+                stack.push(left);
+                stack.push(right);
+            }
+            Node::If(condition, then_branch, else_branch) => {
+                stack.push(condition);
+                stack.push(then_branch);
+                stack.push(else_branch);
+            }
+            _ => {}
+        }
+    }
+}
+
+// a closed ICFP term as a piece for the builder
+impl TryFrom<&Node> for Term {
+    type Error = EvalError;
+
+    fn try_from(node: &Node) -> Result<Term, EvalError> {
+        // a node is visited a second time once its children are converted
+        let mut stack = vec![(node, false)];
+        let mut binders: HashMap<&BigInt, Vec<Binder>> = HashMap::new();
+        let mut terms: Vec<Term> = vec![];
+        while let Some((node, visited)) = stack.pop() {
+            if !visited {
+                if let Node::Lambda(variable, _) = node {
+                    binders.entry(variable).or_default().push(Binder::new());
+                }
+                stack.push((node, true));
+                stack.extend(
+                    node.children()
+                        .into_iter()
+                        .rev()
+                        .map(|child| (child, false)),
+                );
+                continue;
+            }
+            let mut pop = || Box::new(terms.pop().unwrap());
+            let term = match node {
+                Node::Integer(value) => Term::Integer(value.clone()),
+                Node::String(value) => Term::String(value.clone()),
+                Node::Boolean(value) => Term::Boolean(*value),
+                Node::Variable(variable) => {
+                    match binders.get(variable).and_then(|binders| binders.last()) {
+                        Some(binder) => Term::Variable(binder.clone()),
+                        None => {
+                            return Err(EvalError::FreeVariable {
+                                variable: variable.clone(),
+                            })
+                        }
+                    }
+                }
+                Node::UnaryOperator(operator, _) => Term::Unary(operator.clone(), pop()),
+                Node::BinaryOperator(operator, _, _) => {
+                    let right = pop();
+                    Term::Binary(operator.clone(), pop(), right)
+                }
+                Node::If(_, _, _) => {
+                    let else_branch = pop();
+                    let then_branch = pop();
+                    Term::If(pop(), then_branch, else_branch)
+                }
+                Node::Lambda(variable, _) => {
+                    let binder = binders.get_mut(variable).unwrap().pop().unwrap();
+                    Term::Lambda(binder, pop())
+                }
+            };
+            terms.push(term);
+        }
+        Ok(terms.pop().unwrap())
+    }
+}
+
+pub fn int(value: impl Into<BigInt>) -> Term {
+    Term::Integer(value.into())
+}
+
+pub fn string(value: &str) -> Term {
+    Term::String(value.to_string())
+}
+
+pub fn boolean(value: bool) -> Term {
+    Term::Boolean(value)
+}
+
+pub fn lam(body: impl FnOnce(Term) -> Term) -> Term {
+    let binder = Binder::new();
+    let variable = Term::Variable(binder.clone());
+    Term::Lambda(binder, Box::new(body(variable)))
+}
+
+pub fn y_combinator() -> Term {
+    lam(|f| {
+        app(
+            lam(|x| app(x.clone(), x)),
+            lam(|x| app(f, lam(|v| app(app(x.clone(), x), v)))),
+        )
+    })
+}
+
+// recursion through the Y combinator: `body` gets the function itself
+pub fn fix(body: impl FnOnce(Term) -> Term) -> Term {
+    app(y_combinator(), lam(body))
+}
+
+// recursion by self-application, B$ L! B$ v! v! L<self> ..., shorter than the Y combinator:
+// `body` gets the function applied to itself
+pub fn self_apply(body: impl FnOnce(Term) -> Term) -> Term {
+    app(
+        lam(|f| app(f.clone(), f)),
+        lam(|itself| body(app(itself.clone(), itself))),
+    )
+}
+
+pub fn if_(condition: Term, then_branch: Term, else_branch: Term) -> Term {
+    Term::If(
+        Box::new(condition),
+        Box::new(then_branch),
+        Box::new(else_branch),
+    )
+}
+
+fn unary(operator: &str, operand: Term) -> Term {
+    Term::Unary(operator.to_string(), Box::new(operand))
+}
+
+fn binary(operator: &str, left: Term, right: Term) -> Term {
+    Term::Binary(operator.to_string(), Box::new(left), Box::new(right))
+}
+
+pub fn neg(operand: Term) -> Term {
+    unary("-", operand)
+}
+
+pub fn not(operand: Term) -> Term {
+    unary("!", operand)
+}
+
+pub fn string_to_int(operand: Term) -> Term {
+    unary("#", operand)
+}
+
+pub fn int_to_string(operand: Term) -> Term {
+    unary("$", operand)
+}
+
+pub fn app(function: Term, argument: Term) -> Term {
+    binary("$", function, argument)
+}
+
+pub fn app_by_value(function: Term, argument: Term) -> Term {
+    binary("!", function, argument)
+}
+
+pub fn app_by_need(function: Term, argument: Term) -> Term {
+    binary("~", function, argument)
+}
+
+pub fn add(left: Term, right: Term) -> Term {
+    binary("+", left, right)
+}
+
+pub fn sub(left: Term, right: Term) -> Term {
+    binary("-", left, right)
+}
+
+pub fn mul(left: Term, right: Term) -> Term {
+    binary("*", left, right)
+}
+
+pub fn div(left: Term, right: Term) -> Term {
+    binary("/", left, right)
+}
+
+pub fn rem(left: Term, right: Term) -> Term {
+    binary("%", left, right)
+}
+
+pub fn lt(left: Term, right: Term) -> Term {
+    binary("<", left, right)
+}
+
+pub fn gt(left: Term, right: Term) -> Term {
+    binary(">", left, right)
+}
+
+pub fn eq(left: Term, right: Term) -> Term {
+    binary("=", left, right)
+}
+
+pub fn or(left: Term, right: Term) -> Term {
+    binary("|", left, right)
+}
+
+pub fn and(left: Term, right: Term) -> Term {
+    binary("&", left, right)
+}
+
+pub fn concat(left: Term, right: Term) -> Term {
+    binary(".", left, right)
+}
+
+pub fn take(count: Term, value: Term) -> Term {
+    binary("T", count, value)
+}
+
+pub fn drop(count: Term, value: Term) -> Term {
+    binary("D", count, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::evaluator::Evaluator;
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    fn evaluate(term: &Term) -> Node {
+        Evaluator::new(term.build()).evaluate().unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let double = lam(|x| add(x.clone(), x));
+        assert_eq!(double.build().to_string(), "L! B+ v! v!");
+        let term = app(lam(|f| app(f.clone(), app(f, int(3)))), double);
+        assert_eq!(term.build().to_string(), "B$ L! B$ v! B$ v! I$ L! B+ v! v!");
+        assert_eq!(evaluate(&term), Node::Integer(BigInt::from(12)));
+        assert_eq!(
+            if_(not(boolean(false)), string("hello"), int(0))
+                .build()
+                .to_string(),
+            "? U! F S(%,,/ I!"
+        );
+    }
+
+    #[test]
+    fn test_compose() {
+        // a piece used under another lambda must still see its own variable
+        let constant = |value: Term| lam(move |_| value);
+        let term = lam(|x| lam(|y| app(constant(x), y)));
+        assert_eq!(term.build().to_string(), "L! L\" B$ L# v! v\"");
+        let term = app(app(term, string("a")), string("b"));
+        assert_eq!(evaluate(&term), Node::String("a".to_string()));
+
+        let repeat = fix(|repeat| {
+            lam(|s| {
+                lam(|n| {
+                    if_(
+                        eq(n.clone(), int(0)),
+                        string(""),
+                        concat(s.clone(), app(app(repeat, s), sub(n, int(1)))),
+                    )
+                })
+            })
+        });
+        let term = app(app(repeat, string("ab")), int(3));
+        assert_eq!(evaluate(&term), Node::String("ababab".to_string()));
+
+        let countdown = self_apply(|countdown| {
+            lam(|n| {
+                if_(
+                    eq(n.clone(), int(0)),
+                    string("!"),
+                    concat(int_to_string(n.clone()), app(countdown, sub(n, int(1)))),
+                )
+            })
+        });
+        assert!(countdown
+            .build()
+            .to_string()
+            .starts_with("B$ L! B$ v! v! L"));
+        let term = app(countdown, int(3));
+        assert_eq!(evaluate(&term), Node::String("dcb!".to_string()));
+    }
+
+    #[test]
+    fn test_from_node() {
+        let node = parse("L# L# B* v# I#");
+        let piece = Term::try_from(&node).unwrap();
+        assert_eq!(piece.build().to_string(), "L! L\" B* v\" I#");
+        let term = lam(|x| add(x.clone(), app(app(piece, x.clone()), x)));
+        assert_eq!(
+            evaluate(&app(term, int(5))),
+            Node::Integer(BigInt::from(15))
+        );
+        assert_eq!(
+            Term::try_from(&parse("L# B+ v# v$")),
+            Err(EvalError::FreeVariable {
+                variable: BigInt::from(3)
+            })
+        );
+    }
+
+    // lambdas binding 0, 1, ... around `body`, from the outside in
+    fn nested(depth: usize, body: Node) -> Node {
+        (0..depth).rev().fold(body, |body, level| {
+            Node::Lambda(BigInt::from(level), Box::new(body))
+        })
+    }
+
+    #[test]
+    fn test_build_deep() {
+        let node = nested(100_000, Node::Variable(BigInt::from(0)));
+        let term = Term::try_from(&node).unwrap();
+        assert_eq!(term.build().to_string(), node.to_string());
+    }
+
+    #[test]
+    fn test_build_shortest_names() {
+        // only the innermost of 100 nested variables is used, so it gets a one-character id
+        let used = BigInt::from(99);
+        let body = Node::BinaryOperator(
+            "+".to_string(),
+            Box::new(Node::Variable(used.clone())),
+            Box::new(Node::Variable(used)),
+        );
+        let term = Term::try_from(&nested(100, body)).unwrap();
+        assert!(term.build().to_string().ends_with(" L~ B+ v~ v~"));
+    }
+}
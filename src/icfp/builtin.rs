@@ -1,8 +1,9 @@
 use num_bigint::BigInt;
 
-use crate::icfp::util::STRING_ASCII;
+use crate::icfp::util::{BASE94, STRING_ASCII};
 
 use super::{
+    builder::{self, app, concat, gt, if_, int, int_to_string, lam, rem, string, sub},
     parser::Node,
     util::{convert_string, INTEGER_ASCII},
 };
@@ -15,84 +16,30 @@ macro_rules! node {
 }
 
 pub fn y_combinator() -> Node {
-    Node::Lambda(
-        0,
-        node!(Node::BinaryOperator(
-            "$".to_string(),
-            node!(Node::Lambda(
-                1,
-                node!(Node::BinaryOperator(
-                    "$".to_string(),
-                    node!(Node::Variable(1)),
-                    node!(Node::Variable(1))
-                ))
-            )),
-            node!(Node::Lambda(
-                1,
-                node!(Node::BinaryOperator(
-                    "$".to_string(),
-                    node!(Node::Variable(0)),
-                    node!(Node::Lambda(
-                        2,
-                        node!(Node::BinaryOperator(
-                            "$".to_string(),
-                            node!(Node::BinaryOperator(
-                                "$".to_string(),
-                                node!(Node::Variable(1)),
-                                node!(Node::Variable(1))
-                            )),
-                            node!(Node::Variable(2))
-                        ))
-                    ))
-                ))
-            ))
-        )),
-    )
+    builder::y_combinator().build()
 }
 
-const BASE94: isize = 94;
-
 pub fn repeat_char() -> Node {
-    Node::Lambda(
-        0,
-        node!(Node::Lambda(
-            1,
-            node!(Node::If(
-                node!(Node::BinaryOperator(
-                    ">".to_string(),
-                    node!(Node::Variable(1)),
-                    node!(Node::Integer(BigInt::from(BASE94))) // 1文字以上からじゃないと出力できないようにする（ICFPに空文字列が存在しないため）
-                )),
-                node!(Node::BinaryOperator(
-                    ".".to_string(),
-                    node!(Node::UnaryOperator(
-                        "$".to_string(),
-                        node!(Node::BinaryOperator(
-                            "%".to_string(),
-                            node!(Node::Variable(1)),
-                            node!(Node::Integer(BigInt::from(BASE94)))
-                        ))
-                    )),
-                    node!(Node::BinaryOperator(
-                        "$".to_string(),
-                        node!(Node::Variable(0)),
-                        node!(Node::BinaryOperator(
-                            "-".to_string(),
-                            node!(Node::Variable(1)),
-                            node!(Node::Integer(BigInt::from(BASE94)))
-                        ))
-                    ))
-                )),
-                node!(Node::String("".to_string()))
-            ))
-        )),
-    )
+    lam(|repeat| {
+        lam(|n| {
+            if_(
+                // 1文字以上からじゃないと出力できないようにする（ICFPに空文字列が存在しないため）
+                gt(n.clone(), int(BASE94)),
+                concat(
+                    int_to_string(rem(n.clone(), int(BASE94))),
+                    app(repeat, sub(n, int(BASE94))),
+                ),
+                string(""),
+            )
+        })
+    })
+    .build()
 }
 
 pub fn repeat_char_operator(value: char, times: usize) -> Node {
     assert!(times >= 1);
     let value_id = STRING_ASCII.find(value).unwrap();
-    Node::Integer(BigInt::from(BASE94 * times as isize + value_id as isize))
+    Node::Integer(BigInt::from(BASE94 * times + value_id))
 }
 
 #[cfg(test)]
@@ -114,6 +61,10 @@ mod tests {
         ));
         node.dump_tree(0);
         eprintln!("{}", node.to_string());
+        assert_eq!(
+            node.to_string(),
+            "B$ B$ L! B$ L\" B$ v\" v\" L\" B$ v! L# B$ B$ v\" v\" v# L! L\" ? B> v\" I\"! B. U$ B% v\" I\"! B$ v! B- v\" I\"! S I5&"
+        );
         let transpiler = Transpiler::new(node.as_ref().clone());
         let result = transpiler.transpile();
         eprintln!("{}", result);
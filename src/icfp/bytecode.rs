@@ -0,0 +1,393 @@
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use super::arena::{BinaryOp, UnaryOp};
+use super::error::EvalError;
+use super::parser::Node;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Constant {
+    Integer(BigInt),
+    String(String),
+    Boolean(bool),
+    // a variable that is not bound anywhere in the program
+    Variable(BigInt),
+}
+
+// how the argument of an application is passed
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Argument {
+    // the thunk in this slot is passed on, so that its value is shared
+    Shared(usize),
+    // a new thunk that runs this function when it is first needed
+    Delayed(usize),
+    // the value on top of the stack, for ! and for arguments that are already values
+    Value,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Instruction {
+    Constant(usize),
+    // push the value of the thunk in a slot of the current environment, forcing it
+    Load(usize),
+    // push a closure of a function over the slots listed in its captures
+    Closure(usize),
+    Unary(UnaryOp),
+    Binary(BinaryOp),
+    // pop the function, and the argument when it is a Value, then call it
+    Apply(BinaryOp, Argument),
+    JumpIfFalse(usize),
+    Jump(usize),
+    Return,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Capture {
+    pub variable: BigInt,
+    // slot of the variable in the environment where the closure or thunk is created
+    pub slot: usize,
+}
+
+// a lambda body or a delayed argument; its environment is the parameter, if any, in slot 0
+// followed by the captured slots
+#[derive(Debug, Clone, Default)]
+pub struct Function {
+    pub parameter: Option<BigInt>,
+    pub captures: Vec<Capture>,
+    pub code: Vec<Instruction>,
+    // prefix order index of the lambda or the delayed expression in the source program
+    pub source: usize,
+    parent: Option<usize>,
+}
+
+impl Function {
+    pub fn capture_slot(&self, index: usize) -> usize {
+        index + self.parameter.is_some() as usize
+    }
+}
+
+// function 0 is the whole program
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub constants: Vec<Constant>,
+    // kept to read closures back into terms, shared with the caller instead of copied
+    pub source: Rc<Node>,
+}
+
+impl Program {
+    // every node of the source in prefix order, so a function source indexes into it
+    pub fn source_nodes(&self) -> Vec<&Node> {
+        let mut stack = vec![self.source.as_ref()];
+        let mut nodes = vec![];
+        while let Some(node) = stack.pop() {
+            nodes.push(node);
+            match node {
+                Node::UnaryOperator(_, operand) | Node::Lambda(_, operand) => stack.push(operand),
+                Node::BinaryOperator(_, left, right) => {
+                    stack.push(right);
+                    stack.push(left);
+                }
+                Node::If(condition, then_branch, else_branch) => {
+                    stack.push(else_branch);
+                    stack.push(then_branch);
+                    stack.push(condition);
+                }
+                _ => {}
+            }
+        }
+        nodes
+    }
+}
+
+enum Task<'a> {
+    Compile(&'a Node, usize),
+    // the argument of $ or ~, after the function has been compiled
+    Argument(&'a Node, usize, BinaryOp),
+    Emit(usize, Instruction),
+    // jumps of an if expression, patched once the branches are compiled
+    Branch(usize),
+    Else(usize),
+    EndIf(usize),
+}
+
+pub fn compile(source: impl Into<Rc<Node>>) -> Result<Program, EvalError> {
+    let source = source.into();
+    let node = source.as_ref();
+    let mut compiler = Compiler {
+        functions: vec![Function::default()],
+        constants: vec![],
+    };
+    let mut tasks = vec![Task::Emit(0, Instruction::Return), Task::Compile(node, 0)];
+    let mut labels = vec![];
+    // prefix order index of the next node, the same as its index in the token stream
+    let mut position = 0;
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Compile(node, function) => {
+                let instruction = match node {
+                    Node::Integer(value) => compiler.constant(Constant::Integer(value.clone())),
+                    Node::String(value) => compiler.constant(Constant::String(value.clone())),
+                    Node::Boolean(value) => compiler.constant(Constant::Boolean(*value)),
+                    Node::Variable(index) => match compiler.resolve(function, index) {
+                        Some(slot) => Instruction::Load(slot),
+                        None => compiler.constant(Constant::Variable(index.clone())),
+                    },
+                    Node::Lambda(arity, body) => {
+                        let lambda = compiler.function(Some(arity.clone()), function, position);
+                        tasks.push(Task::Emit(lambda, Instruction::Return));
+                        tasks.push(Task::Compile(body, lambda));
+                        Instruction::Closure(lambda)
+                    }
+                    Node::UnaryOperator(operator, operand) => {
+                        let operator = UnaryOp::parse(operator).ok_or_else(|| {
+                            EvalError::UnknownUnaryOperator {
+                                operator: operator.clone(),
+                            }
+                        })?;
+                        tasks.push(Task::Emit(function, Instruction::Unary(operator)));
+                        tasks.push(Task::Compile(operand, function));
+                        position += 1;
+                        continue;
+                    }
+                    Node::BinaryOperator(operator, left, right) => {
+                        let operator = BinaryOp::parse(operator).ok_or_else(|| {
+                            EvalError::UnknownBinaryOperator {
+                                operator: operator.clone(),
+                            }
+                        })?;
+                        match operator {
+                            BinaryOp::Apply | BinaryOp::ApplyLazy => {
+                                tasks.push(Task::Argument(right, function, operator));
+                            }
+                            BinaryOp::ApplyStrict => {
+                                let instruction = Instruction::Apply(operator, Argument::Value);
+                                tasks.push(Task::Emit(function, instruction));
+                                tasks.push(Task::Compile(right, function));
+                            }
+                            _ => {
+                                tasks.push(Task::Emit(function, Instruction::Binary(operator)));
+                                tasks.push(Task::Compile(right, function));
+                            }
+                        }
+                        tasks.push(Task::Compile(left, function));
+                        position += 1;
+                        continue;
+                    }
+                    Node::If(condition, then_branch, else_branch) => {
+                        tasks.push(Task::EndIf(function));
+                        tasks.push(Task::Compile(else_branch, function));
+                        tasks.push(Task::Else(function));
+                        tasks.push(Task::Compile(then_branch, function));
+                        tasks.push(Task::Branch(function));
+                        tasks.push(Task::Compile(condition, function));
+                        position += 1;
+                        continue;
+                    }
+                };
+                compiler.functions[function].code.push(instruction);
+                position += 1;
+            }
+            Task::Argument(node, function, operator) => {
+                let argument = match node {
+                    Node::Variable(index) => match compiler.resolve(function, index) {
+                        Some(slot) => {
+                            position += 1;
+                            Argument::Shared(slot)
+                        }
+                        None => {
+                            tasks.push(Task::Compile(node, function));
+                            Argument::Value
+                        }
+                    },
+                    // values cost nothing to evaluate, so they are passed directly
+                    Node::Integer(_) | Node::String(_) | Node::Boolean(_) | Node::Lambda(_, _) => {
+                        tasks.push(Task::Compile(node, function));
+                        Argument::Value
+                    }
+                    _ => {
+                        let delayed = compiler.function(None, function, position);
+                        tasks.push(Task::Emit(delayed, Instruction::Return));
+                        tasks.push(Task::Compile(node, delayed));
+                        Argument::Delayed(delayed)
+                    }
+                };
+                let instruction = Instruction::Apply(operator, argument);
+                if argument == Argument::Value {
+                    // the argument is compiled first, the application comes after it
+                    let compile = tasks.pop().unwrap();
+                    tasks.push(Task::Emit(function, instruction));
+                    tasks.push(compile);
+                } else {
+                    compiler.functions[function].code.push(instruction);
+                }
+            }
+            Task::Emit(function, instruction) => {
+                compiler.functions[function].code.push(instruction)
+            }
+            Task::Branch(function) => {
+                let code = &mut compiler.functions[function].code;
+                labels.push(code.len());
+                code.push(Instruction::JumpIfFalse(0));
+            }
+            Task::Else(function) => {
+                let code = &mut compiler.functions[function].code;
+                let branch = labels.pop().unwrap();
+                labels.push(code.len());
+                code.push(Instruction::Jump(0));
+                code[branch] = Instruction::JumpIfFalse(code.len());
+            }
+            Task::EndIf(function) => {
+                let code = &mut compiler.functions[function].code;
+                let jump = labels.pop().unwrap();
+                code[jump] = Instruction::Jump(code.len());
+            }
+        }
+    }
+    Ok(Program {
+        functions: compiler.functions,
+        constants: compiler.constants,
+        source: source.clone(),
+    })
+}
+
+struct Compiler {
+    functions: Vec<Function>,
+    constants: Vec<Constant>,
+}
+
+impl Compiler {
+    fn constant(&mut self, constant: Constant) -> Instruction {
+        self.constants.push(constant);
+        Instruction::Constant(self.constants.len() - 1)
+    }
+
+    fn function(&mut self, parameter: Option<BigInt>, parent: usize, source: usize) -> usize {
+        self.functions.push(Function {
+            parameter,
+            captures: vec![],
+            code: vec![],
+            source,
+            parent: Some(parent),
+        });
+        self.functions.len() - 1
+    }
+
+    // the slot of a variable in a function, capturing it through every enclosing function
+    // up to its binder; None for variables that are not bound anywhere
+    fn resolve(&mut self, function: usize, variable: &BigInt) -> Option<usize> {
+        let mut chain = vec![];
+        let mut current = function;
+        let mut slot = loop {
+            let candidate = &self.functions[current];
+            if candidate.parameter.as_ref() == Some(variable) {
+                break 0;
+            }
+            if let Some(index) = candidate
+                .captures
+                .iter()
+                .position(|capture| capture.variable == *variable)
+            {
+                break candidate.capture_slot(index);
+            }
+            chain.push(current);
+            current = candidate.parent?;
+        };
+        for function in chain.into_iter().rev() {
+            let function = &mut self.functions[function];
+            function.captures.push(Capture {
+                variable: variable.clone(),
+                slot,
+            });
+            slot = function.capture_slot(function.captures.len() - 1);
+        }
+        Some(slot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_compile_operators() {
+        let program = compile(parse("? B> I# I$ U- I# I$")).unwrap();
+        assert_eq!(program.functions.len(), 1);
+        assert_eq!(
+            program.functions[0].code,
+            vec![
+                Instruction::Constant(0),
+                Instruction::Constant(1),
+                Instruction::Binary(BinaryOp::GreaterThan),
+                Instruction::JumpIfFalse(7),
+                Instruction::Constant(2),
+                Instruction::Unary(UnaryOp::Negate),
+                Instruction::Jump(8),
+                Instruction::Constant(3),
+                Instruction::Return,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_lambdas() {
+        // λ1. λ2. v1 + v3, applied to a delayed argument
+        let program = compile(parse("B$ L\" L# B+ v\" v$ B* I# I#")).unwrap();
+        assert_eq!(program.functions.len(), 4);
+        assert_eq!(
+            program.functions[0].code,
+            vec![
+                Instruction::Closure(1),
+                Instruction::Apply(BinaryOp::Apply, Argument::Delayed(3)),
+                Instruction::Return,
+            ]
+        );
+        assert_eq!(
+            program.functions[1].code,
+            vec![Instruction::Closure(2), Instruction::Return]
+        );
+        // the inner lambda captures v1 from the outer one, v3 is free
+        assert_eq!(
+            program.functions[2].captures,
+            vec![Capture {
+                variable: BigInt::from(1),
+                slot: 0
+            }]
+        );
+        assert_eq!(
+            program.functions[2].code,
+            vec![
+                Instruction::Load(1),
+                Instruction::Constant(0),
+                Instruction::Binary(BinaryOp::Add),
+                Instruction::Return,
+            ]
+        );
+        assert_eq!(program.constants[0], Constant::Variable(BigInt::from(3)));
+        assert_eq!(
+            program.source_nodes()[program.functions[2].source],
+            &parse("L# B+ v\" v$")
+        );
+        assert_eq!(
+            program.source_nodes()[program.functions[3].source],
+            &parse("B* I# I#")
+        );
+    }
+
+    #[test]
+    fn test_compile_unknown_operator() {
+        assert_eq!(
+            compile(parse("B@ I# I$")).err(),
+            Some(EvalError::UnknownBinaryOperator {
+                operator: "@".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::{HashMap, VecDeque};
+
+use num_bigint::BigInt;
+
+use super::builder::{
+    app, app_by_value, concat, div, drop, eq, fix, if_, int, int_to_string, lam, lt, rem, string,
+    sub, take, Term,
+};
+use super::error::EvalError;
+use super::evaluator::{Evaluator, Strategy, BETA_REDUCTION_LIMIT};
+use super::optimizer::{size, Optimizer};
+use super::parser::Node;
+use super::util::{BASE94, STRING_ASCII};
+
+// the longest substring considered for a dictionary entry
+const MAX_ENTRY: usize = 24;
+const MAX_ENTRIES: usize = 8;
+// a literal prefix is only split off at the spaces within this many characters
+const MAX_PREFIX: usize = 64;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Encoding {
+    // the text as one string literal, which is also the fallback for the whole text
+    Literal,
+    // runs of a character expanded by a recursive lambda, short runs stay literal
+    RunLength,
+    // a text over a small alphabet as the base-k digits of one integer
+    Packed,
+    // repeated substrings bound once by lambdas and concatenated
+    Dictionary,
+}
+
+pub const ALL_ENCODINGS: [Encoding; 4] = [
+    Encoding::Literal,
+    Encoding::RunLength,
+    Encoding::Packed,
+    Encoding::Dictionary,
+];
+
+// builds a program for every encoding, with and without a literal prefix such as
+// "solve lambdaman4 ", and returns the shortest one that evaluates back to the text
+pub struct Compressor {
+    encodings: Vec<Encoding>,
+}
+
+impl Default for Compressor {
+    fn default() -> Self {
+        Compressor::new()
+    }
+}
+
+impl Compressor {
+    pub fn new() -> Compressor {
+        Compressor {
+            encodings: ALL_ENCODINGS.to_vec(),
+        }
+    }
+
+    pub fn with_encodings(mut self, encodings: &[Encoding]) -> Compressor {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    pub fn compress(&self, text: &str) -> Result<Node, EvalError> {
+        if let Some((position, character)) = text
+            .char_indices()
+            .find(|&(_, character)| !STRING_ASCII.contains(character))
+        {
+            return Err(EvalError::InvalidCharacter {
+                character,
+                position,
+            });
+        }
+        // the text may start with words worth keeping literal
+        let splits = text
+            .char_indices()
+            .filter(|&(position, c)| c == ' ' && position < MAX_PREFIX)
+            .map(|(position, _)| position + 1);
+        let mut candidates = vec![];
+        for split in [0].into_iter().chain(splits) {
+            let (prefix, rest) = text.split_at(split);
+            if rest.is_empty() {
+                continue;
+            }
+            for encoding in &self.encodings {
+                for term in encode(*encoding, rest) {
+                    let term = match prefix {
+                        "" => term,
+                        prefix => concat(string(prefix), term),
+                    };
+                    let candidate = term.build();
+                    candidates.push((size(&candidate), candidate));
+                }
+            }
+        }
+        let literal = Node::String(text.to_string());
+        let literal_size = size(&literal);
+        candidates.sort_by_key(|&(length, _)| length);
+        // only the shortest candidate is verified, unless it fails
+        let best = candidates
+            .into_iter()
+            .take_while(|&(length, _)| length < literal_size)
+            .map(|(_, candidate)| candidate)
+            .find(|candidate| verify(candidate, text))
+            .unwrap_or(literal);
+        // the encodings are built without looking at each other, the optimizer folds
+        // what they leave behind, such as constants and repeated closed terms, and
+        // renumbers the binders
+        Optimizer::new().optimize(&best)
+    }
+}
+
+// the official evaluator is call-by-name with a limit on beta reductions
+pub fn verify(node: &Node, text: &str) -> bool {
+    let mut evaluator = Evaluator::with_strategy(node.clone(), Strategy::CallByName)
+        .with_beta_limit(BETA_REDUCTION_LIMIT);
+    match evaluator.evaluate() {
+        Ok(Node::String(ref result)) => result == text,
+        _ => false,
+    }
+}
+
+fn encode(encoding: Encoding, text: &str) -> Vec<Term> {
+    match encoding {
+        Encoding::Literal => vec![string(text)],
+        Encoding::RunLength => run_length(text),
+        Encoding::Packed => packed(text).into_iter().collect(),
+        Encoding::Dictionary => dictionary(text),
+    }
+}
+
+fn index(character: char) -> usize {
+    STRING_ASCII.find(character).unwrap()
+}
+
+// consecutive equal characters with their counts
+pub fn runs(text: &str) -> VecDeque<(char, usize)> {
+    let mut runs: VecDeque<(char, usize)> = VecDeque::new();
+    for c in text.chars() {
+        match runs.back_mut() {
+            Some((last, count)) if *last == c => *count += 1,
+            _ => runs.push_back((c, 1)),
+        }
+    }
+    runs
+}
+
+fn concat_all(mut pieces: Vec<Term>) -> Option<Term> {
+    let mut result = pieces.pop()?;
+    while let Some(piece) = pieces.pop() {
+        result = concat(piece, result);
+    }
+    Some(result)
+}
+
+// one program per threshold: runs at least that long become calls of a lambda taking
+// 94 * count + character, the others are merged into literals
+fn run_length(text: &str) -> Vec<Term> {
+    let runs = runs(text);
+    let longest = runs.iter().map(|&(_, count)| count).max().unwrap_or(0);
+    (2..=longest.min(16))
+        .map(|threshold| {
+            let body = |repeat: Term| {
+                let mut pieces = vec![];
+                let mut literal = String::new();
+                for &(c, count) in &runs {
+                    if count < threshold {
+                        literal.extend(std::iter::repeat_n(c, count));
+                        continue;
+                    }
+                    if !literal.is_empty() {
+                        pieces.push(string(&literal));
+                        literal.clear();
+                    }
+                    pieces.push(app(repeat.clone(), int(BASE94 * count + index(c))));
+                }
+                if !literal.is_empty() {
+                    pieces.push(string(&literal));
+                }
+                concat_all(pieces).unwrap()
+            };
+            let repeat = fix(|repeat| {
+                lam(|n| {
+                    if_(
+                        lt(n.clone(), int(BASE94)),
+                        string(""),
+                        concat(
+                            int_to_string(rem(n.clone(), int(BASE94))),
+                            app_by_value(repeat, sub(n, int(BASE94))),
+                        ),
+                    )
+                })
+            });
+            app(lam(body), repeat)
+        })
+        .collect()
+}
+
+// the characters are the digits of an integer in base k, least significant first, under a
+// leading 1 that keeps the length; a recursive lambda looks each digit up in the alphabet
+fn packed(text: &str) -> Option<Term> {
+    let mut alphabet: Vec<char> = text.chars().collect();
+    alphabet.sort_by_key(|&c| index(c));
+    alphabet.dedup();
+    let base = alphabet.len();
+    if !(2..BASE94 / 2).contains(&base) {
+        return None;
+    }
+    let mut value = BigInt::from(1);
+    for c in text.chars().rev() {
+        value = value * base + alphabet.iter().position(|&a| a == c).unwrap();
+    }
+    let alphabet: String = alphabet.into_iter().collect();
+    let decode = fix(|decode| {
+        lam(|n| {
+            if_(
+                eq(n.clone(), int(1)),
+                string(""),
+                concat(
+                    take(int(1), drop(rem(n.clone(), int(base)), string(&alphabet))),
+                    app_by_value(decode, div(n, int(base))),
+                ),
+            )
+        })
+    });
+    Some(app(decode, int(value)))
+}
+
+enum Segment {
+    Text(String),
+    Entry(usize),
+}
+
+// greedily bind the substring with the best estimated saving, one program per number of
+// entries, since the estimate is only a guide
+fn dictionary(text: &str) -> Vec<Term> {
+    // characters added by each reference and by each entry, roughly
+    const REFERENCE: usize = 9;
+    const ENTRY: usize = 8;
+    let mut segments = vec![Segment::Text(text.to_string())];
+    let mut entries: Vec<String> = vec![];
+    let mut programs = vec![];
+    while entries.len() < MAX_ENTRIES {
+        let mut counts: HashMap<&str, (usize, usize)> = HashMap::new();
+        for segment in &segments {
+            let Segment::Text(text) = segment else {
+                continue;
+            };
+            for length in 2..=MAX_ENTRY.min(text.len()) {
+                for start in 0..=text.len() - length {
+                    // non-overlapping occurrences, counted left to right
+                    let (count, end) = counts.entry(&text[start..start + length]).or_default();
+                    if start >= *end {
+                        *count += 1;
+                        *end = start + length;
+                    }
+                }
+            }
+            // occurrences in different segments never overlap
+            for (_, end) in counts.values_mut() {
+                *end = 0;
+            }
+        }
+        let best = counts
+            .into_iter()
+            .map(|(entry, (count, _))| {
+                let saved = count * entry.len();
+                let added = count * REFERENCE + entry.len() + ENTRY;
+                (saved as isize - added as isize, entry)
+            })
+            .filter(|&(saving, _)| saving > 0)
+            .max();
+        let Some((_, entry)) = best else {
+            break;
+        };
+        let entry = entry.to_string();
+        segments = segments
+            .into_iter()
+            .flat_map(|segment| match segment {
+                Segment::Text(text) => split(&text, &entry, entries.len()),
+                segment => vec![segment],
+            })
+            .collect();
+        entries.push(entry);
+        programs.push(bind(&entries, &segments, &mut vec![]));
+    }
+    programs
+}
+
+fn split(text: &str, entry: &str, id: usize) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut rest = text;
+    while let Some(position) = rest.find(entry) {
+        if position > 0 {
+            segments.push(Segment::Text(rest[..position].to_string()));
+        }
+        segments.push(Segment::Entry(id));
+        rest = &rest[position + entry.len()..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest.to_string()));
+    }
+    segments
+}
+
+// B$ L<entry 0> B$ L<entry 1> ... <concatenation> S<entry 1> S<entry 0>
+fn bind(entries: &[String], segments: &[Segment], variables: &mut Vec<Term>) -> Term {
+    if variables.len() == entries.len() {
+        let pieces = segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => string(text),
+                Segment::Entry(id) => variables[*id].clone(),
+            })
+            .collect();
+        return concat_all(pieces).unwrap();
+    }
+    let entry = string(&entries[variables.len()]);
+    let body = lam(|variable| {
+        variables.push(variable);
+        let body = bind(entries, segments, variables);
+        variables.pop();
+        body
+    });
+    app(body, entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(node: &Node) -> Node {
+        Evaluator::with_strategy(node.clone(), Strategy::CallByName)
+            .evaluate()
+            .unwrap()
+    }
+
+    fn random_path(length: usize) -> String {
+        let mut state: u64 = 1;
+        (0..length)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                ['U', 'D', 'L', 'R'][(state >> 62) as usize]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_runs() {
+        let text = "aaabbbccc";
+        assert_eq!(runs(text), vec![('a', 3), ('b', 3), ('c', 3)]);
+    }
+
+    #[test]
+    fn test_encodings() {
+        let path = random_path(200);
+        let texts = [
+            format!("solve lambdaman4 {}", path),
+            format!("{}{}{}", "a".repeat(300), "L".repeat(200), "b".repeat(150)),
+            format!("{}hello world!", "hello world, ".repeat(10)),
+        ];
+        for text in &texts {
+            for encoding in ALL_ENCODINGS {
+                for term in encode(encoding, text) {
+                    let node = term.build();
+                    assert_eq!(
+                        evaluate(&node),
+                        Node::String(text.clone()),
+                        "{:?}",
+                        encoding
+                    );
+                }
+            }
+        }
+        assert!(!dictionary(&texts[2]).is_empty());
+        assert!(packed(&texts[2]).is_some());
+        assert!(packed(&"ab".repeat(47)).is_some());
+    }
+
+    #[test]
+    fn test_compress() {
+        let path = random_path(1000);
+        let texts = [
+            "solve lambdaman1 LLLDURRRUDRRURR".to_string(),
+            format!("solve lambdaman4 {}", path),
+            format!("solve lambdaman9 {}", "RRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRRR".repeat(3)),
+            "abc ".repeat(40),
+        ];
+        for text in &texts {
+            let literal = Node::String(text.clone());
+            let node = Compressor::new().compress(text).unwrap();
+            assert!(size(&node) <= size(&literal));
+            assert_eq!(evaluate(&node), literal);
+            // the result is already optimized
+            assert_eq!(Optimizer::new().optimize(&node), Ok(node));
+            for encoding in [Encoding::RunLength, Encoding::Packed, Encoding::Dictionary] {
+                let node = Compressor::new()
+                    .with_encodings(&[encoding])
+                    .compress(text)
+                    .unwrap();
+                assert_eq!(evaluate(&node), literal);
+            }
+        }
+        let path = Compressor::new().compress(&texts[1]).unwrap();
+        // two bits per move, 1000 moves fit in 306 base-94 digits next to the decoder
+        assert!(size(&path) < 450, "{}", path.to_string());
+        assert!(Compressor::new().compress("tab\tstop").is_err());
+    }
+}
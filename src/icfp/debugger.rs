@@ -0,0 +1,448 @@
+use std::collections::VecDeque;
+
+use num_bigint::BigInt;
+
+use super::error::EvalError;
+use super::evaluator::{is_application, reduce_primitive, substitute};
+use super::parser::Node;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Breakpoint {
+    // stop before reducing a unary or binary operator, `?` matches if expressions
+    Operator(String),
+    // stop before applying the lambda that binds this variable
+    Lambda(BigInt),
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    // child indices from the root to the redex
+    pub path: Vec<usize>,
+    pub redex: Node,
+    pub result: Node,
+}
+
+// single-steps a term by leftmost-outermost small-step reduction, like the official evaluator
+pub struct Debugger {
+    term: Node,
+    // terms before each step together with the beta count at that time
+    history: VecDeque<(Node, usize)>,
+    history_limit: usize,
+    breakpoints: Vec<Breakpoint>,
+    steps: usize,
+    beta_reductions: usize,
+    // run stopped before the current redex, so the next run reduces it instead of stopping again
+    at_breakpoint: bool,
+}
+
+impl Debugger {
+    pub fn new(node: Node) -> Debugger {
+        Debugger {
+            term: node,
+            history: VecDeque::new(),
+            history_limit: 1000,
+            breakpoints: vec![],
+            steps: 0,
+            beta_reductions: 0,
+            at_breakpoint: false,
+        }
+    }
+
+    // keep at most `limit` previous terms to step back to
+    pub fn with_history_limit(mut self, limit: usize) -> Debugger {
+        self.history_limit = limit;
+        while self.history.len() > limit {
+            self.history.pop_front();
+        }
+        self
+    }
+
+    pub fn term(&self) -> &Node {
+        &self.term
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn beta_reductions(&self) -> usize {
+        self.beta_reductions
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let length = self.breakpoints.len();
+        self.breakpoints.retain(|b| b != breakpoint);
+        self.breakpoints.len() != length
+    }
+
+    // the term cannot be reduced any further
+    pub fn is_finished(&self) -> bool {
+        self.redex_path().is_none()
+    }
+
+    pub fn redex(&self) -> Option<&Node> {
+        self.redex_path().map(|path| node_at(&self.term, &path))
+    }
+
+    pub fn redex_path(&self) -> Option<Vec<usize>> {
+        let mut path = vec![];
+        let mut node = &self.term;
+        loop {
+            let next = match node {
+                Node::UnaryOperator(_, operand) if !is_value(operand) => 0,
+                Node::BinaryOperator(operator, left, right) if is_application(operator) => {
+                    match left.as_ref() {
+                        Node::Lambda(_, _) if operator == "!" && !is_value(right) => 1,
+                        Node::Lambda(_, _) => return Some(path),
+                        _ => 0,
+                    }
+                }
+                Node::BinaryOperator(_, left, _) if !is_value(left) => 0,
+                Node::BinaryOperator(_, _, right) if !is_value(right) => 1,
+                Node::If(condition, _, _) if !is_value(condition) => 0,
+                Node::UnaryOperator(_, _) | Node::BinaryOperator(_, _, _) | Node::If(_, _, _) => {
+                    return Some(path)
+                }
+                _ => return None,
+            };
+            path.push(next);
+            node = node.children()[next];
+        }
+    }
+
+    // reduce the current redex, returning None when the term is already finished
+    pub fn step(&mut self) -> Result<Option<Step>, EvalError> {
+        let Some(path) = self.redex_path() else {
+            return Ok(None);
+        };
+        let beta_reductions = self.beta_reductions;
+        let redex = node_at(&self.term, &path);
+        let result = match redex {
+            Node::BinaryOperator(operator, left, right) if is_application(operator) => {
+                match left.as_ref() {
+                    Node::Lambda(arity, body) => {
+                        self.beta_reductions += 1;
+                        substitute(body, arity, right)
+                    }
+                    _ => unreachable!("the redex of an application is a lambda"),
+                }
+            }
+            Node::If(condition, then_branch, else_branch) => match condition.as_ref() {
+                Node::Boolean(true) => then_branch.as_ref().clone(),
+                Node::Boolean(false) => else_branch.as_ref().clone(),
+                condition => {
+                    return Err(EvalError::UnsupportedCondition {
+                        condition: condition.name(),
+                    })
+                }
+            },
+            _ => reduce_primitive(redex)?,
+        };
+        let redex = redex.clone();
+
+        if self.history_limit > 0 {
+            if self.history.len() == self.history_limit {
+                self.history.pop_front();
+            }
+            self.history.push_back((self.term.clone(), beta_reductions));
+        }
+        *node_at_mut(&mut self.term, &path) = result.clone();
+        self.steps += 1;
+        self.at_breakpoint = false;
+        Ok(Some(Step {
+            path,
+            redex,
+            result,
+        }))
+    }
+
+    // undo the last step, false when there is no recorded history left
+    pub fn back(&mut self) -> bool {
+        match self.history.pop_back() {
+            Some((term, beta_reductions)) => {
+                self.term = term;
+                self.beta_reductions = beta_reductions;
+                self.steps -= 1;
+                self.at_breakpoint = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // step until a redex hits a breakpoint, returning it, or until the term is finished; the
+    // current redex is checked too unless the last run already stopped at it
+    pub fn run(&mut self) -> Result<Option<Breakpoint>, EvalError> {
+        loop {
+            if !self.at_breakpoint {
+                if let Some(breakpoint) = self.hit_breakpoint() {
+                    self.at_breakpoint = true;
+                    return Ok(Some(breakpoint));
+                }
+            }
+            if self.step()?.is_none() {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn hit_breakpoint(&self) -> Option<Breakpoint> {
+        let redex = self.redex()?;
+        self.breakpoints
+            .iter()
+            .find(|breakpoint| match (breakpoint, redex) {
+                (Breakpoint::Operator(operator), Node::If(_, _, _)) => operator == "?",
+                (Breakpoint::Operator(operator), Node::UnaryOperator(op, _))
+                | (Breakpoint::Operator(operator), Node::BinaryOperator(op, _, _)) => {
+                    operator == op
+                }
+                (Breakpoint::Lambda(id), Node::BinaryOperator(op, left, _))
+                    if is_application(op) =>
+                {
+                    matches!(left.as_ref(), Node::Lambda(arity, _) if arity == id)
+                }
+                _ => false,
+            })
+            .cloned()
+    }
+
+    // dump_tree style rendering of the whole term; the redex is marked with `>` and nodes
+    // deeper than `max_depth` are folded unless they lead to the redex
+    pub fn render(&self, max_depth: usize) -> String {
+        render_tree(&self.term, self.redex_path().as_deref(), max_depth)
+    }
+
+    pub fn render_redex(&self, max_depth: usize) -> String {
+        match self.redex() {
+            Some(redex) => render_tree(redex, Some(&[]), max_depth),
+            None => String::new(),
+        }
+    }
+}
+
+fn is_value(node: &Node) -> bool {
+    matches!(
+        node,
+        Node::Integer(_) | Node::String(_) | Node::Boolean(_) | Node::Lambda(_, _)
+    )
+}
+
+fn node_at<'a>(node: &'a Node, path: &[usize]) -> &'a Node {
+    path.iter()
+        .fold(node, |node, &index| node.children()[index])
+}
+
+fn node_at_mut<'a>(node: &'a mut Node, path: &[usize]) -> &'a mut Node {
+    let mut node = node;
+    for &index in path {
+        node = match (node, index) {
+            (Node::UnaryOperator(_, child), 0)
+            | (Node::Lambda(_, child), 0)
+            | (Node::BinaryOperator(_, child, _), 0)
+            | (Node::BinaryOperator(_, _, child), 1)
+            | (Node::If(child, _, _), 0)
+            | (Node::If(_, child, _), 1)
+            | (Node::If(_, _, child), 2) => child.as_mut(),
+            _ => panic!("invalid path"),
+        };
+    }
+    node
+}
+
+fn label(node: &Node) -> String {
+    match node {
+        Node::Integer(value) => format!("Integer({})", value),
+        Node::String(value) => format!("String({})", value.replace('\n', "\\n")),
+        Node::Boolean(value) => format!("Boolean({})", value),
+        Node::Variable(value) => format!("Variable({})", value),
+        Node::UnaryOperator(operator, _) => format!("UnaryOperator({})", operator),
+        Node::BinaryOperator(operator, _, _) => format!("BinaryOperator({})", operator),
+        Node::If(_, _, _) => "If".to_string(),
+        Node::Lambda(arity, _) => format!("Lambda({})", arity),
+    }
+}
+
+fn render_tree(node: &Node, redex: Option<&[usize]>, max_depth: usize) -> String {
+    let mut output = String::new();
+    // (node, depth, the rest of the path when this node lies on the way to the redex)
+    let mut stack = vec![(node, 0, redex)];
+    while let Some((node, depth, path)) = stack.pop() {
+        let marker = if path == Some(&[]) { "> " } else { "  " };
+        let children = node.children();
+        if depth > max_depth && path.is_none() {
+            output += &format!("{}{:indent$}...\n", marker, "", indent = depth * 2);
+            continue;
+        }
+        output += &format!(
+            "{}{:indent$}{}\n",
+            marker,
+            "",
+            label(node),
+            indent = depth * 2
+        );
+        for (index, child) in children.into_iter().enumerate().rev() {
+            let path = match path {
+                Some([first, rest @ ..]) if *first == index => Some(rest),
+                _ => None,
+            };
+            stack.push((child, depth + 1, path));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_step() {
+        let mut debugger = Debugger::new(parse("B$ L# B+ v# I# I$"));
+        assert_eq!(debugger.redex_path(), Some(vec![]));
+        let step = debugger.step().unwrap().unwrap();
+        assert_eq!(step.result, parse("B+ I$ I#"));
+        assert_eq!(debugger.beta_reductions(), 1);
+        let step = debugger.step().unwrap().unwrap();
+        assert_eq!(step.result, Node::Integer(BigInt::from(5)));
+        assert!(debugger.is_finished());
+        assert!(debugger.step().unwrap().is_none());
+        assert_eq!(debugger.steps(), 2);
+    }
+
+    #[test]
+    fn test_step_inner_redex() {
+        // the condition is reduced before the if itself
+        let mut debugger = Debugger::new(parse("? B> I# I$ S9%3 S./"));
+        assert_eq!(debugger.redex_path(), Some(vec![0]));
+        debugger.step().unwrap();
+        assert_eq!(debugger.term(), &parse("? F S9%3 S./"));
+        debugger.step().unwrap();
+        assert_eq!(debugger.term(), &Node::String("no".to_string()));
+    }
+
+    #[test]
+    fn test_back() {
+        let program = "B$ L# B+ v# I# I$";
+        let mut debugger = Debugger::new(parse(program));
+        debugger.run().unwrap();
+        assert_eq!(debugger.term(), &Node::Integer(BigInt::from(5)));
+        assert!(debugger.back());
+        assert!(debugger.back());
+        assert!(!debugger.back());
+        assert_eq!(debugger.term(), &parse(program));
+        assert_eq!(debugger.beta_reductions(), 0);
+        assert_eq!(debugger.steps(), 0);
+
+        let mut debugger = Debugger::new(parse(program)).with_history_limit(1);
+        debugger.run().unwrap();
+        assert!(debugger.back());
+        assert!(!debugger.back());
+        assert_eq!(debugger.term(), &parse("B+ I$ I#"));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // the Y combinator example from the language notes
+        let program = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%";
+        let mut debugger = Debugger::new(parse(program));
+        debugger.add_breakpoint(Breakpoint::Operator("+".to_string()));
+        assert_eq!(
+            debugger.run().unwrap(),
+            Some(Breakpoint::Operator("+".to_string()))
+        );
+        assert!(matches!(debugger.redex(), Some(Node::BinaryOperator(op, _, _)) if op == "+"));
+
+        assert!(debugger.remove_breakpoint(&Breakpoint::Operator("+".to_string())));
+        debugger.add_breakpoint(Breakpoint::Lambda(BigInt::from(3)));
+        assert_eq!(
+            debugger.run().unwrap(),
+            Some(Breakpoint::Lambda(BigInt::from(3)))
+        );
+
+        debugger.remove_breakpoint(&Breakpoint::Lambda(BigInt::from(3)));
+        assert_eq!(debugger.run().unwrap(), None);
+        assert_eq!(debugger.term(), &Node::Integer(BigInt::from(16)));
+        assert_eq!(debugger.beta_reductions(), 109);
+    }
+
+    #[test]
+    fn test_breakpoint_at_first_redex() {
+        let mut debugger = Debugger::new(parse("B+ I# I$"));
+        debugger.add_breakpoint(Breakpoint::Operator("+".to_string()));
+        assert_eq!(
+            debugger.run().unwrap(),
+            Some(Breakpoint::Operator("+".to_string()))
+        );
+        assert_eq!(debugger.steps(), 0);
+        // continuing reduces the redex it stopped at
+        assert_eq!(debugger.run().unwrap(), None);
+        assert_eq!(debugger.term(), &Node::Integer(BigInt::from(5)));
+    }
+
+    #[test]
+    fn test_step_error() {
+        let mut debugger = Debugger::new(parse("B+ I# S#"));
+        assert!(matches!(
+            debugger.step(),
+            Err(EvalError::UnsupportedBinaryOperands { .. })
+        ));
+        assert_eq!(debugger.steps(), 0);
+    }
+
+    #[test]
+    fn test_render() {
+        let debugger = Debugger::new(parse("B+ B* I# I$ B$ L# v# I%"));
+        assert_eq!(
+            debugger.render(10),
+            [
+                "  BinaryOperator(+)",
+                ">   BinaryOperator(*)",
+                "      Integer(2)",
+                "      Integer(3)",
+                "    BinaryOperator($)",
+                "      Lambda(2)",
+                "        Variable(2)",
+                "      Integer(4)",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            debugger.render(1),
+            [
+                "  BinaryOperator(+)",
+                ">   BinaryOperator(*)",
+                "      ...",
+                "      ...",
+                "    BinaryOperator($)",
+                "      ...",
+                "      ...",
+                "",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            debugger.render_redex(0),
+            ["> BinaryOperator(*)", "    ...", "    ...", ""].join("\n")
+        );
+    }
+}
@@ -0,0 +1,714 @@
+use num_bigint::BigInt;
+
+use super::evaluator::is_application;
+use super::parser::Node;
+use super::surface::Expr;
+
+// pseudo-code lines are broken when they would be longer than this
+pub const WIDTH: usize = 80;
+
+// turn a program into the surface language, with immediately applied lambdas as `let`,
+// fixpoint combinators as `let rec` and string repetition loops as `repeat s n`;
+// strict and lazy applications are shown like `$`
+pub fn decompile(node: &Node) -> Expr {
+    Decompiler {
+        scope: vec![],
+        functions: 0,
+        values: 0,
+    }
+    .decompile(node)
+}
+
+pub fn decompile_to_string(node: &Node) -> String {
+    pretty(&decompile(node))
+}
+
+const FUNCTION_NAMES: [&str; 4] = ["f", "g", "h", "k"];
+const VALUE_NAMES: [&str; 8] = ["x", "y", "z", "a", "b", "c", "d", "e"];
+
+type Bindings = Vec<(BigInt, String)>;
+
+struct Decompiler {
+    // the name given to each variable id, innermost binder last
+    scope: Bindings,
+    functions: usize,
+    values: usize,
+}
+
+impl Decompiler {
+    fn fresh(names: &[&str], count: &mut usize) -> String {
+        let (name, round) = (names[*count % names.len()], *count / names.len());
+        *count += 1;
+        match round {
+            0 => name.to_string(),
+            _ => format!("{}{}", name, round),
+        }
+    }
+
+    fn fresh_function(&mut self) -> String {
+        Decompiler::fresh(&FUNCTION_NAMES, &mut self.functions)
+    }
+
+    fn fresh_value(&mut self) -> String {
+        Decompiler::fresh(&VALUE_NAMES, &mut self.values)
+    }
+
+    // decompile a node with extra names in scope
+    fn scoped(&mut self, names: Bindings, node: &Node) -> Expr {
+        let depth = self.scope.len();
+        self.scope.extend(names);
+        let expr = self.decompile(node);
+        self.scope.truncate(depth);
+        expr
+    }
+
+    // name the parameters of a curried lambda and decompile its body
+    fn lambda<'a>(&mut self, mut node: &'a Node) -> (Vec<String>, Bindings, &'a Node) {
+        let mut bindings = vec![];
+        while let Node::Lambda(variable, body) = node {
+            bindings.push((variable.clone(), self.fresh_value()));
+            node = body;
+        }
+        let names = bindings.iter().map(|(_, name)| name.clone()).collect();
+        (names, bindings, node)
+    }
+
+    fn decompile(&mut self, node: &Node) -> Expr {
+        match node {
+            Node::Integer(value) => Expr::Integer(value.clone()),
+            Node::String(value) => Expr::String(value.clone()),
+            Node::Boolean(value) => Expr::Boolean(*value),
+            Node::Variable(variable) => {
+                match self.scope.iter().rev().find(|(bound, _)| bound == variable) {
+                    Some((_, name)) => Expr::Name(name.clone()),
+                    None => Expr::Name(format!("v{}", variable)),
+                }
+            }
+            Node::Lambda(_, _) => {
+                let (names, bindings, body) = self.lambda(node);
+                let body = self.scoped(bindings, body);
+                Expr::Lambda(names, Box::new(body))
+            }
+            Node::UnaryOperator(operator, operand) => {
+                let operand = self.decompile(operand);
+                match operator.as_str() {
+                    "#" => builtin("string_to_int", vec![operand]),
+                    "$" => builtin("int_to_string", vec![operand]),
+                    _ => Expr::Unary(operator.clone(), Box::new(operand)),
+                }
+            }
+            Node::BinaryOperator(operator, left, right) if is_application(operator) => {
+                self.application(left, right)
+            }
+            Node::BinaryOperator(operator, left, right) => {
+                let (left, right) = (self.decompile(left), self.decompile(right));
+                match operator.as_str() {
+                    "T" => builtin("take", vec![left, right]),
+                    "D" => builtin("drop", vec![left, right]),
+                    _ => Expr::Binary(operator.clone(), Box::new(left), Box::new(right)),
+                }
+            }
+            Node::If(condition, then_branch, else_branch) => Expr::If(
+                Box::new(self.decompile(condition)),
+                Box::new(self.decompile(then_branch)),
+                Box::new(self.decompile(else_branch)),
+            ),
+        }
+    }
+
+    fn application(&mut self, left: &Node, right: &Node) -> Expr {
+        match (left, right) {
+            (_, Node::Lambda(function, body)) if is_fixpoint(left) => {
+                let name = self.fresh_function();
+                let (parameters, mut bindings, body) = self.lambda(body);
+                bindings.insert(0, (function.clone(), name.clone()));
+                let body = self.scoped(bindings, body);
+                Expr::LetRec(
+                    name.clone(),
+                    parameters,
+                    Box::new(body),
+                    Box::new(Expr::Name(name)),
+                )
+            }
+            (Node::Lambda(variable, body), _) => {
+                let value = self.decompile(right);
+                match value {
+                    // a recursive function bound by a let keeps its name
+                    Expr::LetRec(name, parameters, value, result)
+                        if *result == Expr::Name(name.clone()) =>
+                    {
+                        let body = self.scoped(vec![(variable.clone(), name.clone())], body);
+                        let_rec(name, parameters, *value, body)
+                    }
+                    value => {
+                        let name = match value {
+                            Expr::Lambda(_, _) => self.fresh_function(),
+                            _ => self.fresh_value(),
+                        };
+                        let body = self.scoped(vec![(variable.clone(), name.clone())], body);
+                        Expr::Let(name, Box::new(value), Box::new(body))
+                    }
+                }
+            }
+            _ => {
+                let function = self.decompile(left);
+                let argument = self.decompile(right);
+                apply(function, argument)
+            }
+        }
+    }
+}
+
+fn builtin(name: &str, arguments: Vec<Expr>) -> Expr {
+    arguments
+        .into_iter()
+        .fold(Expr::Name(name.to_string()), |function, argument| {
+            Expr::Apply(Box::new(function), Box::new(argument))
+        })
+}
+
+// names are unique, so the argument can be moved into the body of a let
+fn apply(function: Expr, argument: Expr) -> Expr {
+    match function {
+        Expr::LetRec(name, parameters, value, body) => {
+            let_rec(name, parameters, *value, apply(*body, argument))
+        }
+        Expr::Let(name, value, body) => Expr::Let(name, value, Box::new(apply(*body, argument))),
+        function => Expr::Apply(Box::new(function), Box::new(argument)),
+    }
+}
+
+fn let_rec(name: String, parameters: Vec<String>, value: Expr, body: Expr) -> Expr {
+    match repeat(&name, &parameters, &value, &body) {
+        Some(expr) => expr,
+        None => Expr::LetRec(name, parameters, Box::new(value), Box::new(body)),
+    }
+}
+
+// `let rec f n = if n == 0 then "" else s ++ f (n - 1)`, with the test or the concatenation
+// the other way round, repeats s; when the body only calls it, each `f count` becomes
+// `repeat s count`
+fn repeat(name: &str, parameters: &[String], value: &Expr, body: &Expr) -> Option<Expr> {
+    let [counter] = parameters else {
+        return None;
+    };
+    let Expr::If(condition, then_branch, else_branch) = value else {
+        return None;
+    };
+    let is = |expr: &Expr, operator: &str, left: &Expr, right: &Expr| match expr {
+        Expr::Binary(found, l, r) => found == operator && **l == *left && **r == *right,
+        _ => false,
+    };
+    let (n, zero, one) = (
+        Expr::Name(counter.clone()),
+        Expr::Integer(BigInt::from(0)),
+        Expr::Integer(BigInt::from(1)),
+    );
+    let (empty, step) = if is(condition, "=", &n, &zero)
+        || is(condition, "=", &zero, &n)
+        || is(condition, "<", &n, &one)
+    {
+        (then_branch, else_branch)
+    } else if is(condition, ">", &n, &zero) {
+        (else_branch, then_branch)
+    } else {
+        return None;
+    };
+    let Expr::Binary(operator, left, right) = step.as_ref() else {
+        return None;
+    };
+    let call = Expr::Apply(
+        Box::new(Expr::Name(name.to_string())),
+        Box::new(Expr::Binary("-".to_string(), Box::new(n), Box::new(one))),
+    );
+    let string = match (**left == call, **right == call) {
+        (false, true) => left,
+        (true, false) => right,
+        _ => return None,
+    };
+    if **empty != Expr::String(String::new())
+        || operator != "."
+        || mentions(string, name)
+        || mentions(string, counter)
+    {
+        return None;
+    }
+    replace_calls(body, name, string)
+}
+
+// the expression with every `f count` replaced by `repeat s count`, None if f is used
+// in any other way
+fn replace_calls(expr: &Expr, name: &str, string: &Expr) -> Option<Expr> {
+    let replace = |expr: &Expr| replace_calls(expr, name, string).map(Box::new);
+    Some(match expr {
+        Expr::Apply(function, count) if **function == Expr::Name(name.to_string()) => {
+            builtin("repeat", vec![string.clone(), *replace(count)?])
+        }
+        Expr::Name(found) if found == name => return None,
+        Expr::Integer(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Name(_) => expr.clone(),
+        Expr::Lambda(parameters, body) => Expr::Lambda(parameters.clone(), replace(body)?),
+        Expr::Unary(operator, operand) => Expr::Unary(operator.clone(), replace(operand)?),
+        Expr::Apply(function, argument) => Expr::Apply(replace(function)?, replace(argument)?),
+        Expr::Binary(operator, left, right) => {
+            Expr::Binary(operator.clone(), replace(left)?, replace(right)?)
+        }
+        Expr::If(condition, then_branch, else_branch) => Expr::If(
+            replace(condition)?,
+            replace(then_branch)?,
+            replace(else_branch)?,
+        ),
+        Expr::Let(variable, value, body) => {
+            Expr::Let(variable.clone(), replace(value)?, replace(body)?)
+        }
+        Expr::LetRec(variable, parameters, value, body) => Expr::LetRec(
+            variable.clone(),
+            parameters.clone(),
+            replace(value)?,
+            replace(body)?,
+        ),
+    })
+}
+
+fn mentions(expr: &Expr, name: &str) -> bool {
+    match expr {
+        Expr::Name(found) => found == name,
+        Expr::Integer(_) | Expr::String(_) | Expr::Boolean(_) => false,
+        Expr::Lambda(_, body) | Expr::Unary(_, body) => mentions(body, name),
+        Expr::Apply(left, right)
+        | Expr::Binary(_, left, right)
+        | Expr::Let(_, left, right)
+        | Expr::LetRec(_, _, left, right) => mentions(left, name) || mentions(right, name),
+        Expr::If(condition, then_branch, else_branch) => {
+            mentions(condition, name) || mentions(then_branch, name) || mentions(else_branch, name)
+        }
+    }
+}
+
+// fixpoint combinators: λf. A B where each half is λx. x x, λx. f (x x) or λx. f (λv. x x v),
+// and at least one of them calls f
+fn is_fixpoint(node: &Node) -> bool {
+    let Node::Lambda(function, body) = node else {
+        return false;
+    };
+    let Some((left, right)) = application(body) else {
+        return false;
+    };
+    match (half(left, function), half(right, function)) {
+        (Some(left), Some(right)) => left || right,
+        _ => false,
+    }
+}
+
+// whether a half of a fixpoint combinator calls the function, None if it is not one
+fn half(node: &Node, function: &BigInt) -> Option<bool> {
+    let Node::Lambda(variable, body) = node else {
+        return None;
+    };
+    let is_variable =
+        |node: &Node, id: &BigInt| matches!(node, Node::Variable(found) if found == id);
+    let self_application = |node: &Node| match application(node) {
+        Some((left, right)) => is_variable(left, variable) && is_variable(right, variable),
+        None => false,
+    };
+    if variable == function {
+        return None;
+    }
+    if self_application(body) {
+        return Some(false);
+    }
+    let (called, argument) = application(body)?;
+    if !is_variable(called, function) {
+        return None;
+    }
+    let delayed = match argument {
+        Node::Lambda(parameter, inner) if parameter != variable && parameter != function => {
+            match application(inner) {
+                Some((left, right)) => self_application(left) && is_variable(right, parameter),
+                None => false,
+            }
+        }
+        _ => false,
+    };
+    (self_application(argument) || delayed).then_some(true)
+}
+
+fn application(node: &Node) -> Option<(&Node, &Node)> {
+    match node {
+        Node::BinaryOperator(operator, left, right) if is_application(operator) => {
+            Some((left, right))
+        }
+        _ => None,
+    }
+}
+
+pub fn pretty(expr: &Expr) -> String {
+    layout(expr, 0)
+}
+
+// surface symbol and precedence level of an infix operator, with `!` over a comparison
+// shown as the negated comparison
+fn infix(expr: &Expr) -> Option<(&'static str, usize, &Expr, &Expr)> {
+    match expr {
+        Expr::Unary(operator, operand) if operator == "!" => match operand.as_ref() {
+            Expr::Binary(operator, left, right) => {
+                let symbol = match operator.as_str() {
+                    "=" => "!=",
+                    ">" => "<=",
+                    "<" => ">=",
+                    _ => return None,
+                };
+                Some((symbol, 3, left, right))
+            }
+            _ => None,
+        },
+        Expr::Binary(operator, left, right) => {
+            let (symbol, level) = match operator.as_str() {
+                "|" => ("||", 1),
+                "&" => ("&&", 2),
+                "=" => ("==", 3),
+                "<" => ("<", 3),
+                ">" => (">", 3),
+                "." => ("++", 4),
+                "+" => ("+", 5),
+                "-" => ("-", 5),
+                "*" => ("*", 6),
+                "/" => ("/", 6),
+                "%" => ("%", 6),
+                _ => return None,
+            };
+            Some((symbol, level, left, right))
+        }
+        _ => None,
+    }
+}
+
+// the levels the two operands of an infix operator need to be written without parentheses
+fn operand_levels(symbol: &str, level: usize) -> (usize, usize) {
+    match (symbol, level) {
+        ("++", _) => (level + 1, level),
+        (_, 3) => (level + 1, level + 1),
+        _ => (level, level + 1),
+    }
+}
+
+const UNARY_LEVEL: usize = 7;
+const APPLY_LEVEL: usize = 8;
+const ATOM_LEVEL: usize = 9;
+
+fn level(expr: &Expr) -> usize {
+    if let Some((_, level, _, _)) = infix(expr) {
+        return level;
+    }
+    match expr {
+        Expr::Integer(value) if *value < BigInt::from(0) => UNARY_LEVEL,
+        Expr::Integer(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Name(_) => ATOM_LEVEL,
+        Expr::Unary(_, _) => UNARY_LEVEL,
+        Expr::Apply(_, _) => APPLY_LEVEL,
+        // binary operators without a symbol are not produced by the decompiler
+        Expr::Binary(_, _, _) => APPLY_LEVEL,
+        Expr::Lambda(_, _) | Expr::If(_, _, _) | Expr::Let(_, _, _) | Expr::LetRec(_, _, _, _) => 0,
+    }
+}
+
+fn escape(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+// `name parameters` of a let, with the parameters of a lambda value moved to the left
+fn let_head(expr: &Expr) -> Option<(String, &Expr)> {
+    match expr {
+        Expr::Let(name, value, _) => match value.as_ref() {
+            Expr::Lambda(parameters, body) => {
+                Some((format!("{} {}", name, parameters.join(" ")), body))
+            }
+            value => Some((name.clone(), value)),
+        },
+        Expr::LetRec(name, parameters, value, _) => {
+            let mut head = format!("rec {}", name);
+            for parameter in parameters {
+                head.push(' ');
+                head.push_str(parameter);
+            }
+            Some((head, value))
+        }
+        _ => None,
+    }
+}
+
+fn inline(expr: &Expr, at: usize) -> String {
+    let result = if let Some((symbol, level, left, right)) = infix(expr) {
+        let (left_level, right_level) = operand_levels(symbol, level);
+        format!(
+            "{} {} {}",
+            inline(left, left_level),
+            symbol,
+            inline(right, right_level)
+        )
+    } else {
+        match expr {
+            Expr::Integer(value) => value.to_string(),
+            Expr::String(value) => escape(value),
+            Expr::Boolean(value) => value.to_string(),
+            Expr::Name(name) => name.clone(),
+            Expr::Lambda(parameters, body) => {
+                format!("fn {} -> {}", parameters.join(" "), inline(body, 0))
+            }
+            Expr::Apply(function, argument) => format!(
+                "{} {}",
+                inline(function, APPLY_LEVEL),
+                inline(argument, ATOM_LEVEL)
+            ),
+            Expr::Unary(operator, operand) => {
+                format!("{}{}", operator, inline(operand, UNARY_LEVEL))
+            }
+            Expr::Binary(operator, left, right) => format!(
+                "B{} {} {}",
+                operator,
+                inline(left, ATOM_LEVEL),
+                inline(right, ATOM_LEVEL)
+            ),
+            Expr::If(condition, then_branch, else_branch) => format!(
+                "if {} then {} else {}",
+                inline(condition, 0),
+                inline(then_branch, 0),
+                inline(else_branch, 0)
+            ),
+            Expr::Let(_, _, body) | Expr::LetRec(_, _, _, body) => {
+                let (head, value) = let_head(expr).unwrap();
+                format!("let {} = {} in {}", head, inline(value, 0), inline(body, 0))
+            }
+        }
+    };
+    if level(expr) < at {
+        format!("({})", result)
+    } else {
+        result
+    }
+}
+
+fn has_let(expr: &Expr) -> bool {
+    match expr {
+        Expr::Let(_, _, _) | Expr::LetRec(_, _, _, _) => true,
+        Expr::Integer(_) | Expr::String(_) | Expr::Boolean(_) | Expr::Name(_) => false,
+        Expr::Lambda(_, body) | Expr::Unary(_, body) => has_let(body),
+        Expr::Apply(left, right) | Expr::Binary(_, left, right) => has_let(left) || has_let(right),
+        Expr::If(condition, then_branch, else_branch) => {
+            has_let(condition) || has_let(then_branch) || has_let(else_branch)
+        }
+    }
+}
+
+// lay out an expression starting at the given column, lets are always on their own lines
+fn layout(expr: &Expr, indent: usize) -> String {
+    if !has_let(expr) {
+        let result = inline(expr, 0);
+        if indent + result.len() <= WIDTH {
+            return result;
+        }
+    }
+    let pad = " ".repeat(indent);
+    let inner = " ".repeat(indent + 4);
+    if let Some((symbol, level, left, right)) = infix(expr) {
+        let (left_level, right_level) = operand_levels(symbol, level);
+        return format!(
+            "{}\n{}{} {}",
+            operand(left, indent, left_level),
+            pad,
+            symbol,
+            operand(right, indent + symbol.len() + 1, right_level)
+        );
+    }
+    match expr {
+        Expr::Let(_, _, body) | Expr::LetRec(_, _, _, body) => {
+            let (head, value) = let_head(expr).unwrap();
+            let value_layout = layout(value, indent + 4);
+            let definition = format!("let {} = {} in", head, value_layout);
+            let definition = if !value_layout.contains('\n') && indent + definition.len() <= WIDTH {
+                definition
+            } else {
+                format!("let {} =\n{}{}\n{}in", head, inner, value_layout, pad)
+            };
+            format!("{}\n{}{}", definition, pad, layout(body, indent))
+        }
+        Expr::If(condition, then_branch, else_branch) => {
+            let else_layout = match else_branch.as_ref() {
+                Expr::If(_, _, _) => format!(" {}", layout(else_branch, indent)),
+                _ => format!("\n{}{}", inner, layout(else_branch, indent + 4)),
+            };
+            format!(
+                "if {} then\n{}{}\n{}else{}",
+                layout(condition, indent + 3),
+                inner,
+                layout(then_branch, indent + 4),
+                pad,
+                else_layout
+            )
+        }
+        Expr::Lambda(parameters, body) => format!(
+            "fn {} ->\n{}{}",
+            parameters.join(" "),
+            inner,
+            layout(body, indent + 4)
+        ),
+        Expr::Apply(_, _) => {
+            let mut arguments = vec![];
+            let mut function = expr;
+            while let Expr::Apply(left, right) = function {
+                arguments.push(right.as_ref());
+                function = left;
+            }
+            let mut result = operand(function, indent, APPLY_LEVEL);
+            for argument in arguments.into_iter().rev() {
+                result.push('\n');
+                result.push_str(&inner);
+                result.push_str(&operand(argument, indent + 4, ATOM_LEVEL));
+            }
+            result
+        }
+        Expr::Unary(operator, body) => {
+            format!("{}{}", operator, operand(body, indent + 1, UNARY_LEVEL))
+        }
+        _ => inline(expr, 0),
+    }
+}
+
+// an operand that needs parentheses gets them around its own indented block
+fn operand(expr: &Expr, indent: usize, at: usize) -> String {
+    if level(expr) >= at {
+        return layout(expr, indent);
+    }
+    if !has_let(expr) {
+        let result = inline(expr, at);
+        if indent + result.len() <= WIDTH {
+            return result;
+        }
+    }
+    format!(
+        "(\n{}{}\n{})",
+        " ".repeat(indent + 4),
+        layout(expr, indent + 4),
+        " ".repeat(indent)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{
+        evaluator::Evaluator,
+        parser::Parser,
+        surface::{compile, compile_source},
+        tokenizer::Tokenizer,
+    };
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_decompile() {
+        let cases = [
+            ("B+ I\" B* I# I$", "1 + 2 * 3"),
+            ("B* B+ I\" I# I$", "(1 + 2) * 3"),
+            ("B. S% B. S& S'", "\"e\" ++ \"f\" ++ \"g\""),
+            ("B. B. S% S& S'", "(\"e\" ++ \"f\") ++ \"g\""),
+            ("U! B= I! v%", "0 != v4"),
+            ("BT I# U$ I$", "take 2 (int_to_string 3)"),
+            ("L! L\" B$ v! v\"", "fn x y -> x y"),
+            ("B$ L# B+ v# v# I$", "let x = 3 in\nx + x"),
+            // the example from the language notes, a Y combinator computing 16
+            (
+                "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%",
+                "\
+let rec f x =
+    if x == 0 then
+        1
+    else
+        let y = x - 1 in
+        f y + f y
+in
+f 4",
+            ),
+            (
+                "B$ L! B$ v! I# L! L\" B+ v! v\"",
+                "let f x y = x + y in\nf 2",
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(decompile_to_string(&parse(input)), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_decompile_surface_programs() {
+        let cases = [
+            (
+                "let rec fact n = if n == 0 then 1 else n * fact (n - 1) in fact 10",
+                "let rec f x = if x == 0 then 1 else x * f (x - 1) in\nf 10",
+            ),
+            (
+                "let rec loop n = if n == 0 then \"\" else \"R\" ++ loop (n - 1) in \"path \" ++ loop 50",
+                "\"path \" ++ repeat \"R\" 50",
+            ),
+            (
+                "let rec loop n = if n > 0 then loop (n - 1) ++ \"UD\" else \"\" in loop 3",
+                "repeat \"UD\" 3",
+            ),
+            (
+                "let greet = fn name -> \"Hello\\n\\\"\" ++ name in greet (take 3 \"World!\")",
+                "let f x = \"Hello\\n\\\"\" ++ x in\nf (take 3 \"World!\")",
+            ),
+        ];
+        for (source, expected) in cases {
+            let node = compile_source(source).unwrap();
+            assert_eq!(decompile_to_string(&node), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_decompile_layout() {
+        let source = "let rec f x y = if x == 0 then y else f (x - 1) (y ++ \"a long string literal to make the line wrap\") in f 100 \"\"";
+        let expected = "\
+let rec f x y =
+    if x == 0 then
+        y
+    else
+        f (x - 1) (y ++ \"a long string literal to make the line wrap\")
+in
+f 100 \"\"";
+        let node = compile_source(source).unwrap();
+        assert_eq!(decompile_to_string(&node), expected);
+    }
+
+    #[test]
+    fn test_decompile_round_trip() {
+        // without `repeat`, the pseudo-code compiles back to an equivalent program
+        let inputs = [
+            "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%",
+            "B$ L! B$ v! I# L! L\" B+ v! v\"",
+            "? U! B< I# I$ S% BD I\" S%&'",
+        ];
+        for input in inputs {
+            let node = parse(input);
+            let expr = decompile(&node);
+            let recompiled = compile_source(&pretty(&expr)).unwrap();
+            assert_eq!(compile(&expr), recompiled);
+            assert_eq!(
+                Evaluator::new(recompiled).evaluate().unwrap(),
+                Evaluator::new(node).evaluate().unwrap()
+            );
+        }
+    }
+}
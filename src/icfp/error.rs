@@ -0,0 +1,145 @@
+use std::fmt;
+
+use num_bigint::BigInt;
+
+use super::tokenizer::Token;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    // a character outside of ASCII 33..=126 inside a token, `position` is a byte offset
+    InvalidCharacter {
+        character: char,
+        position: usize,
+    },
+    // strict tokenizing only: a token starting with a character that is not an indicator
+    UnknownIndicator {
+        indicator: char,
+        position: usize,
+    },
+    // strict tokenizing only: a body that does not fit its indicator, e.g. `T!` or an empty `I`
+    InvalidBody {
+        indicator: char,
+        body: String,
+        position: usize,
+    },
+    // an error in a program of the surface language, `line` and `column` start at 1
+    SyntaxError {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+    // the token stream ended while an operator was still waiting for operands
+    UnexpectedEndOfInput {
+        position: usize,
+    },
+    // a token that cannot start an expression, `position` is a token index
+    UnexpectedToken {
+        token: Token,
+        position: usize,
+    },
+    UnknownUnaryOperator {
+        operator: String,
+    },
+    UnknownBinaryOperator {
+        operator: String,
+    },
+    UnsupportedUnaryOperand {
+        operator: String,
+        operand: String,
+    },
+    UnsupportedBinaryOperands {
+        operator: String,
+        left: String,
+        right: String,
+    },
+    UnsupportedCondition {
+        condition: String,
+    },
+    DivisionByZero {
+        operator: String,
+    },
+    // more beta reductions than the configured budget were needed
+    BetaReductionLimitExceeded {
+        limit: usize,
+    },
+    // a call-by-need thunk demanded its own value while being evaluated
+    InfiniteLoop,
+    // the result of the program is not the kind of value the caller asked for
+    UnexpectedResult {
+        expected: String,
+        actual: String,
+    },
+    // a variable bound by no lambda around it, where only closed terms are accepted
+    FreeVariable {
+        variable: BigInt,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::InvalidCharacter {
+                character,
+                position,
+            } => write!(f, "invalid character {:?} at byte {}", character, position),
+            EvalError::UnknownIndicator {
+                indicator,
+                position,
+            } => write!(f, "unknown indicator {:?} at byte {}", indicator, position),
+            EvalError::InvalidBody {
+                indicator,
+                body,
+                position,
+            } => write!(
+                f,
+                "invalid body {:?} for indicator {} at byte {}",
+                body, indicator, position
+            ),
+            EvalError::SyntaxError {
+                message,
+                line,
+                column,
+            } => write!(f, "{} at line {}, column {}", message, line, column),
+            EvalError::UnexpectedEndOfInput { position } => {
+                write!(f, "unexpected end of input at token {}", position)
+            }
+            EvalError::UnexpectedToken { token, position } => {
+                write!(f, "unexpected token {:?} at token {}", token, position)
+            }
+            EvalError::UnknownUnaryOperator { operator } => {
+                write!(f, "unknown unary operator U{}", operator)
+            }
+            EvalError::UnknownBinaryOperator { operator } => {
+                write!(f, "unknown binary operator B{}", operator)
+            }
+            EvalError::UnsupportedUnaryOperand { operator, operand } => {
+                write!(f, "unsupported operand for U{}: {}", operator, operand)
+            }
+            EvalError::UnsupportedBinaryOperands {
+                operator,
+                left,
+                right,
+            } => write!(
+                f,
+                "unsupported operands for B{}: {} and {}",
+                operator, left, right
+            ),
+            EvalError::UnsupportedCondition { condition } => {
+                write!(f, "unsupported condition for ?: {}", condition)
+            }
+            EvalError::DivisionByZero { operator } => {
+                write!(f, "division by zero in B{}", operator)
+            }
+            EvalError::BetaReductionLimitExceeded { limit } => {
+                write!(f, "exceeded the limit of {} beta reductions", limit)
+            }
+            EvalError::InfiniteLoop => write!(f, "infinite loop detected while forcing a thunk"),
+            EvalError::UnexpectedResult { expected, actual } => {
+                write!(f, "expected {} as the result, got {}", expected, actual)
+            }
+            EvalError::FreeVariable { variable } => write!(f, "free variable {}", variable),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
@@ -1,265 +1,657 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use num_bigint::BigInt;
 
-use super::parser::Node;
+use super::arena::{Arena, BinaryOp, NodeId, Term, UnaryOp};
+use super::bytecode::compile;
+use super::error::EvalError;
+use super::lazy_evaluator::LazyEvaluator;
+use super::parser::{Node, Parser};
+use super::profiler::Profile;
+use super::tokenizer::Tokenizer;
 use super::util::{
     convert_integer_to_bigint, convert_string, deconvert_integer_from_bigint, deconvert_string,
 };
+use super::vm::Vm;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Strategy {
+    // rewrite the tree by substituting arguments into lambda bodies
+    #[default]
+    Substitution,
+    // closures and environments with shared thunks
+    CallByNeed,
+    // closures and environments without sharing, counting beta reductions like the official evaluator
+    CallByName,
+    // compile to bytecode and run it on the virtual machine, call-by-need
+    Bytecode,
+}
+
+// the official evaluator aborts after this many beta reductions
+pub const BETA_REDUCTION_LIMIT: usize = 10_000_000;
 
 pub struct Evaluator {
-    node: Node,
-    cache: HashMap<Node, Node>,
+    // shared with the bytecode program instead of copied into it
+    node: Rc<Node>,
+    // evaluation works on hash-consed terms, so the cache compares ids instead of trees
+    arena: Arena,
+    cache: HashMap<NodeId, NodeId>,
     eval_count: usize,
+    strategy: Strategy,
+    beta_reductions: usize,
+    beta_limit: Option<usize>,
+    profiling: bool,
+    profile: Option<Profile>,
 }
 
 impl Evaluator {
     pub fn new(node: Node) -> Evaluator {
+        Evaluator::with_strategy(node, Strategy::default())
+    }
+
+    pub fn with_strategy(node: Node, strategy: Strategy) -> Evaluator {
         Evaluator {
-            node,
+            node: Rc::new(node),
+            arena: Arena::new(),
             cache: HashMap::new(),
             eval_count: 0,
+            strategy,
+            beta_reductions: 0,
+            beta_limit: None,
+            profiling: false,
+            profile: None,
         }
     }
 
-    pub fn evaluate(&mut self) -> Node {
-        let result = self.evaluate_node(&self.node.clone());
-        let result = match result {
-            Node::String(_) => result,
-            Node::Integer(_) => result,
-            _ => self.evaluate_node(&result),
-        };
-        eprintln!("Evaluated {} nodes", self.eval_count);
-        result
+    // abort with EvalError::BetaReductionLimitExceeded once more than `limit` reductions are done
+    pub fn with_beta_limit(mut self, limit: usize) -> Evaluator {
+        self.beta_limit = Some(limit);
+        self
+    }
+
+    // collect a profile while evaluating, only the environment strategies are profiled
+    pub fn with_profiler(mut self) -> Evaluator {
+        self.profiling = true;
+        self
     }
 
-    fn evaluate_node(&mut self, node: &Node) -> Node {
-        if let Some(result) = self.cache.get(node) {
-            return result.clone();
+    // the profile of the last call to evaluate
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // the number of beta reductions performed by the last call to evaluate
+    pub fn beta_reductions(&self) -> usize {
+        self.beta_reductions
+    }
+
+    pub fn evaluate(&mut self) -> Result<Node, EvalError> {
+        if self.strategy == Strategy::Bytecode {
+            let program = compile(self.node.clone())?;
+            let mut vm = Vm::new(&program);
+            if let Some(limit) = self.beta_limit {
+                vm = vm.with_beta_limit(limit);
+            }
+            let result = vm.run();
+            self.eval_count = vm.instructions();
+            self.beta_reductions = vm.beta_reductions();
+            return result;
         }
-        self.eval_count += 1;
-        let result = match node {
-            Node::Integer(_) => node.clone(),
-            Node::String(_) => node.clone(),
-            Node::Boolean(_) => node.clone(),
-            Node::Variable(_) => node.clone(),
-            Node::Lambda(arity, body) => Node::Lambda(arity.clone(), body.clone()),
-            Node::UnaryOperator(operator, operand) => {
-                self.evaluate_unary_operator(operator, *operand.clone())
-            }
-            Node::BinaryOperator(_, _, _) => self.evaluate_binary_operator(node.clone()),
-            Node::If(condition, then_branch, else_branch) => {
-                let condition = self.evaluate_node(condition);
-                match condition {
-                    Node::Boolean(true) => self.evaluate_node(then_branch),
-                    Node::Boolean(false) => self.evaluate_node(else_branch),
-                    _ => panic!("Unsupported condition: {:?}", condition),
-                }
+        if self.strategy != Strategy::Substitution {
+            let mut evaluator = match self.strategy {
+                Strategy::CallByName => LazyEvaluator::call_by_name(&self.node),
+                _ => LazyEvaluator::new(&self.node),
+            };
+            if let Some(limit) = self.beta_limit {
+                evaluator = evaluator.with_beta_limit(limit);
             }
-            _ => panic!("Unsupported node: {:?}", node),
+            if self.profiling {
+                evaluator = evaluator.with_profiler();
+            }
+            let result = evaluator.evaluate();
+            self.eval_count = evaluator.eval_count();
+            self.beta_reductions = evaluator.beta_reductions();
+            self.profile = evaluator.take_profile();
+            return result;
+        }
+        let root = self.arena.from_node(&self.node)?;
+        let result = self.evaluate_node(root)?;
+        let result = match self.arena.get(result).as_ref() {
+            Term::String(_) => result,
+            Term::Integer(_) => result,
+            _ => self.evaluate_node(result)?,
         };
-        // println!("======= evaluated node end: {} =======", result.name());
-        self.cache.insert(node.clone(), result.clone());
-        result
+        Ok(self.arena.to_node(result))
     }
 
-    fn evaluate_unary_operator(&mut self, operator: &str, operand: Node) -> Node {
-        let operand = self.evaluate_node(&operand);
-        match operator {
-            "-" => match operand {
-                Node::Integer(value) => Node::Integer(-value),
-                _ => panic!("Unsupported operand for unary operator: {:?}", operand),
-            },
-            "!" => match operand {
-                Node::Boolean(value) => Node::Boolean(!value),
-                _ => panic!("Unsupported operand for unary operator: {:?}", operand),
-            },
-            // string to int
-            "#" => match operand {
-                Node::String(value) => {
-                    let result = deconvert_string(value);
-                    let result = convert_integer_to_bigint(result);
-                    Node::Integer(result)
-                }
-                _ => panic!("Unsupported operand for unary operator: {:?}", operand),
-            },
-            // int to string
-            "$" => match operand {
-                Node::Integer(value) => {
-                    let result = deconvert_integer_from_bigint(value);
-                    let result = convert_string(result);
-                    Node::String(result)
-                }
-                _ => panic!("Unsupported operand for unary operator: {:?}", operand),
-            },
-            _ => panic!("Unsupported unary operator: {}", operator),
+    // the number of nodes evaluated, or of instructions executed by the bytecode machine
+    pub fn eval_count(&self) -> usize {
+        self.eval_count
+    }
+
+    fn count_beta_reduction(&mut self) -> Result<(), EvalError> {
+        self.beta_reductions += 1;
+        match self.beta_limit {
+            Some(limit) if self.beta_reductions > limit => {
+                Err(EvalError::BetaReductionLimitExceeded { limit })
+            }
+            _ => Ok(()),
         }
     }
 
-    fn evaluate_binary_operator(&mut self, node: Node) -> Node {
-        let (operator, left, right) = match node {
-            Node::BinaryOperator(ref operator, ref left, ref right) => (operator, left, right),
-            _ => panic!("Expected binary operator"),
-        };
-        // eprintln!("eval left before");
-        // left.edump_tree(0);
-        let left = self.evaluate_node(left);
-        // eprintln!("eval left after");
-        // left.edump_tree(0);
-        // eprintln!("eval right before");
-        // right.edump_tree(0);
-        let right = self.evaluate_node(right);
-        // eprintln!("eval right after");
-        // right.edump_tree(0);
-        // eprintln!("NODE_NAME: {}", node.name());
-        match (operator.as_str(), left, right) {
-            ("+", Node::Integer(left), Node::Integer(right)) => {
-                let result = left + right;
-                eprintln!("{}", result.to_string());
-                Node::Integer(result)
-            }
-            ("-", Node::Integer(left), Node::Integer(right)) => Node::Integer(left - right),
-            ("*", Node::Integer(left), Node::Integer(right)) => Node::Integer(left * right),
-            ("/", Node::Integer(left), Node::Integer(right)) => {
-                eprintln!("divided! / {}", right);
-                let result = left / right;
-                eprintln!("divided digit: {}", result.to_string().len());
-                Node::Integer(result)
-            }
-            ("%", Node::Integer(left), Node::Integer(right)) => Node::Integer(left % right),
-            ("<", Node::Integer(left), Node::Integer(right)) => Node::Boolean(left < right),
-            (">", Node::Integer(left), Node::Integer(right)) => Node::Boolean(left > right),
-            ("=", Node::Integer(left), Node::Integer(right)) => Node::Boolean(left == right),
-            ("=", Node::String(left), Node::String(right)) => Node::Boolean(left == right),
-            ("=", Node::Boolean(left), Node::Boolean(right)) => Node::Boolean(left == right),
-            ("|", Node::Boolean(left), Node::Boolean(right)) => Node::Boolean(left || right),
-            ("&", Node::Boolean(left), Node::Boolean(right)) => Node::Boolean(left && right),
-            (".", Node::String(left), Node::String(right)) => {
-                let result = left + &right;
-                Node::String(result)
-            }
-            ("T", Node::Integer(left), Node::String(right)) => {
-                let left_usize = left.to_string().parse::<usize>().unwrap();
-                let result = right.chars().take(left_usize).collect();
-                Node::String(result)
-            }
-            ("D", Node::Integer(left), Node::String(right)) => {
-                let left_usize = left.to_string().parse::<usize>().unwrap();
-                let result = right.chars().skip(left_usize).collect();
-                Node::String(result)
-            }
-            ("$", Node::Lambda(_, _), Node::Lambda(_, _)) => {
-                let mut node = self.apply_one_lambda(&node);
-                // println!("START IMPOOOOOOOOOOOOOOOOOOOTANT");
-                loop {
-                    // node.dump_tree(0);
-                    if !matches!(node, Node::BinaryOperator(_, _, _)) {
-                        break;
+    // evaluate with an explicit frame stack instead of native recursion, so deep terms and
+    // long chains of tail calls do not overflow; evaluated values are pushed on `values`
+    fn evaluate_node(&mut self, node: NodeId) -> Result<NodeId, EvalError> {
+        let mut frames = vec![Frame::Evaluate(node)];
+        let mut values = vec![];
+        while let Some(frame) = frames.pop() {
+            if self.arena.needs_collection() {
+                self.collect(&frame, &frames, &values);
+            }
+            match frame {
+                Frame::Evaluate(node) => {
+                    if let Some(&result) = self.cache.get(&node) {
+                        values.push(result);
+                        continue;
                     }
-                    if let Node::BinaryOperator(op, _, _) = node.clone() {
-                        if op != "$" {
-                            break;
+                    self.eval_count += 1;
+                    let term = self.arena.get(node);
+                    // a term in tail position has the value of the term it replaced, so only the
+                    // outermost one is remembered and loops do not grow the frame stack
+                    if !matches!(term.as_ref(), Term::Lambda(_, _))
+                        && !term.children().is_empty()
+                        && !matches!(frames.last(), Some(Frame::Cache(_)))
+                    {
+                        frames.push(Frame::Cache(node));
+                    }
+                    match *term {
+                        Term::Unary(operator, operand) => {
+                            frames.push(Frame::Unary(operator));
+                            frames.push(Frame::Evaluate(operand));
+                        }
+                        Term::Binary(operator, left, right) => {
+                            frames.push(Frame::Left(operator, right));
+                            frames.push(Frame::Evaluate(left));
                         }
+                        Term::If(condition, then_branch, else_branch) => {
+                            frames.push(Frame::Branch(then_branch, else_branch));
+                            frames.push(Frame::Evaluate(condition));
+                        }
+                        // literals, variables and lambdas are already values
+                        _ => values.push(node),
                     }
-                    node = self.apply_one_lambda(&node);
                 }
-                // println!("END IMPOOOOOOOOOOOOOOOOOOOTANT");
-                // node.dump_tree(0);
-                node
-            }
-            ("$", Node::Lambda(arity, body), arg) => {
-                let mut variables = HashMap::new();
-                variables.insert(arity, arg);
-                let new_body = self.replace_variable(&body, &variables);
-                self.evaluate_node(&new_body)
+                Frame::Unary(operator) => {
+                    let operand = values.pop().unwrap();
+                    values.push(self.evaluate_unary_operator(operator, operand)?);
+                }
+                // $ and ~ substitute their argument unevaluated, the other operators evaluate it
+                Frame::Left(operator @ (BinaryOp::Apply | BinaryOp::ApplyLazy), right) => {
+                    let left = values.pop().unwrap();
+                    match self.evaluate_binary_operator(operator, left, right)? {
+                        Reduction::Value(result) => values.push(result),
+                        Reduction::Evaluate(next) => frames.push(Frame::Evaluate(next)),
+                    }
+                }
+                Frame::Left(operator, right) => {
+                    frames.push(Frame::Binary(operator));
+                    frames.push(Frame::Evaluate(right));
+                }
+                Frame::Binary(operator) => {
+                    let right = values.pop().unwrap();
+                    let left = values.pop().unwrap();
+                    match self.evaluate_binary_operator(operator, left, right)? {
+                        Reduction::Value(result) => values.push(result),
+                        Reduction::Evaluate(next) => frames.push(Frame::Evaluate(next)),
+                    }
+                }
+                Frame::Branch(then_branch, else_branch) => {
+                    let condition = values.pop().unwrap();
+                    match self.arena.get(condition).as_ref() {
+                        Term::Boolean(true) => frames.push(Frame::Evaluate(then_branch)),
+                        Term::Boolean(false) => frames.push(Frame::Evaluate(else_branch)),
+                        _ => {
+                            return Err(EvalError::UnsupportedCondition {
+                                condition: self.arena.name(condition),
+                            })
+                        }
+                    }
+                }
+                Frame::Cache(node) => {
+                    self.cache.insert(node, *values.last().unwrap());
+                }
             }
-            _ => node,
         }
+        Ok(values.pop().unwrap())
+    }
+
+    // drop the terms that no frame or value refers to any more; their ids are reused, so the
+    // cache is dropped with them
+    fn collect(&mut self, frame: &Frame, frames: &[Frame], values: &[NodeId]) {
+        let roots = frames
+            .iter()
+            .chain([frame])
+            .flat_map(Frame::ids)
+            .chain(values.iter().copied());
+        self.arena.collect(roots);
+        self.cache.clear();
+    }
+
+    fn evaluate_unary_operator(
+        &mut self,
+        operator: UnaryOp,
+        operand: NodeId,
+    ) -> Result<NodeId, EvalError> {
+        let result = match (operator, self.arena.get(operand).as_ref()) {
+            (UnaryOp::Negate, Term::Integer(value)) => Term::Integer(-value),
+            (UnaryOp::Not, Term::Boolean(value)) => Term::Boolean(!*value),
+            // string to int
+            (UnaryOp::StringToInt, Term::String(value)) => {
+                let result = deconvert_string(value.clone());
+                let result = convert_integer_to_bigint(result);
+                Term::Integer(result)
+            }
+            // int to string
+            (UnaryOp::IntToString, Term::Integer(value)) => {
+                let result = deconvert_integer_from_bigint(value.clone());
+                let result = convert_string(result);
+                Term::String(result)
+            }
+            _ => {
+                return Err(EvalError::UnsupportedUnaryOperand {
+                    operator: operator.as_str().to_string(),
+                    operand: self.arena.name(operand),
+                })
+            }
+        };
+        Ok(self.arena.intern(result))
+    }
+
+    // `left` is evaluated, and so is `right` unless the operator is $ or ~
+    fn evaluate_binary_operator(
+        &mut self,
+        operator: BinaryOp,
+        left: NodeId,
+        right: NodeId,
+    ) -> Result<Reduction, EvalError> {
+        let (left_term, right_term) = (self.arena.get(left), self.arena.get(right));
+        let result = match (operator, left_term.as_ref(), right_term.as_ref()) {
+            (BinaryOp::Add, Term::Integer(left), Term::Integer(right)) => {
+                Term::Integer(left + right)
+            }
+            (BinaryOp::Subtract, Term::Integer(left), Term::Integer(right)) => {
+                Term::Integer(left - right)
+            }
+            (BinaryOp::Multiply, Term::Integer(left), Term::Integer(right)) => {
+                Term::Integer(left * right)
+            }
+            (BinaryOp::Divide | BinaryOp::Modulo, Term::Integer(_), Term::Integer(right))
+                if *right == BigInt::from(0) =>
+            {
+                return Err(EvalError::DivisionByZero {
+                    operator: operator.as_str().to_string(),
+                })
+            }
+            (BinaryOp::Divide, Term::Integer(left), Term::Integer(right)) => {
+                Term::Integer(left / right)
+            }
+            (BinaryOp::Modulo, Term::Integer(left), Term::Integer(right)) => {
+                Term::Integer(left % right)
+            }
+            (BinaryOp::LessThan, Term::Integer(left), Term::Integer(right)) => {
+                Term::Boolean(left < right)
+            }
+            (BinaryOp::GreaterThan, Term::Integer(left), Term::Integer(right)) => {
+                Term::Boolean(left > right)
+            }
+            (BinaryOp::Equal, Term::Integer(left), Term::Integer(right)) => {
+                Term::Boolean(left == right)
+            }
+            (BinaryOp::Equal, Term::String(left), Term::String(right)) => {
+                Term::Boolean(left == right)
+            }
+            (BinaryOp::Equal, Term::Boolean(left), Term::Boolean(right)) => {
+                Term::Boolean(left == right)
+            }
+            (BinaryOp::Or, Term::Boolean(left), Term::Boolean(right)) => {
+                Term::Boolean(*left || *right)
+            }
+            (BinaryOp::And, Term::Boolean(left), Term::Boolean(right)) => {
+                Term::Boolean(*left && *right)
+            }
+            (BinaryOp::Concat, Term::String(left), Term::String(right)) => {
+                Term::String(left.clone() + right)
+            }
+            (BinaryOp::Take, Term::Integer(left), Term::String(right))
+                if *left >= BigInt::from(0) =>
+            {
+                let left_usize = left.to_string().parse::<usize>().unwrap_or(usize::MAX);
+                Term::String(right.chars().take(left_usize).collect())
+            }
+            (BinaryOp::Drop, Term::Integer(left), Term::String(right))
+                if *left >= BigInt::from(0) =>
+            {
+                let left_usize = left.to_string().parse::<usize>().unwrap_or(usize::MAX);
+                Term::String(right.chars().skip(left_usize).collect())
+            }
+            (_, Term::Lambda(_, _), _) if operator.is_application() => {
+                let redex = self.arena.intern(Term::Binary(operator, left, right));
+                return Ok(Reduction::Evaluate(self.apply_one_lambda(redex)?));
+            }
+            // an application of a free variable cannot be reduced any further
+            (_, Term::Variable(_), _) if operator.is_application() => {
+                Term::Binary(operator, left, right)
+            }
+            _ => {
+                return Err(EvalError::UnsupportedBinaryOperands {
+                    operator: operator.as_str().to_string(),
+                    left: self.arena.name(left),
+                    right: self.arena.name(right),
+                })
+            }
+        };
+        Ok(Reduction::Value(self.arena.intern(result)))
     }
 
     // 1段階だけ適用する、再帰的には適用するとStack Overflowになる
-    fn apply_one_lambda(&mut self, node: &Node) -> Node {
-        // println!("======= applying one lambda =======");
-        assert!(matches!(node, Node::BinaryOperator(_, _, _)));
-        let (operator, left, right) = match node {
-            Node::BinaryOperator(operator, left, right) => (operator, left, right),
-            _ => panic!("Expected binary operator"),
+    fn apply_one_lambda(&mut self, node: NodeId) -> Result<NodeId, EvalError> {
+        let Term::Binary(operator, left, right) = *self.arena.get(node) else {
+            panic!("Expected binary operator")
         };
-        assert_eq!(operator, "$");
-        let (lambda, body, arg) = match (left.as_ref(), right.as_ref()) {
-            (Node::Lambda(arity, body), arg) => (arity, body, arg),
-            _ => panic!("The left side of $ operator must be a lambda"),
+        assert!(operator.is_application());
+        match self.arena.get(left).as_ref() {
+            Term::Lambda(arity, body) => {
+                self.count_beta_reduction()?;
+                Ok(self.arena.substitute(*body, *arity, right))
+            }
+            _ => Err(EvalError::UnsupportedBinaryOperands {
+                operator: operator.as_str().to_string(),
+                left: self.arena.name(left),
+                right: self.arena.name(right),
+            }),
+        }
+    }
+}
+
+enum Frame {
+    Evaluate(NodeId),
+    Unary(UnaryOp),
+    // the left operand is on the value stack, the right one is not evaluated yet
+    Left(BinaryOp, NodeId),
+    // both operands are on the value stack, left below right
+    Binary(BinaryOp),
+    Branch(NodeId, NodeId),
+    // remember the value on top of the stack as the value of the term
+    Cache(NodeId),
+}
+
+impl Frame {
+    // the terms this frame still refers to, which must survive a collection
+    fn ids(&self) -> Vec<NodeId> {
+        match self {
+            Frame::Evaluate(id) | Frame::Left(_, id) | Frame::Cache(id) => vec![*id],
+            Frame::Branch(then_branch, else_branch) => vec![*then_branch, *else_branch],
+            Frame::Unary(_) | Frame::Binary(_) => vec![],
+        }
+    }
+}
+
+// an operator either produces a value or a term that still has to be evaluated in its place
+enum Reduction {
+    Value(NodeId),
+    Evaluate(NodeId),
+}
+
+// reduce one operator whose operands are already values, used by the step debugger
+pub(crate) fn reduce_primitive(node: &Node) -> Result<Node, EvalError> {
+    let mut evaluator = Evaluator::new(node.clone());
+    let id = evaluator.arena.from_node(node)?;
+    let result = evaluator.evaluate_node(id)?;
+    Ok(evaluator.arena.to_node(result))
+}
+
+// $ is call-by-name, ! is call-by-value and ~ is call-by-need
+pub fn is_application(operator: &str) -> bool {
+    matches!(operator, "$" | "!" | "~")
+}
+
+// tokenize, parse and evaluate a program whose result must be a string, like a server reply
+pub fn evaluate_string(input: &str) -> Result<String, EvalError> {
+    let tokens = Tokenizer::new(input).tokenize()?;
+    let node = Parser::new(&tokens).parse()?;
+    match &Evaluator::new(node).evaluate()? {
+        Node::String(text) => Ok(text.clone()),
+        result => Err(EvalError::UnexpectedResult {
+            expected: "String".to_string(),
+            actual: result.name(),
+        }),
+    }
+}
+
+pub fn substitute(body: &Node, variable: &BigInt, value: &Node) -> Node {
+    let mut substitution = HashMap::new();
+    substitution.insert(variable.clone(), value.clone());
+    substitute_all(body, substitution)
+}
+
+// replace every free occurrence of the variables at once, renaming binders that would capture
+pub fn substitute_all(node: &Node, substitution: HashMap<BigInt, Node>) -> Node {
+    let substitution = substitution
+        .into_iter()
+        .map(|(variable, value)| {
+            let free = free_variables(&value);
+            (variable, (Rc::new(value), Rc::new(free)))
+        })
+        .collect();
+    replace_variable(node, Rc::new(substitution))
+}
+
+type Substitution = HashMap<BigInt, (Rc<Node>, Rc<HashSet<BigInt>>)>;
+
+enum Task<'n> {
+    Visit(&'n Node, Rc<Substitution>),
+    Unary(&'n str),
+    Binary(&'n str),
+    If,
+    Lambda(BigInt),
+}
+
+// capture-avoiding substitution, walking the term with an explicit stack
+fn replace_variable(node: &Node, substitution: Rc<Substitution>) -> Node {
+    let mut tasks = vec![Task::Visit(node, substitution)];
+    let mut results: Vec<Node> = vec![];
+    while let Some(task) = tasks.pop() {
+        let node = match task {
+            Task::Visit(node, substitution) if substitution.is_empty() => node.clone(),
+            Task::Visit(node, substitution) => match node {
+                Node::Variable(index) => match substitution.get(index) {
+                    Some((value, _)) => value.as_ref().clone(),
+                    None => node.clone(),
+                },
+                Node::Lambda(arity, body) => {
+                    let captures = substitution
+                        .iter()
+                        .any(|(variable, (_, free))| variable != arity && free.contains(arity));
+                    let captures = captures && {
+                        let free_in_body = free_variables(body);
+                        substitution.iter().any(|(variable, (_, free))| {
+                            variable != arity
+                                && free.contains(arity)
+                                && free_in_body.contains(variable)
+                        })
+                    };
+                    let mut binder = arity.clone();
+                    let mut inner = substitution.clone();
+                    if captures {
+                        // the binder would capture a free variable of a value, so rename it
+                        binder = substitution
+                            .iter()
+                            .map(|(variable, (value, _))| variable.clone().max(max_variable(value)))
+                            .fold(max_variable(body).max(arity.clone()), BigInt::max)
+                            + 1;
+                        let renamed = (
+                            Rc::new(Node::Variable(binder.clone())),
+                            Rc::new(HashSet::from([binder.clone()])),
+                        );
+                        Rc::make_mut(&mut inner).insert(arity.clone(), renamed);
+                    } else if substitution.contains_key(arity) {
+                        Rc::make_mut(&mut inner).remove(arity);
+                    }
+                    tasks.push(Task::Lambda(binder));
+                    tasks.push(Task::Visit(body, inner));
+                    continue;
+                }
+                Node::UnaryOperator(operator, operand) => {
+                    tasks.push(Task::Unary(operator));
+                    tasks.push(Task::Visit(operand, substitution));
+                    continue;
+                }
+                Node::BinaryOperator(operator, left, right) => {
+                    tasks.push(Task::Binary(operator));
+                    tasks.push(Task::Visit(right, substitution.clone()));
+                    tasks.push(Task::Visit(left, substitution));
+                    continue;
+                }
+                Node::If(condition, then_branch, else_branch) => {
+                    tasks.push(Task::If);
+                    tasks.push(Task::Visit(else_branch, substitution.clone()));
+                    tasks.push(Task::Visit(then_branch, substitution.clone()));
+                    tasks.push(Task::Visit(condition, substitution));
+                    continue;
+                }
+                _ => node.clone(),
+            },
+            Task::Unary(operator) => {
+                let operand = results.pop().unwrap();
+                Node::UnaryOperator(operator.to_string(), Box::new(operand))
+            }
+            Task::Binary(operator) => {
+                let right = results.pop().unwrap();
+                let left = results.pop().unwrap();
+                Node::BinaryOperator(operator.to_string(), Box::new(left), Box::new(right))
+            }
+            Task::If => {
+                let else_branch = results.pop().unwrap();
+                let then_branch = results.pop().unwrap();
+                let condition = results.pop().unwrap();
+                Node::If(
+                    Box::new(condition),
+                    Box::new(then_branch),
+                    Box::new(else_branch),
+                )
+            }
+            Task::Lambda(arity) => {
+                let body = results.pop().unwrap();
+                Node::Lambda(arity, Box::new(body))
+            }
         };
-        let mut variables = HashMap::new();
-        variables.insert(lambda.clone(), arg.clone());
-        self.replace_variable(&body, &variables)
+        results.push(node);
     }
+    results.pop().unwrap()
+}
 
-    fn replace_variable(&mut self, node: &Node, variables: &HashMap<usize, Node>) -> Node {
+pub fn free_variables(node: &Node) -> HashSet<BigInt> {
+    // None marks the end of a lambda body, where its binder goes out of scope
+    let mut stack = vec![Some(node)];
+    let mut bound = vec![];
+    let mut free = HashSet::new();
+    while let Some(node) = stack.pop() {
         match node {
-            Node::Integer(_) => node.clone(),
-            Node::String(_) => node.clone(),
-            Node::Boolean(_) => node.clone(),
-            Node::Variable(index) => match variables.get(index) {
-                Some(value) => value.clone(),
-                None => node.clone(),
-            },
-            Node::Lambda(arity, body) => {
-                let mut new_variables = variables.clone();
-                new_variables.insert(arity.clone(), Node::Variable(arity.clone()));
-                let new_body = self.replace_variable(body, &new_variables);
-                Node::Lambda(arity.clone(), Box::new(new_body))
+            None => {
+                bound.pop();
             }
-            Node::UnaryOperator(operator, operand) => {
-                let new_operand = self.replace_variable(operand, variables);
-                Node::UnaryOperator(operator.clone(), Box::new(new_operand))
+            Some(Node::Variable(index)) if !bound.contains(&index) => {
+                free.insert(index.clone());
             }
-            Node::BinaryOperator(operator, left, right) => {
-                let new_left = self.replace_variable(left, variables);
-                let new_right = self.replace_variable(right, variables);
-                Node::BinaryOperator(operator.clone(), Box::new(new_left), Box::new(new_right))
+            Some(Node::Lambda(arity, body)) => {
+                bound.push(arity);
+                stack.push(None);
+                stack.push(Some(body));
+            }
+            Some(Node::UnaryOperator(_, operand)) => stack.push(Some(operand)),
+            Some(Node::BinaryOperator(_, left, right)) => {
+                stack.push(Some(right));
+                stack.push(Some(left));
+            }
+            Some(Node::If(condition, then_branch, else_branch)) => {
+                stack.push(Some(else_branch));
+                stack.push(Some(then_branch));
+                stack.push(Some(condition));
+            }
+            _ => {}
+        }
+    }
+    free
+}
+
+// the largest variable id used anywhere in the node, bound or free
+pub fn max_variable(node: &Node) -> BigInt {
+    let mut stack = vec![node];
+    let mut max = &BigInt::from(0);
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Variable(index) => max = max.max(index),
+            Node::Lambda(arity, body) => {
+                max = max.max(arity);
+                stack.push(body);
+            }
+            Node::UnaryOperator(_, operand) => stack.push(operand),
+            Node::BinaryOperator(_, left, right) => {
+                stack.push(left);
+                stack.push(right);
             }
             Node::If(condition, then_branch, else_branch) => {
-                let new_condition = self.replace_variable(condition, variables);
-                let new_then_branch = self.replace_variable(then_branch, variables);
-                let new_else_branch = self.replace_variable(else_branch, variables);
-                Node::If(
-                    Box::new(new_condition),
-                    Box::new(new_then_branch),
-                    Box::new(new_else_branch),
-                )
+                stack.push(condition);
+                stack.push(then_branch);
+                stack.push(else_branch);
             }
-            _ => panic!("Unsupported node: {:?}", node),
+            _ => {}
         }
     }
+    max.clone()
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{
-        icfp::{parser::Parser, tokenizer::Tokenizer},
-        node,
-    };
+    use crate::node;
 
     use super::*;
 
+    impl Evaluator {
+        fn intern(&mut self, node: &Node) -> NodeId {
+            self.arena.from_node(node).unwrap()
+        }
+
+        fn node(&self, id: NodeId) -> Node {
+            self.arena.to_node(id)
+        }
+    }
+
     #[test]
     fn test_evaluate_unary_operator() {
         let mut evaluator = Evaluator::new(Node::Integer(BigInt::from(42)));
+        let mut evaluate = |operator, operand: Node| {
+            let operand = evaluator.intern(&operand);
+            let result = evaluator
+                .evaluate_unary_operator(operator, operand)
+                .unwrap();
+            evaluator.node(result)
+        };
         assert_eq!(
-            evaluator.evaluate_unary_operator("-", Node::Integer(BigInt::from(42))),
+            evaluate(UnaryOp::Negate, Node::Integer(BigInt::from(42))),
             Node::Integer(BigInt::from(-42))
         );
         assert_eq!(
-            evaluator.evaluate_unary_operator("!", Node::Boolean(true)),
+            evaluate(UnaryOp::Not, Node::Boolean(true)),
             Node::Boolean(false)
         );
-        // assert_eq!(
-        //     evaluator.evaluate_unary_operator("#", Node::String("4%34".to_string())),
-        //     Node::Integer(BigInt::from(15818151))
-        // );
         assert_eq!(
-            evaluator.evaluate_unary_operator("$", Node::Integer(BigInt::from(15818151))),
+            evaluate(UnaryOp::StringToInt, Node::String("test".to_string())),
+            Node::Integer(BigInt::from(15818151))
+        );
+        assert_eq!(
+            evaluate(UnaryOp::IntToString, Node::Integer(BigInt::from(15818151))),
             Node::String("test".to_string())
         );
     }
@@ -349,11 +741,15 @@ mod tests {
         ];
         for (operator, left, right, expected) in cases {
             assert_eq!(
-                evaluator.evaluate_binary_operator(Node::BinaryOperator(
-                    operator.to_string(),
-                    Box::new(left),
-                    Box::new(right)
-                )),
+                {
+                    let node = evaluator.intern(&Node::BinaryOperator(
+                        operator.to_string(),
+                        Box::new(left),
+                        Box::new(right),
+                    ));
+                    let result = evaluator.evaluate_node(node).unwrap();
+                    evaluator.node(result)
+                },
                 expected
             );
         }
@@ -362,7 +758,7 @@ mod tests {
     #[test]
     fn test_evaluate_lambda() {
         let mut evaluator = Evaluator::new(Node::Lambda(
-            1,
+            BigInt::from(1),
             Box::new(Node::BinaryOperator(
                 "+".to_string(),
                 Box::new(Node::Integer(BigInt::from(1))),
@@ -370,9 +766,9 @@ mod tests {
             )),
         ));
         assert_eq!(
-            evaluator.evaluate(),
+            evaluator.evaluate().unwrap(),
             Node::Lambda(
-                1,
+                BigInt::from(1),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
                     Box::new(Node::Integer(BigInt::from(1))),
@@ -382,21 +778,21 @@ mod tests {
         );
 
         let mut evaluator = Evaluator::new(Node::Lambda(
-            1,
+            BigInt::from(1),
             Box::new(Node::BinaryOperator(
                 "+".to_string(),
                 Box::new(Node::Integer(BigInt::from(1))),
-                Box::new(Node::Variable(2)),
+                Box::new(Node::Variable(BigInt::from(2))),
             )),
         ));
         assert_eq!(
-            evaluator.evaluate(),
+            evaluator.evaluate().unwrap(),
             Node::Lambda(
-                1,
+                BigInt::from(1),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
                     Box::new(Node::Integer(BigInt::from(1))),
-                    Box::new(Node::Variable(2))
+                    Box::new(Node::Variable(BigInt::from(2)))
                 ))
             )
         );
@@ -404,29 +800,32 @@ mod tests {
         let mut evaluator = Evaluator::new(Node::BinaryOperator(
             "$".to_string(),
             Box::new(Node::Lambda(
-                1,
+                BigInt::from(1),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
                     Box::new(Node::Integer(BigInt::from(1))),
-                    Box::new(Node::Variable(1)),
+                    Box::new(Node::Variable(BigInt::from(1))),
                 )),
             )),
             Box::new(Node::Integer(BigInt::from(42))),
         ));
-        assert_eq!(evaluator.evaluate(), Node::Integer(BigInt::from(43)));
+        assert_eq!(
+            evaluator.evaluate().unwrap(),
+            Node::Integer(BigInt::from(43))
+        );
 
         let mut evaluator = Evaluator::new(Node::BinaryOperator(
             "$".to_string(),
             Box::new(Node::BinaryOperator(
                 "$".to_string(),
                 Box::new(Node::Lambda(
-                    2,
+                    BigInt::from(2),
                     Box::new(Node::Lambda(
-                        1,
+                        BigInt::from(1),
                         Box::new(Node::BinaryOperator(
                             "+".to_string(),
-                            Box::new(Node::Variable(1)),
-                            Box::new(Node::Variable(2)),
+                            Box::new(Node::Variable(BigInt::from(1))),
+                            Box::new(Node::Variable(BigInt::from(2))),
                         )),
                     )),
                 )),
@@ -434,18 +833,21 @@ mod tests {
             )),
             Box::new(Node::Integer(BigInt::from(43))),
         ));
-        assert_eq!(evaluator.evaluate(), Node::Integer(BigInt::from(85)));
+        assert_eq!(
+            evaluator.evaluate().unwrap(),
+            Node::Integer(BigInt::from(85))
+        );
     }
 
     #[test]
     fn test_evaluate() {
         let mut tokenizer = Tokenizer::new("B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK");
-        let tokens = tokenizer.tokenize();
+        let tokens = tokenizer.tokenize().unwrap();
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse();
+        let node = parser.parse().unwrap();
         let mut evaluator = Evaluator::new(node);
         assert_eq!(
-            evaluator.evaluate(),
+            evaluator.evaluate().unwrap(),
             Node::String("Hello World!".to_string())
         );
     }
@@ -453,35 +855,37 @@ mod tests {
     #[test]
     fn test_apply_one_lambda() {
         let mut evaluator = Evaluator::new(Node::String("test".to_string()));
-        let result = evaluator.apply_one_lambda(&Node::BinaryOperator(
+        let node = evaluator.intern(&Node::BinaryOperator(
             "$".to_string(),
             node!(Node::Lambda(
-                1,
+                BigInt::from(1),
                 node!(Node::BinaryOperator(
                     "$".to_string(),
-                    node!(Node::Variable(1)),
+                    node!(Node::Variable(BigInt::from(1))),
                     node!(Node::Integer(BigInt::from(1))),
                 )),
             )),
             node!(Node::Lambda(
-                1,
+                BigInt::from(1),
                 node!(Node::BinaryOperator(
                     "$".to_string(),
-                    node!(Node::Variable(1)),
+                    node!(Node::Variable(BigInt::from(1))),
                     node!(Node::Integer(BigInt::from(2))),
                 )),
             )),
         ));
+        let result = evaluator.apply_one_lambda(node).unwrap();
+        let result = evaluator.node(result);
 
         assert_eq!(
             result,
             Node::BinaryOperator(
                 "$".to_string(),
                 node!(Node::Lambda(
-                    1,
+                    BigInt::from(1),
                     node!(Node::BinaryOperator(
                         "$".to_string(),
-                        node!(Node::Variable(1)),
+                        node!(Node::Variable(BigInt::from(1))),
                         node!(Node::Integer(BigInt::from(2))),
                     )),
                 )),
@@ -495,55 +899,55 @@ mod tests {
         let node = Node::BinaryOperator(
             "$".to_string(),
             node!(Node::Lambda(
-                1,
+                BigInt::from(1),
                 node!(Node::BinaryOperator(
                     "$".to_string(),
                     node!(Node::Lambda(
-                        2,
+                        BigInt::from(2),
                         node!(Node::BinaryOperator(
                             "$".to_string(),
-                            node!(Node::Variable(1)),
+                            node!(Node::Variable(BigInt::from(1))),
                             node!(Node::BinaryOperator(
                                 "$".to_string(),
-                                node!(Node::Variable(2)),
-                                node!(Node::Variable(2)),
+                                node!(Node::Variable(BigInt::from(2))),
+                                node!(Node::Variable(BigInt::from(2))),
                             )),
                         )),
                     )),
                     node!(Node::Lambda(
-                        2,
+                        BigInt::from(2),
                         node!(Node::BinaryOperator(
                             "$".to_string(),
-                            node!(Node::Variable(1)),
+                            node!(Node::Variable(BigInt::from(1))),
                             node!(Node::BinaryOperator(
                                 "$".to_string(),
-                                node!(Node::Variable(2)),
-                                node!(Node::Variable(2)),
+                                node!(Node::Variable(BigInt::from(2))),
+                                node!(Node::Variable(BigInt::from(2))),
                             )),
                         )),
                     )),
                 )),
             )),
             node!(Node::Lambda(
-                3,
+                BigInt::from(3),
                 node!(Node::Lambda(
-                    2,
+                    BigInt::from(2),
                     node!(Node::If(
                         node!(Node::BinaryOperator(
                             "=".to_string(),
-                            node!(Node::Variable(2)),
+                            node!(Node::Variable(BigInt::from(2))),
                             node!(Node::Integer(BigInt::from(1))),
                         )),
-                        node!(Node::Variable(1)),
+                        node!(Node::Variable(BigInt::from(1))),
                         node!(Node::BinaryOperator(
                             ".".to_string(),
-                            node!(Node::Variable(1)),
+                            node!(Node::Variable(BigInt::from(1))),
                             node!(Node::BinaryOperator(
                                 "$".to_string(),
-                                node!(Node::Variable(3)),
+                                node!(Node::Variable(BigInt::from(3))),
                                 node!(Node::BinaryOperator(
                                     "-".to_string(),
-                                    node!(Node::Variable(2)),
+                                    node!(Node::Variable(BigInt::from(2))),
                                     node!(Node::Integer(BigInt::from(1))),
                                 )),
                             )),
@@ -553,7 +957,266 @@ mod tests {
             )),
         );
         let mut evaluator = Evaluator::new(Node::String("test".to_string()));
-        let result = evaluator.apply_one_lambda(&node);
-        result.dump_tree(0);
+        let node = evaluator.intern(&node);
+        let result = evaluator.apply_one_lambda(node).unwrap();
+        let result = evaluator.node(result);
+        // one step substitutes the function into both halves of the fixed-point combinator
+        let function = "L$ L# ? B= v# I\" v\" B. v\" B$ v$ B- v# I\"";
+        let half = format!("L# B$ {} B$ v# v#", function);
+        assert_eq!(result.to_string(), format!("B$ {} {}", half, half));
+        assert_eq!(evaluator.beta_reductions(), 1);
+    }
+
+    #[test]
+    fn test_evaluate_deep_programs() {
+        // a 200000 deep chain of additions
+        let input = format!("{}I\"", "B+ I\" ".repeat(200_000));
+        let mut evaluator = Evaluator::new(parse(&input));
+        assert_eq!(
+            evaluator.evaluate(),
+            Ok(Node::Integer(BigInt::from(200_001)))
+        );
+        // a self-applied loop counting down 100000 times in tail position, with a strict counter
+        let count = Node::Integer(BigInt::from(100_000)).to_string();
+        let input = format!(
+            "B$ B$ L# B$ v# v# L# L$ ? B= v$ I! S B! B$ v# v# B- v$ I\" {}",
+            count
+        );
+        let mut evaluator = Evaluator::new(parse(&input));
+        assert_eq!(evaluator.evaluate(), Ok(Node::String(String::new())));
+        // the terms of finished iterations are collected, so the arena stays small
+        let mut evaluator = Evaluator::new(parse(&input));
+        evaluator.arena = Arena::with_collection_threshold(1 << 16);
+        assert_eq!(evaluator.evaluate(), Ok(Node::String(String::new())));
+        assert!(evaluator.arena.len() < 10_000);
+    }
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_substitute_avoids_capture() {
+        // (λ1. v2)[v2 := v1] must not become λ1. v1
+        let result = substitute(
+            &parse("L\" v#"),
+            &BigInt::from(2),
+            &Node::Variable(BigInt::from(1)),
+        );
+        assert_eq!(result, parse("L$ v\""));
+
+        // shadowed binders are left untouched
+        let result = substitute(
+            &parse("L# v#"),
+            &BigInt::from(2),
+            &Node::Integer(BigInt::from(1)),
+        );
+        assert_eq!(result, parse("L# v#"));
+
+        // both nested binders clash with the free variables of the argument
+        let result = substitute(
+            &parse("L\" L$ B+ v\" v#"),
+            &BigInt::from(2),
+            &parse("B+ v\" v$"),
+        );
+        assert_eq!(result, parse("L% L& B+ v% B+ v\" v$"));
+
+        // renaming must not clash with variables that are free in the body
+        let result = substitute(&parse("L\" B+ v\" v$"), &BigInt::from(3), &parse("v\""));
+        assert_eq!(result, parse("L% B+ v% v\""));
+    }
+
+    #[test]
+    fn test_evaluate_capture_prone() {
+        let cases = vec![
+            // ((λ2. λ1. v2) v1) 2 is the free variable v1, not 2
+            ("B$ B$ L# L\" v# v\" I#", Node::Variable(BigInt::from(1))),
+            // the unused free argument from the language notes
+            (
+                "B$ L# B$ L\" B+ v\" v\" B* I$ I# v8",
+                Node::Integer(BigInt::from(12)),
+            ),
+            // (λ2. λ1. v2) v1 reads back as a renamed lambda
+            ("B$ L# L\" v# v\"", parse("L$ v\"")),
+            // K combinator applied through a binder of the same name
+            (
+                "B$ B$ L\" L# v\" B$ L# v# I$ I#",
+                Node::Integer(BigInt::from(3)),
+            ),
+            // the inner binder shadows the outer v1 only inside its own body
+            (
+                "B$ B$ L\" L# B$ L\" B- v\" v# v\" I% I#",
+                Node::Integer(BigInt::from(2)),
+            ),
+        ];
+        for (input, expected) in cases {
+            for strategy in [Strategy::Substitution, Strategy::CallByNeed] {
+                let mut evaluator = Evaluator::with_strategy(parse(input), strategy);
+                assert_eq!(
+                    evaluator.evaluate().unwrap(),
+                    expected,
+                    "{} ({:?})",
+                    input,
+                    strategy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate_errors() {
+        let cases = vec![
+            (
+                "B+ I\" S4%",
+                EvalError::UnsupportedBinaryOperands {
+                    operator: "+".to_string(),
+                    left: "Integer".to_string(),
+                    right: "String".to_string(),
+                },
+            ),
+            (
+                "B/ I\" I!",
+                EvalError::DivisionByZero {
+                    operator: "/".to_string(),
+                },
+            ),
+            (
+                "? I\" T F",
+                EvalError::UnsupportedCondition {
+                    condition: "Integer".to_string(),
+                },
+            ),
+            (
+                "U? I\"",
+                EvalError::UnknownUnaryOperator {
+                    operator: "?".to_string(),
+                },
+            ),
+        ];
+        for (input, expected) in cases {
+            for strategy in [Strategy::Substitution, Strategy::CallByNeed] {
+                let mut evaluator = Evaluator::with_strategy(parse(input), strategy);
+                assert_eq!(evaluator.evaluate(), Err(expected.clone()), "{}", input);
+            }
+        }
+
+        assert_eq!(
+            evaluate_string("B+ I\" I\""),
+            Err(EvalError::UnexpectedResult {
+                expected: "String".to_string(),
+                actual: "Integer".to_string(),
+            })
+        );
+        assert_eq!(evaluate_string("B. S4% S34"), Ok("test".to_string()));
+    }
+
+    #[test]
+    fn test_beta_reduction_limit() {
+        // the example from the language notes takes 109 beta reductions on the official evaluator
+        let input = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%";
+        let mut evaluator = Evaluator::with_strategy(parse(input), Strategy::CallByName);
+        assert_eq!(evaluator.evaluate(), Ok(Node::Integer(BigInt::from(16))));
+        assert_eq!(evaluator.beta_reductions(), 109);
+
+        let mut evaluator =
+            Evaluator::with_strategy(parse(input), Strategy::CallByName).with_beta_limit(109);
+        assert_eq!(evaluator.evaluate(), Ok(Node::Integer(BigInt::from(16))));
+
+        for strategy in [
+            Strategy::Substitution,
+            Strategy::CallByNeed,
+            Strategy::CallByName,
+        ] {
+            let mut evaluator =
+                Evaluator::with_strategy(parse(input), strategy).with_beta_limit(10);
+            assert_eq!(
+                evaluator.evaluate(),
+                Err(EvalError::BetaReductionLimitExceeded { limit: 10 })
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_application_operators() {
+        let cases = vec![
+            ("B! L# B+ v# v# B* I$ I#", Node::Integer(BigInt::from(12))),
+            ("B~ L# B+ v# v# B* I$ I#", Node::Integer(BigInt::from(12))),
+            (
+                "B~ B! L# L$ B. v# v$ S4% S34",
+                Node::String("test".to_string()),
+            ),
+        ];
+        for (input, expected) in cases {
+            for strategy in [
+                Strategy::Substitution,
+                Strategy::CallByNeed,
+                Strategy::CallByName,
+            ] {
+                let mut evaluator = Evaluator::with_strategy(parse(input), strategy);
+                assert_eq!(evaluator.evaluate(), Ok(expected.clone()), "{}", input);
+            }
+            assert_eq!(parse(input).to_string(), input);
+        }
+
+        // the argument is used twice: call-by-name reduces it twice, ! and ~ only once
+        let cases = vec![
+            ("B$ L# B+ v# v# B$ L\" v\" I#", 3),
+            ("B! L# B+ v# v# B$ L\" v\" I#", 2),
+            ("B~ L# B+ v# v# B$ L\" v\" I#", 2),
+        ];
+        for (input, expected) in cases {
+            let mut evaluator = Evaluator::with_strategy(parse(input), Strategy::CallByName);
+            assert_eq!(evaluator.evaluate(), Ok(Node::Integer(BigInt::from(4))));
+            assert_eq!(evaluator.beta_reductions(), expected, "{}", input);
+        }
+
+        // only strict application evaluates an unused argument
+        for strategy in [Strategy::Substitution, Strategy::CallByName] {
+            for input in ["B$ L# I\" B/ I\" I!", "B~ L# I\" B/ I\" I!"] {
+                let mut evaluator = Evaluator::with_strategy(parse(input), strategy);
+                assert_eq!(evaluator.evaluate(), Ok(Node::Integer(BigInt::from(1))));
+            }
+            let input = "B! L# I\" B/ I\" I!";
+            let mut evaluator = Evaluator::with_strategy(parse(input), strategy);
+            assert_eq!(
+                evaluator.evaluate(),
+                Err(EvalError::DivisionByZero {
+                    operator: "/".to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_evaluate_long_variable_ids() {
+        // twenty base-94 digits do not fit in 64 bits
+        let x = "~".repeat(20);
+        let y = format!("{}!", "~".repeat(19));
+        let cases = vec![
+            (
+                format!("B$ B$ L{x} L{y} B- v{x} v{y} I( I#"),
+                "I&".to_string(),
+            ),
+            (format!("B$ L{x} L{y} v{x} I#"), format!("L{y} I#")),
+            // the binder is renamed past the largest id instead of capturing v{x}
+            (
+                format!("B$ L\" L{x} v\" v{x}"),
+                format!("L\"{} v{x}", "!".repeat(20)),
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(parse(&input).to_string(), input);
+            for strategy in [
+                Strategy::Substitution,
+                Strategy::CallByNeed,
+                Strategy::CallByName,
+                Strategy::Bytecode,
+            ] {
+                let mut evaluator = Evaluator::with_strategy(parse(&input), strategy);
+                let result = evaluator.evaluate().unwrap();
+                assert_eq!(result.to_string(), expected, "{} ({:?})", input, strategy);
+            }
+        }
     }
 }
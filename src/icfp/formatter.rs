@@ -0,0 +1,273 @@
+use super::parser::Node;
+use super::tokenizer::Token;
+
+// in compact mode, subtrees that fit in this many columns stay on one line, comments aside
+pub const WIDTH: usize = 80;
+const INDENT: usize = 2;
+// deeper levels stay at this column, so the output of deeply nested programs stays linear
+const MAX_INDENT: usize = WIDTH / 2;
+
+// lays a program out as ICFP source: an operator whose operands are not all leaves goes on
+// its own line with the operands indented below it, lambdas share the line with their body.
+// Line breaks, indentation and comments are not ICFP, so formatted source goes through
+// `strip_comments` before it is tokenized or submitted; that gives back the same node.
+#[derive(Default)]
+pub struct Formatter {
+    compact: bool,
+    // end each line with a `;` comment holding the decoded strings and integers on it
+    comments: bool,
+}
+
+struct Line {
+    indent: usize,
+    tokens: Vec<Token>,
+}
+
+impl Formatter {
+    pub fn new() -> Formatter {
+        Formatter::default()
+    }
+
+    pub fn compact(mut self) -> Formatter {
+        self.compact = true;
+        self
+    }
+
+    pub fn with_comments(mut self) -> Formatter {
+        self.comments = true;
+        self
+    }
+
+    pub fn format(&self, node: &Node) -> String {
+        self.layout(node)
+            .iter()
+            .map(|line| self.render(line))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    // lines in prefix order, with a work stack of the nodes still to lay out and their indent
+    fn layout(&self, node: &Node) -> Vec<Line> {
+        let mut lines = vec![];
+        let mut stack = vec![(node, 0)];
+        while let Some((mut node, indent)) = stack.pop() {
+            let mut tokens = vec![];
+            while let Node::Lambda(variable, body) = node {
+                tokens.push(Token::Lambda(variable.clone()));
+                node = body;
+            }
+            let children = node.children();
+            let inline = if self.compact {
+                let used = indent + width(&tokens);
+                flatten(node, WIDTH.saturating_sub(used))
+            } else if children.iter().all(|child| is_leaf(child)) {
+                flatten(node, usize::MAX)
+            } else {
+                None
+            };
+            match inline {
+                Some(inline) => {
+                    tokens.extend(inline);
+                    lines.push(Line { indent, tokens });
+                }
+                None => {
+                    tokens.push(head(node));
+                    lines.push(Line { indent, tokens });
+                    stack.extend(
+                        children
+                            .into_iter()
+                            .rev()
+                            .map(|child| (child, (indent + INDENT).min(MAX_INDENT))),
+                    );
+                }
+            }
+        }
+        lines
+    }
+
+    fn render(&self, line: &Line) -> String {
+        let mut result = " ".repeat(line.indent);
+        let tokens: Vec<String> = line.tokens.iter().map(|token| token.to_string()).collect();
+        result.push_str(&tokens.join(" "));
+        if self.comments {
+            let values: Vec<String> = line
+                .tokens
+                .iter()
+                .filter_map(|token| match token {
+                    Token::Integer(value) => Some(value.to_string()),
+                    Token::String(value) => Some(format!("{:?}", value)),
+                    _ => None,
+                })
+                .collect();
+            if !values.is_empty() {
+                result.push_str(" ; ");
+                result.push_str(&values.join(" "));
+            }
+        }
+        result
+    }
+}
+
+// the plain one-line ICFP source of formatted output: comments, which start at a word
+// beginning with `;`, run to the end of their line and are dropped, and lines are joined
+pub fn strip_comments(source: &str) -> String {
+    source
+        .lines()
+        .flat_map(|line| {
+            line.split_whitespace()
+                .take_while(|word| !word.starts_with(';'))
+        })
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn is_leaf(node: &Node) -> bool {
+    node.children().is_empty()
+}
+
+fn head(node: &Node) -> Token {
+    match node {
+        Node::Integer(value) => Token::Integer(value.clone()),
+        Node::String(value) => Token::String(value.clone()),
+        Node::Boolean(value) => Token::Boolean(*value),
+        Node::Variable(value) => Token::Variable(value.clone()),
+        Node::UnaryOperator(operator, _) => Token::UnaryOperator(operator.clone()),
+        Node::BinaryOperator(operator, _, _) => Token::BinaryOperator(operator.clone()),
+        Node::If(_, _, _) => Token::If,
+        Node::Lambda(variable, _) => Token::Lambda(variable.clone()),
+    }
+}
+
+// columns taken by tokens separated by spaces
+fn width(tokens: &[Token]) -> usize {
+    tokens
+        .iter()
+        .map(|token| token.to_string().len() + 1)
+        .sum::<usize>()
+}
+
+// the tokens of a node in prefix order, None once they take more than `limit` columns
+fn flatten(node: &Node, limit: usize) -> Option<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut used = 0;
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+        let token = head(node);
+        used += token.to_string().len() + (!tokens.is_empty()) as usize;
+        if used > limit {
+            return None;
+        }
+        tokens.push(token);
+        stack.extend(node.children().into_iter().rev());
+    }
+    Some(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    // the example from the language notes, a Y combinator computing 16
+    const Y_COMBINATOR: &str = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%";
+
+    #[test]
+    fn test_format() {
+        let expected = "\
+B$
+  B$
+    L\" B$
+      L# B$
+        v\"
+        B$ v# v#
+      L# B$
+        v\"
+        B$ v# v#
+    L\" L# ?
+      B= v# I!
+      I\"
+      B$
+        L$ B+
+          B$ v\" v$
+          B$ v\" v$
+        B- v# I\"
+  I%";
+        assert_eq!(Formatter::new().format(&parse(Y_COMBINATOR)), expected);
+    }
+
+    #[test]
+    fn test_format_compact() {
+        let expected = "\
+B$
+  B$
+    L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#
+    L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\"
+  I%";
+        let node = parse(Y_COMBINATOR);
+        assert_eq!(Formatter::new().compact().format(&node), expected);
+        let short = parse("B. S(%,,/ U$ I/6");
+        assert_eq!(
+            Formatter::new().compact().format(&short),
+            "B. S(%,,/ U$ I/6"
+        );
+    }
+
+    #[test]
+    fn test_format_comments() {
+        let node = parse("B. S(%,,/ U$ B+ I/6 I\"");
+        let expected = "\
+B.
+  S(%,,/ ; \"hello\"
+  U$
+    B+ I/6 I\" ; 1337 1";
+        assert_eq!(Formatter::new().with_comments().format(&node), expected);
+        assert_eq!(
+            Formatter::new().compact().with_comments().format(&node),
+            "B. S(%,,/ U$ B+ I/6 I\" ; \"hello\" 1337 1"
+        );
+    }
+
+    #[test]
+    fn test_format_deep() {
+        let input = format!("{}S#", "B. S# ".repeat(200_000));
+        let node = parse(&input);
+        let formatted = Formatter::new().compact().format(&node);
+        // the indentation stops growing, so lines stay within the width
+        assert!(formatted.lines().all(|line| line.len() <= WIDTH));
+        // the derived comparison of nodes recurses, their source does not
+        assert_eq!(strip_comments(&formatted), input);
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let inputs = [
+            Y_COMBINATOR,
+            "B$ L! B$ v! B$ v! B$ v! B$ v! I\" L! B+ B+ v! v! B+ v! v!",
+            "? B> I# I$ S; U- I#",
+            "B. SF B. S; B. S\\ S;",
+            "L! L\" L# ? T v! B~ v\" B! v# F",
+        ];
+        let formatters = [
+            Formatter::new(),
+            Formatter::new().compact(),
+            Formatter::new().with_comments(),
+            Formatter::new().compact().with_comments(),
+        ];
+        for input in inputs {
+            let node = parse(input);
+            for formatter in &formatters {
+                let formatted = formatter.format(&node);
+                let source = strip_comments(&formatted);
+                assert_eq!(source, node.to_string(), "{}", formatted);
+                let strict = Tokenizer::strict(&source).tokenize().unwrap();
+                assert_eq!(Parser::new(&strict).parse().unwrap(), node);
+            }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use num_bigint::BigInt;
+
+use super::builder::{
+    app, app_by_value, concat, div, drop, if_, int, int_to_string, lam, lt, rem, self_apply,
+    string, sub, take, Term,
+};
+use super::compressor::{runs, verify, Compressor, Encoding};
+use super::error::EvalError;
+use super::optimizer::{renumber, size};
+use super::parser::Node;
+use super::util::{BASE94, STRING_ASCII};
+
+// the digit of each move in the packed integer
+const MOVES: &str = "UDLR";
+
+// "solve lambdamanN PATH" as the problem number and the path
+pub fn parse_solution(text: &str) -> Option<(usize, &str)> {
+    let rest = text.trim().strip_prefix("solve lambdaman")?;
+    let (problem, path) = rest.split_once(' ')?;
+    Some((problem.parse().ok()?, path))
+}
+
+// the shortest program found that evaluates to the solution of the problem: the moves
+// packed into one integer, runs of moves packed into one integer, or the run-length
+// forms of the compressor; candidates are checked with the evaluator, shortest first
+pub fn encode(problem: usize, path: &str) -> Result<Node, EvalError> {
+    if let Some((position, character)) = path
+        .char_indices()
+        .find(|&(_, character)| !MOVES.contains(character))
+    {
+        return Err(EvalError::InvalidCharacter {
+            character,
+            position,
+        });
+    }
+    let prefix = format!("solve lambdaman{} ", problem);
+    let text = format!("{}{}", prefix, path);
+    // verified by the compressor, and the literal text if nothing is shorter
+    let compressed = Compressor::new()
+        .with_encodings(&[Encoding::RunLength])
+        .compress(&text)?;
+    let compressed_size = size(&compressed);
+    let mut candidates = vec![];
+    if !path.is_empty() {
+        for term in [packed(&prefix, path), packed_runs(&prefix, path)] {
+            let candidate = renumber(&term.build());
+            candidates.push((size(&candidate), candidate));
+        }
+    }
+    candidates.sort_by_key(|&(length, _)| length);
+    // only the shortest packed program is verified, unless it fails
+    Ok(candidates
+        .into_iter()
+        .take_while(|&(length, _)| length < compressed_size)
+        .map(|(_, candidate)| candidate)
+        .find(|candidate| verify(candidate, &text))
+        .unwrap_or(compressed))
+}
+
+fn digit(c: char) -> usize {
+    MOVES.find(c).unwrap()
+}
+
+fn move_of(digit: Term) -> Term {
+    take(int(1), drop(digit, string(MOVES)))
+}
+
+// the moves are the base-4 digits of one integer under a leading 1, the decoder strips
+// the last digit, recurses on the rest and appends the move, ending with the prefix
+fn packed(prefix: &str, path: &str) -> Term {
+    let mut value = BigInt::from(1);
+    for c in path.chars() {
+        value = value * 4 + digit(c);
+    }
+    let decode = self_apply(|decode| {
+        lam(|n| {
+            if_(
+                lt(n.clone(), int(4)),
+                string(prefix),
+                concat(
+                    app_by_value(decode, div(n.clone(), int(4))),
+                    move_of(rem(n, int(4))),
+                ),
+            )
+        })
+    });
+    app(decode, int(value))
+}
+
+// every run is the digit 94 * count + character in base 94 * (longest run + 1), the
+// decoder expands a digit with a second loop that prints the character with U$
+fn packed_runs(prefix: &str, path: &str) -> Term {
+    let runs = runs(path);
+    let base = BASE94 * (runs.iter().map(|&(_, count)| count).max().unwrap() + 1);
+    let mut value = BigInt::from(1);
+    for &(c, count) in &runs {
+        value = value * base + BASE94 * count + STRING_ASCII.find(c).unwrap();
+    }
+    let expand = self_apply(|expand| {
+        lam(|m| {
+            if_(
+                lt(m.clone(), int(BASE94)),
+                string(""),
+                concat(
+                    int_to_string(rem(m.clone(), int(BASE94))),
+                    app_by_value(expand, sub(m, int(BASE94))),
+                ),
+            )
+        })
+    });
+    let decode = lam(|expand| {
+        self_apply(|decode| {
+            lam(|n| {
+                if_(
+                    lt(n.clone(), int(base)),
+                    string(prefix),
+                    concat(
+                        app_by_value(decode, div(n.clone(), int(base))),
+                        app(expand, rem(n, int(base))),
+                    ),
+                )
+            })
+        })
+    });
+    app(app(decode, expand), int(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icfp::evaluator::{Evaluator, Strategy};
+
+    fn evaluate(node: &Node) -> Node {
+        Evaluator::with_strategy(node.clone(), Strategy::CallByName)
+            .evaluate()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_solution() {
+        assert_eq!(
+            parse_solution("solve lambdaman12 UDLR\n"),
+            Some((12, "UDLR"))
+        );
+        assert_eq!(parse_solution("solve spaceship1 1"), None);
+    }
+
+    #[test]
+    fn test_decoders() {
+        let paths = [
+            "U",
+            "RDLU",
+            "UUUUDDLRRRRRRRLLLU",
+            "DDDDDDDDDDDDDDDDDDDDDDDDD",
+        ];
+        for path in paths {
+            let expected = Node::String(format!("solve lambdaman3 {}", path));
+            let node = packed("solve lambdaman3 ", path).build();
+            assert_eq!(evaluate(&node), expected);
+            let node = packed_runs("solve lambdaman3 ", path).build();
+            assert_eq!(evaluate(&node), expected);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut state: u64 = 7;
+        let path: String = (0..2000)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                MOVES.chars().nth((state >> 62) as usize).unwrap()
+            })
+            .collect();
+        let node = encode(5, &path).unwrap();
+        assert_eq!(
+            evaluate(&node),
+            Node::String(format!("solve lambdaman5 {}", path))
+        );
+        // two bits per move in base 94 plus the decoder
+        assert!(size(&node) < 2000 / 3 + 100, "{}", size(&node));
+
+        let path = format!("{}{}{}", "R".repeat(500), "D".repeat(499), "L".repeat(500));
+        let node = encode(8, &path).unwrap();
+        assert_eq!(
+            evaluate(&node),
+            Node::String(format!("solve lambdaman8 {}", path))
+        );
+        assert!(size(&node) < 170, "{}", node.to_string());
+
+        // many long runs, where packing the runs beats packing the moves
+        let path: String = (0..200)
+            .map(|i| {
+                let c = MOVES.chars().nth(i % 4).unwrap();
+                c.to_string().repeat(10 + i * 7 % 23)
+            })
+            .collect();
+        let node = encode(9, &path).unwrap();
+        assert_eq!(
+            evaluate(&node),
+            Node::String(format!("solve lambdaman9 {}", path))
+        );
+        assert!(size(&node) < size(&packed("solve lambdaman9 ", &path).build()));
+
+        assert_eq!(
+            encode(1, "").unwrap(),
+            Node::String("solve lambdaman1 ".to_string())
+        );
+        assert!(encode(1, "UDX").is_err());
+    }
+}
@@ -0,0 +1,632 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use super::error::EvalError;
+use super::evaluator::{free_variables, is_application, substitute_all};
+use super::parser::Node;
+use super::profiler::{Profile, Profiler};
+use super::util::{
+    convert_integer_to_bigint, convert_string, deconvert_integer_from_bigint, deconvert_string,
+};
+
+// call-by-need evaluator: lambdas become closures over an environment and
+// arguments are bound as shared thunks that are evaluated at most once
+#[derive(Clone)]
+enum Value<'a> {
+    Integer(BigInt),
+    Boolean(bool),
+    String(String),
+    Closure(&'a BigInt, &'a Node, Env<'a>),
+    // a variable that is not bound anywhere in the program
+    Variable(BigInt),
+}
+
+impl Value<'_> {
+    fn name(&self) -> String {
+        match self {
+            Value::Integer(_) => "Integer".to_string(),
+            Value::Boolean(_) => "Boolean".to_string(),
+            Value::String(_) => "String".to_string(),
+            Value::Closure(arity, _, _) => format!("Lambda({})", arity),
+            Value::Variable(_) => "Variable".to_string(),
+        }
+    }
+}
+
+enum ThunkState<'a> {
+    // the flag marks thunks of ~ that are shared even when evaluating call-by-name
+    Delayed(&'a Node, Env<'a>, bool),
+    Evaluating,
+    Forced(Value<'a>),
+}
+
+type Thunk<'a> = Rc<RefCell<ThunkState<'a>>>;
+
+struct Binding<'a> {
+    variable: &'a BigInt,
+    thunk: Thunk<'a>,
+    next: Env<'a>,
+}
+
+#[derive(Clone, Default)]
+struct Env<'a>(Option<Rc<Binding<'a>>>);
+
+impl<'a> Env<'a> {
+    fn bind(&self, variable: &'a BigInt, thunk: Thunk<'a>) -> Env<'a> {
+        Env(Some(Rc::new(Binding {
+            variable,
+            thunk,
+            next: self.clone(),
+        })))
+    }
+
+    fn lookup(&self, variable: &BigInt) -> Option<&Thunk<'a>> {
+        let mut env = self;
+        while let Some(binding) = &env.0 {
+            if binding.variable == variable {
+                return Some(&binding.thunk);
+            }
+            env = &binding.next;
+        }
+        None
+    }
+}
+
+// walk the chain with a work stack, a recursive drop overflows on long environments
+impl Drop for Env<'_> {
+    fn drop(&mut self) {
+        let mut stack = vec![self.0.take()];
+        while let Some(env) = stack.pop() {
+            let Some(binding) = env.and_then(|binding| Rc::try_unwrap(binding).ok()) else {
+                continue;
+            };
+            let Binding {
+                thunk, mut next, ..
+            } = binding;
+            stack.push(next.0.take());
+            if let Ok(state) = Rc::try_unwrap(thunk) {
+                match state.into_inner() {
+                    ThunkState::Delayed(_, mut env, _)
+                    | ThunkState::Forced(Value::Closure(_, _, mut env)) => stack.push(env.0.take()),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+enum Control<'a> {
+    Eval(&'a Node, Env<'a>),
+    Return(Value<'a>),
+}
+
+// what to do with the value once the current term is evaluated
+enum Frame<'a> {
+    // application and operator frames hold the node itself, so that the profiler can find it
+    // the function of an application is evaluated, the argument is next
+    Apply(&'a Node, Env<'a>),
+    // the argument of ! is evaluated, apply the function to it
+    StrictApply(&'a Node, Value<'a>),
+    Unary(&'a Node),
+    BinaryLeft(&'a Node, Env<'a>),
+    BinaryRight(&'a Node, Value<'a>),
+    If(&'a Node, &'a Node, Env<'a>),
+    // store the value in a thunk that was being forced
+    Update(Thunk<'a>),
+    // the body of a profiled lambda is evaluated, return to this node of the call tree
+    Leave(usize),
+}
+
+pub struct LazyEvaluator<'a> {
+    node: &'a Node,
+    eval_count: usize,
+    // when false, thunks are re-evaluated on every use like the official call-by-name evaluator
+    memoize: bool,
+    beta_reductions: usize,
+    beta_limit: Option<usize>,
+    profiler: Option<Profiler<'a>>,
+}
+
+impl<'a> LazyEvaluator<'a> {
+    pub fn new(node: &'a Node) -> LazyEvaluator<'a> {
+        LazyEvaluator {
+            node,
+            eval_count: 0,
+            memoize: true,
+            beta_reductions: 0,
+            beta_limit: None,
+            profiler: None,
+        }
+    }
+
+    pub fn call_by_name(node: &'a Node) -> LazyEvaluator<'a> {
+        LazyEvaluator {
+            memoize: false,
+            ..LazyEvaluator::new(node)
+        }
+    }
+
+    pub fn with_beta_limit(mut self, limit: usize) -> LazyEvaluator<'a> {
+        self.beta_limit = Some(limit);
+        self
+    }
+
+    pub fn beta_reductions(&self) -> usize {
+        self.beta_reductions
+    }
+
+    // attribute reductions and operator work to the lambdas of the program
+    pub fn with_profiler(mut self) -> LazyEvaluator<'a> {
+        self.profiler = Some(Profiler::new(self.node));
+        self
+    }
+
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profiler.take().map(Profiler::finish)
+    }
+
+    pub fn evaluate(&mut self) -> Result<Node, EvalError> {
+        let value = self.run(self.node, Env::default())?;
+        Ok(self.readback(&value))
+    }
+
+    pub fn eval_count(&self) -> usize {
+        self.eval_count
+    }
+
+    // a CEK machine: the continuation lives on the heap so deep terms do not overflow the stack
+    fn run(&mut self, node: &'a Node, env: Env<'a>) -> Result<Value<'a>, EvalError> {
+        let mut control = Control::Eval(node, env);
+        let mut frames: Vec<Frame<'a>> = vec![];
+        loop {
+            control = match control {
+                Control::Eval(node, env) => self.step(node, env, &mut frames)?,
+                Control::Return(value) => match frames.pop() {
+                    None => return Ok(value),
+                    Some(frame) => self.resume(frame, value, &mut frames)?,
+                },
+            };
+        }
+    }
+
+    fn step(
+        &mut self,
+        node: &'a Node,
+        env: Env<'a>,
+        frames: &mut Vec<Frame<'a>>,
+    ) -> Result<Control<'a>, EvalError> {
+        self.eval_count += 1;
+        let value = match node {
+            Node::Integer(value) => Value::Integer(value.clone()),
+            Node::String(value) => Value::String(value.clone()),
+            Node::Boolean(value) => Value::Boolean(*value),
+            Node::Variable(index) => match env.lookup(index) {
+                Some(thunk) => {
+                    let thunk = thunk.clone();
+                    return self.force(thunk, frames);
+                }
+                None => Value::Variable(index.clone()),
+            },
+            Node::Lambda(arity, body) => Value::Closure(arity, body, env),
+            Node::UnaryOperator(_, operand) => {
+                frames.push(Frame::Unary(node));
+                return Ok(Control::Eval(operand, env));
+            }
+            Node::BinaryOperator(operator, left, _) if is_application(operator) => {
+                frames.push(Frame::Apply(node, env.clone()));
+                return Ok(Control::Eval(left, env));
+            }
+            Node::BinaryOperator(_, left, _) => {
+                frames.push(Frame::BinaryLeft(node, env.clone()));
+                return Ok(Control::Eval(left, env));
+            }
+            Node::If(condition, then_branch, else_branch) => {
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_application(node, &[]);
+                }
+                frames.push(Frame::If(then_branch, else_branch, env.clone()));
+                return Ok(Control::Eval(condition, env));
+            }
+        };
+        Ok(Control::Return(value))
+    }
+
+    fn resume(
+        &mut self,
+        frame: Frame<'a>,
+        value: Value<'a>,
+        frames: &mut Vec<Frame<'a>>,
+    ) -> Result<Control<'a>, EvalError> {
+        let value = match frame {
+            Frame::Apply(redex @ Node::BinaryOperator(operator, _, right), env)
+                if operator == "!" =>
+            {
+                frames.push(Frame::StrictApply(redex, value));
+                return Ok(Control::Eval(right, env));
+            }
+            Frame::Apply(redex @ Node::BinaryOperator(operator, _, right), env) => {
+                let argument = self.delay(right, &env, operator == "~");
+                return self.apply(redex, value, argument, frames);
+            }
+            Frame::StrictApply(redex, function) => {
+                let argument = Rc::new(RefCell::new(ThunkState::Forced(value)));
+                return self.apply(redex, function, argument, frames);
+            }
+            Frame::Unary(site @ Node::UnaryOperator(operator, _)) => {
+                self.record_application(site, &[&value]);
+                self.evaluate_unary_operator(operator, value)?
+            }
+            Frame::BinaryLeft(site @ Node::BinaryOperator(_, _, right), env) => {
+                frames.push(Frame::BinaryRight(site, value));
+                return Ok(Control::Eval(right, env));
+            }
+            Frame::BinaryRight(site @ Node::BinaryOperator(operator, _, _), left) => {
+                self.record_application(site, &[&left, &value]);
+                self.evaluate_binary_operator(operator, left, value)?
+            }
+            Frame::Apply(_, _)
+            | Frame::Unary(_)
+            | Frame::BinaryLeft(_, _)
+            | Frame::BinaryRight(_, _) => {
+                unreachable!("operator frames always hold an operator node")
+            }
+            Frame::If(then_branch, else_branch, env) => match value {
+                Value::Boolean(true) => return Ok(Control::Eval(then_branch, env)),
+                Value::Boolean(false) => return Ok(Control::Eval(else_branch, env)),
+                condition => {
+                    return Err(EvalError::UnsupportedCondition {
+                        condition: condition.name(),
+                    })
+                }
+            },
+            Frame::Leave(caller) => {
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.leave(caller);
+                }
+                value
+            }
+            Frame::Update(thunk) => {
+                *thunk.borrow_mut() = ThunkState::Forced(value.clone());
+                value
+            }
+        };
+        Ok(Control::Return(value))
+    }
+
+    // variables are passed through as the same thunk so that the result is shared
+    fn delay(&mut self, node: &'a Node, env: &Env<'a>, shared: bool) -> Thunk<'a> {
+        let state = match node {
+            Node::Variable(index) => match env.lookup(index) {
+                Some(thunk) => return thunk.clone(),
+                None => ThunkState::Forced(Value::Variable(index.clone())),
+            },
+            Node::Integer(value) => ThunkState::Forced(Value::Integer(value.clone())),
+            Node::String(value) => ThunkState::Forced(Value::String(value.clone())),
+            Node::Boolean(value) => ThunkState::Forced(Value::Boolean(*value)),
+            Node::Lambda(arity, body) => {
+                ThunkState::Forced(Value::Closure(arity, body, env.clone()))
+            }
+            _ => ThunkState::Delayed(node, env.clone(), shared),
+        };
+        Rc::new(RefCell::new(state))
+    }
+
+    fn force(
+        &mut self,
+        thunk: Thunk<'a>,
+        frames: &mut Vec<Frame<'a>>,
+    ) -> Result<Control<'a>, EvalError> {
+        let state = std::mem::replace(&mut *thunk.borrow_mut(), ThunkState::Evaluating);
+        match state {
+            ThunkState::Forced(value) => {
+                *thunk.borrow_mut() = ThunkState::Forced(value.clone());
+                Ok(Control::Return(value))
+            }
+            ThunkState::Delayed(node, env, shared) if !self.memoize && !shared => {
+                *thunk.borrow_mut() = ThunkState::Delayed(node, env.clone(), shared);
+                Ok(Control::Eval(node, env))
+            }
+            ThunkState::Delayed(node, env, _) => {
+                frames.push(Frame::Update(thunk));
+                Ok(Control::Eval(node, env))
+            }
+            ThunkState::Evaluating => Err(EvalError::InfiniteLoop),
+        }
+    }
+
+    fn count_beta_reduction(&mut self) -> Result<(), EvalError> {
+        self.beta_reductions += 1;
+        match self.beta_limit {
+            Some(limit) if self.beta_reductions > limit => {
+                Err(EvalError::BetaReductionLimitExceeded { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_application(&mut self, site: &Node, operands: &[&Value<'a>]) {
+        if let Some(profiler) = &mut self.profiler {
+            let integers: Vec<&BigInt> = operands
+                .iter()
+                .filter_map(|operand| match operand {
+                    Value::Integer(value) => Some(value),
+                    _ => None,
+                })
+                .collect();
+            profiler.record_application(site, &integers);
+        }
+    }
+
+    fn apply(
+        &mut self,
+        redex: &'a Node,
+        function: Value<'a>,
+        argument: Thunk<'a>,
+        frames: &mut Vec<Frame<'a>>,
+    ) -> Result<Control<'a>, EvalError> {
+        let Node::BinaryOperator(operator, _, _) = redex else {
+            unreachable!("applications are binary operators")
+        };
+        match function {
+            // a tail call: the body replaces the application without pushing a frame
+            Value::Closure(arity, body, env) => {
+                self.count_beta_reduction()?;
+                if let Some(profiler) = &mut self.profiler {
+                    // only the profiler needs to know when the body is done, and it treats
+                    // applications in tail position as tail calls too
+                    let tail_call = matches!(frames.last(), Some(Frame::Leave(_)));
+                    let caller = profiler.enter(redex, body, tail_call);
+                    if !tail_call {
+                        frames.push(Frame::Leave(caller));
+                    }
+                }
+                Ok(Control::Eval(body, env.bind(arity, argument)))
+            }
+            _ => Err(EvalError::UnsupportedBinaryOperands {
+                operator: operator.clone(),
+                left: function.name(),
+                right: "Thunk".to_string(),
+            }),
+        }
+    }
+
+    fn evaluate_unary_operator(
+        &mut self,
+        operator: &str,
+        operand: Value<'a>,
+    ) -> Result<Value<'a>, EvalError> {
+        let value = match (operator, operand) {
+            ("-", Value::Integer(value)) => Value::Integer(-value),
+            ("!", Value::Boolean(value)) => Value::Boolean(!value),
+            // string to int
+            ("#", Value::String(value)) => {
+                Value::Integer(convert_integer_to_bigint(deconvert_string(value)))
+            }
+            // int to string
+            ("$", Value::Integer(value)) => {
+                Value::String(convert_string(deconvert_integer_from_bigint(value)))
+            }
+            ("-" | "!" | "#" | "$", operand) => {
+                return Err(EvalError::UnsupportedUnaryOperand {
+                    operator: operator.to_string(),
+                    operand: operand.name(),
+                })
+            }
+            _ => {
+                return Err(EvalError::UnknownUnaryOperator {
+                    operator: operator.to_string(),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn evaluate_binary_operator(
+        &mut self,
+        operator: &str,
+        left: Value<'a>,
+        right: Value<'a>,
+    ) -> Result<Value<'a>, EvalError> {
+        let value = match (operator, left, right) {
+            ("+", Value::Integer(left), Value::Integer(right)) => Value::Integer(left + right),
+            ("-", Value::Integer(left), Value::Integer(right)) => Value::Integer(left - right),
+            ("*", Value::Integer(left), Value::Integer(right)) => Value::Integer(left * right),
+            ("/" | "%", Value::Integer(_), Value::Integer(right)) if right == BigInt::from(0) => {
+                return Err(EvalError::DivisionByZero {
+                    operator: operator.to_string(),
+                })
+            }
+            ("/", Value::Integer(left), Value::Integer(right)) => Value::Integer(left / right),
+            ("%", Value::Integer(left), Value::Integer(right)) => Value::Integer(left % right),
+            ("<", Value::Integer(left), Value::Integer(right)) => Value::Boolean(left < right),
+            (">", Value::Integer(left), Value::Integer(right)) => Value::Boolean(left > right),
+            ("=", Value::Integer(left), Value::Integer(right)) => Value::Boolean(left == right),
+            ("=", Value::String(left), Value::String(right)) => Value::Boolean(left == right),
+            ("=", Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left == right),
+            ("|", Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left || right),
+            ("&", Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left && right),
+            (".", Value::String(left), Value::String(right)) => Value::String(left + &right),
+            ("T", Value::Integer(left), Value::String(right)) if left >= BigInt::from(0) => {
+                let left_usize = left.to_string().parse::<usize>().unwrap_or(usize::MAX);
+                Value::String(right.chars().take(left_usize).collect())
+            }
+            ("D", Value::Integer(left), Value::String(right)) if left >= BigInt::from(0) => {
+                let left_usize = left.to_string().parse::<usize>().unwrap_or(usize::MAX);
+                Value::String(right.chars().skip(left_usize).collect())
+            }
+            (
+                "+" | "-" | "*" | "/" | "%" | "<" | ">" | "=" | "|" | "&" | "." | "T" | "D",
+                left,
+                right,
+            ) => {
+                return Err(EvalError::UnsupportedBinaryOperands {
+                    operator: operator.to_string(),
+                    left: left.name(),
+                    right: right.name(),
+                })
+            }
+            _ => {
+                return Err(EvalError::UnknownBinaryOperator {
+                    operator: operator.to_string(),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    // convert a value back into a term, substituting the captured environment
+    fn readback(&self, value: &Value<'a>) -> Node {
+        match value {
+            Value::Integer(value) => Node::Integer(value.clone()),
+            Value::Boolean(value) => Node::Boolean(*value),
+            Value::String(value) => Node::String(value.clone()),
+            Value::Variable(index) => Node::Variable(index.clone()),
+            Value::Closure(arity, body, env) => {
+                let lambda = Node::Lambda((*arity).clone(), Box::new((*body).clone()));
+                self.readback_node(&lambda, env)
+            }
+        }
+    }
+
+    fn readback_node(&self, node: &Node, env: &Env<'a>) -> Node {
+        let mut substitution = HashMap::new();
+        for variable in free_variables(node) {
+            let value = match env.lookup(&variable) {
+                Some(thunk) => match &*thunk.borrow() {
+                    ThunkState::Forced(value) => self.readback(value),
+                    ThunkState::Delayed(node, env, _) => self.readback_node(node, env),
+                    ThunkState::Evaluating => continue,
+                },
+                None => continue,
+            };
+            substitution.insert(variable, value);
+        }
+        substitute_all(node, substitution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{
+        evaluator::{Evaluator, Strategy},
+        parser::Parser,
+        tokenizer::Tokenizer,
+    };
+
+    use super::*;
+
+    fn evaluate_both(input: &str) -> (Node, Node) {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let by_substitution = Evaluator::new(node.clone()).evaluate().unwrap();
+        let by_need = Evaluator::with_strategy(node, Strategy::CallByNeed)
+            .evaluate()
+            .unwrap();
+        (by_substitution, by_need)
+    }
+
+    #[test]
+    fn test_evaluate_operators() {
+        let cases = vec![
+            ("U- I$", Node::Integer(BigInt::from(-3))),
+            ("U! T", Node::Boolean(false)),
+            ("U$ I4%34", Node::String("test".to_string())),
+            ("B/ U- I( I#", Node::Integer(BigInt::from(-3))),
+            ("B% U- I( I#", Node::Integer(BigInt::from(-1))),
+            ("B= S4% S4%", Node::Boolean(true)),
+            ("BT I$ S4%34", Node::String("tes".to_string())),
+            ("BD I$ S4%34", Node::String("t".to_string())),
+            ("? B> I# I$ S9%3 S./", Node::String("no".to_string())),
+        ];
+        for (input, expected) in cases {
+            let (by_substitution, by_need) = evaluate_both(input);
+            assert_eq!(by_need, expected);
+            assert_eq!(by_need, by_substitution);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_lambda() {
+        let node = Node::Lambda(
+            BigInt::from(1),
+            Box::new(Node::BinaryOperator(
+                "+".to_string(),
+                Box::new(Node::Integer(BigInt::from(1))),
+                Box::new(Node::Variable(BigInt::from(2))),
+            )),
+        );
+        let result = LazyEvaluator::new(&node).evaluate().unwrap();
+        assert_eq!(result, node);
+
+        let (by_substitution, by_need) = evaluate_both("B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK");
+        assert_eq!(by_need, Node::String("Hello World!".to_string()));
+        assert_eq!(by_need, by_substitution);
+
+        let (by_substitution, by_need) = evaluate_both("B$ B$ L# L\" B+ v\" v# I\" I#");
+        assert_eq!(by_need, Node::Integer(BigInt::from(3)));
+        assert_eq!(by_need, by_substitution);
+    }
+
+    #[test]
+    fn test_readback_closure() {
+        let tokens = Tokenizer::new("B$ L# L$ B+ v# v$ I+").tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let result = LazyEvaluator::new(&node).evaluate().unwrap();
+        assert_eq!(
+            result,
+            Node::Lambda(
+                BigInt::from(3),
+                Box::new(Node::BinaryOperator(
+                    "+".to_string(),
+                    Box::new(Node::Integer(BigInt::from(10))),
+                    Box::new(Node::Variable(BigInt::from(3))),
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn test_evaluate_recursion() {
+        // the example from the language notes, a Y combinator computing 16
+        let (by_substitution, by_need) = evaluate_both(
+            "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%",
+        );
+        assert_eq!(by_need, Node::Integer(BigInt::from(16)));
+        assert_eq!(by_need, by_substitution);
+    }
+
+    #[test]
+    fn test_evaluate_shared_argument() {
+        // efficiency1: doubling 22 times, which is 4^22 additions without sharing
+        let tokens = Tokenizer::new("B$ L! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! I\" L! B+ B+ v! v! B+ v! v!").tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let mut evaluator = LazyEvaluator::new(&node);
+        assert_eq!(
+            evaluator.evaluate().unwrap(),
+            Node::Integer(BigInt::from(4).pow(22))
+        );
+        assert!(evaluator.eval_count < 1000);
+    }
+
+    #[test]
+    fn test_evaluate_deep_program() {
+        // a concatenation chain far deeper than a recursive evaluator can handle
+        let depth = 100000;
+        let input = "B. S# ".repeat(depth) + "S#";
+        let tokens = Tokenizer::new(&input).tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        assert_eq!(node.to_string(), input);
+        let result = LazyEvaluator::new(&node).evaluate().unwrap();
+        assert_eq!(result, Node::String("c".repeat(depth + 1)));
+
+        // a recursive function that calls itself before the addition, 20000 levels deep
+        let input = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I! B+ I\" B$ v\" B- v# I\" I#9i";
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let result = LazyEvaluator::new(&node).evaluate().unwrap();
+        assert_eq!(result, Node::Integer(BigInt::from(20000)));
+    }
+}
@@ -0,0 +1,679 @@
+use std::collections::{HashMap, HashSet};
+
+use num_bigint::BigInt;
+
+use super::error::EvalError;
+use super::evaluator::{
+    free_variables, is_application, reduce_primitive, substitute, Evaluator, Strategy,
+    BETA_REDUCTION_LIMIT,
+};
+use super::parser::Node;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Pass {
+    // operators whose operands are all literals, when the result is not longer
+    ConstantFolding,
+    // B$ L x body arg becomes body with arg substituted, when that is shorter
+    Inlining,
+    // ? on a literal boolean
+    DeadBranches,
+    // L x B$ f vx becomes f when x is not free in f
+    EtaReduction,
+    // the closed subterm that saves the most characters when bound once with B$ L
+    CommonSubexpressions,
+    // binders renamed to the smallest ids that do not capture, see `renumber`
+    Renumbering,
+}
+
+pub const ALL_PASSES: [Pass; 6] = [
+    Pass::ConstantFolding,
+    Pass::DeadBranches,
+    Pass::Inlining,
+    Pass::EtaReduction,
+    Pass::CommonSubexpressions,
+    Pass::Renumbering,
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Objective {
+    // characters of the program first, beta reductions to break ties
+    #[default]
+    Size,
+    // beta reductions first, characters to break ties
+    Cost,
+}
+
+// runs passes until none of them improves the program; the result of a pass is kept only
+// if the program still evaluates to the same value and its score under the objective drops
+pub struct Optimizer {
+    passes: Vec<Pass>,
+    objective: Objective,
+    beta_limit: usize,
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Optimizer::new()
+    }
+}
+
+impl Optimizer {
+    pub fn new() -> Optimizer {
+        Optimizer {
+            passes: ALL_PASSES.to_vec(),
+            objective: Objective::default(),
+            beta_limit: BETA_REDUCTION_LIMIT,
+        }
+    }
+
+    pub fn with_passes(mut self, passes: &[Pass]) -> Optimizer {
+        self.passes = passes.to_vec();
+        self
+    }
+
+    pub fn with_objective(mut self, objective: Objective) -> Optimizer {
+        self.objective = objective;
+        self
+    }
+
+    // candidates needing more than `limit` beta reductions are rejected
+    pub fn with_beta_limit(mut self, limit: usize) -> Optimizer {
+        self.beta_limit = limit;
+        self
+    }
+
+    pub fn optimize(&self, node: &Node) -> Result<Node, EvalError> {
+        let (expected, beta_reductions) = self.evaluate(node)?;
+        // the derived comparison of nodes recurses, their source does not
+        let expected = expected.to_string();
+        let mut best = node.clone();
+        let mut best_source = best.to_string();
+        let mut best_beta_reductions = beta_reductions;
+        let mut improved = true;
+        while improved {
+            improved = false;
+            for pass in &self.passes {
+                let candidate = pass.run(&best);
+                let source = candidate.to_string();
+                if source == best_source {
+                    continue;
+                }
+                // a longer candidate cannot win on size, so it is not worth evaluating
+                if self.objective == Objective::Size && source.len() > best_source.len() {
+                    continue;
+                }
+                let (result, beta_reductions) = match pass.added_beta_reductions() {
+                    Some(added) if best_beta_reductions + added <= self.beta_limit => {
+                        (expected.clone(), best_beta_reductions + added)
+                    }
+                    _ => {
+                        let Ok((result, beta_reductions)) = self.evaluate(&candidate) else {
+                            continue;
+                        };
+                        (result.to_string(), beta_reductions)
+                    }
+                };
+                let score = self.score(source.len(), beta_reductions);
+                if result == expected && score < self.score(best_source.len(), best_beta_reductions)
+                {
+                    best = candidate;
+                    best_source = source;
+                    best_beta_reductions = beta_reductions;
+                    improved = true;
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    // call-by-name counts beta reductions the way the official evaluator does
+    fn evaluate(&self, node: &Node) -> Result<(Node, usize), EvalError> {
+        let mut evaluator = Evaluator::with_strategy(node.clone(), Strategy::CallByName)
+            .with_beta_limit(self.beta_limit);
+        let result = evaluator.evaluate()?;
+        Ok((result, evaluator.beta_reductions()))
+    }
+
+    fn score(&self, size: usize, beta_reductions: usize) -> (usize, usize) {
+        match self.objective {
+            Objective::Size => (size, beta_reductions),
+            Objective::Cost => (beta_reductions, size),
+        }
+    }
+}
+
+impl Pass {
+    pub fn run(&self, node: &Node) -> Node {
+        match self {
+            Pass::ConstantFolding => rewrite(node, &fold_constant),
+            Pass::Inlining => rewrite(node, &inline),
+            Pass::DeadBranches => rewrite(node, &remove_dead_branch),
+            Pass::EtaReduction => rewrite(node, &eta_reduce),
+            Pass::CommonSubexpressions => extract_common_subexpression(node),
+            Pass::Renumbering => renumber(node),
+        }
+    }
+
+    // the beta reductions that the pass adds under call-by-name when it keeps the value by
+    // construction, so its candidates need no evaluation: renaming binders adds none, and
+    // binding a closed subterm adds the one application of its lambda
+    fn added_beta_reductions(&self) -> Option<usize> {
+        match self {
+            Pass::Renumbering => Some(0),
+            Pass::CommonSubexpressions => Some(1),
+            _ => None,
+        }
+    }
+}
+
+// characters of the program as submitted
+pub fn size(node: &Node) -> usize {
+    node.to_string().len()
+}
+
+fn is_literal(node: &Node) -> bool {
+    matches!(node, Node::Integer(_) | Node::String(_) | Node::Boolean(_))
+}
+
+// rebuild the tree bottom-up, trying `rule` on every node once its children are done; walks
+// with a work stack, `true` marks a node whose children are rebuilt
+fn rewrite(node: &Node, rule: &dyn Fn(&Node) -> Option<Node>) -> Node {
+    let mut stack = vec![(node, false)];
+    let mut results = vec![];
+    while let Some((node, rebuilt)) = stack.pop() {
+        if !rebuilt {
+            stack.push((node, true));
+            stack.extend(
+                node.children()
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, false)),
+            );
+            continue;
+        }
+        let node = rebuild(node, &mut results);
+        results.push(rule(&node).unwrap_or(node));
+    }
+    results.pop().unwrap()
+}
+
+// the node with its children taken from the end of `results`
+fn rebuild(node: &Node, results: &mut Vec<Node>) -> Node {
+    let mut pop = || Box::new(results.pop().unwrap());
+    match node {
+        Node::UnaryOperator(operator, _) => Node::UnaryOperator(operator.clone(), pop()),
+        Node::BinaryOperator(operator, _, _) => {
+            let right = pop();
+            Node::BinaryOperator(operator.clone(), pop(), right)
+        }
+        Node::If(_, _, _) => {
+            let else_branch = pop();
+            let then_branch = pop();
+            Node::If(pop(), then_branch, else_branch)
+        }
+        Node::Lambda(variable, _) => Node::Lambda(variable.clone(), pop()),
+        _ => node.clone(),
+    }
+}
+
+fn fold_constant(node: &Node) -> Option<Node> {
+    let foldable = match node {
+        Node::UnaryOperator(_, operand) => is_literal(operand),
+        Node::BinaryOperator(operator, left, right) => {
+            !is_application(operator) && is_literal(left) && is_literal(right)
+        }
+        _ => false,
+    };
+    if !foldable {
+        return None;
+    }
+    // errors such as division by zero are left for the evaluator to report
+    let result = reduce_primitive(node).ok()?;
+    match &result {
+        Node::Integer(value) if *value < BigInt::from(0) => None,
+        _ => (size(&result) <= size(node)).then_some(result),
+    }
+}
+
+fn inline(node: &Node) -> Option<Node> {
+    let Node::BinaryOperator(operator, function, argument) = node else {
+        return None;
+    };
+    let Node::Lambda(variable, body) = function.as_ref() else {
+        return None;
+    };
+    // call-by-value evaluates the argument even if it is unused, keep that unless it is a value
+    let strict = operator == "!"
+        && !matches!(
+            argument.as_ref(),
+            Node::Integer(_)
+                | Node::String(_)
+                | Node::Boolean(_)
+                | Node::Variable(_)
+                | Node::Lambda(_, _)
+        );
+    if !is_application(operator) || strict {
+        return None;
+    }
+    // B$ L<id> <body> <argument> keeps only the body, where every use grows by the argument;
+    // check that before building the substitution
+    let uses = count_uses(body, variable);
+    let (argument_size, variable_size) = (size(argument), size(&Node::Variable(variable.clone())));
+    if uses * argument_size
+        >= "B$  ".len() + variable_size + " ".len() + argument_size + uses * variable_size
+    {
+        return None;
+    }
+    let result = substitute(body, variable, argument);
+    (size(&result) < size(node)).then_some(result)
+}
+
+// the occurrences of the variable in the node that no lambda of the same id binds
+fn count_uses(node: &Node, variable: &BigInt) -> usize {
+    let mut stack = vec![node];
+    let mut uses = 0;
+    while let Some(node) = stack.pop() {
+        match node {
+            Node::Variable(index) if index == variable => uses += 1,
+            Node::Lambda(arity, _) if arity == variable => {}
+            _ => stack.extend(node.children()),
+        }
+    }
+    uses
+}
+
+fn remove_dead_branch(node: &Node) -> Option<Node> {
+    match node {
+        Node::If(condition, then_branch, else_branch) => match condition.as_ref() {
+            Node::Boolean(true) => Some(then_branch.as_ref().clone()),
+            Node::Boolean(false) => Some(else_branch.as_ref().clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn eta_reduce(node: &Node) -> Option<Node> {
+    let Node::Lambda(variable, body) = node else {
+        return None;
+    };
+    let Node::BinaryOperator(operator, function, argument) = body.as_ref() else {
+        return None;
+    };
+    match argument.as_ref() {
+        Node::Variable(argument)
+            if operator == "$"
+                && argument == variable
+                && !free_variables(function).contains(variable) =>
+        {
+            Some(function.as_ref().clone())
+        }
+        _ => None,
+    }
+}
+
+// bind the most profitable repeated closed subterm with a lambda applied at the root
+fn extract_common_subexpression(node: &Node) -> Node {
+    let (subterms, used) = number_subterms(node);
+    let root = subterms.last().unwrap().1;
+    let mut counts: HashMap<usize, (&Node, usize)> = HashMap::new();
+    for &(subterm, number, closed) in &subterms {
+        if closed {
+            counts.entry(number).or_insert((subterm, 0)).1 += 1;
+        }
+    }
+    // the shortest id that no binder or variable of the program uses
+    let mut variable = BigInt::from(0);
+    while used.contains(&variable) {
+        variable += 1;
+    }
+    let replacement = Node::Variable(variable.clone());
+    // B$ L<id> <body> <value>, with the occurrences replaced in the body
+    let overhead = "B$ L  ".len() + size(&replacement) - "v".len();
+    let best = counts
+        .into_iter()
+        .filter(|&(number, (_, count))| count > 1 && number != root)
+        .map(|(number, (subterm, count))| {
+            let length = size(subterm);
+            let saved = count * length;
+            let added = count * size(&replacement) + overhead + length;
+            (saved as isize - added as isize, subterm, number)
+        })
+        .filter(|&(saving, _, _)| saving > 0)
+        .max_by(|a, b| a.0.cmp(&b.0).then_with(|| size(b.1).cmp(&size(a.1))));
+    let Some((_, subterm, number)) = best else {
+        return node.clone();
+    };
+    let body = replace_subterm(&subterms, number, &replacement);
+    Node::BinaryOperator(
+        "$".to_string(),
+        Box::new(Node::Lambda(variable, Box::new(body))),
+        Box::new(subterm.clone()),
+    )
+}
+
+// a subterm with its children replaced by their numbers, so that equal subterms have equal
+// shapes and comparing them does not recurse like the derived comparison of nodes
+#[derive(PartialEq, Eq, Hash)]
+enum Shape<'a> {
+    Leaf(&'a Node),
+    Unary(&'a str, usize),
+    Binary(&'a str, usize, usize),
+    If(usize, usize, usize),
+    Lambda(&'a BigInt, usize),
+}
+
+// the subterms in post-order, each with a number shared by the subterms equal to it and
+// whether it is closed, and every id used by a binder or variable; walks with a work stack
+fn number_subterms(node: &Node) -> (Vec<(&Node, usize, bool)>, HashSet<BigInt>) {
+    let mut stack = vec![(node, false)];
+    // the number and free variables of every subterm whose parent is not done yet
+    let mut results: Vec<(usize, HashSet<BigInt>)> = vec![];
+    let mut shapes = HashMap::new();
+    let mut subterms = vec![];
+    let mut used = HashSet::new();
+    while let Some((node, visited)) = stack.pop() {
+        if !visited {
+            stack.push((node, true));
+            stack.extend(
+                node.children()
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, false)),
+            );
+            continue;
+        }
+        let operands = results.split_off(results.len() - node.children().len());
+        let numbers: Vec<usize> = operands.iter().map(|(number, _)| *number).collect();
+        let mut free: HashSet<BigInt> = operands.into_iter().flat_map(|(_, free)| free).collect();
+        let shape = match node {
+            Node::Variable(variable) => {
+                used.insert(variable.clone());
+                free.insert(variable.clone());
+                Shape::Leaf(node)
+            }
+            Node::UnaryOperator(operator, _) => Shape::Unary(operator, numbers[0]),
+            Node::BinaryOperator(operator, _, _) => Shape::Binary(operator, numbers[0], numbers[1]),
+            Node::If(_, _, _) => Shape::If(numbers[0], numbers[1], numbers[2]),
+            Node::Lambda(variable, _) => {
+                used.insert(variable.clone());
+                free.remove(variable);
+                Shape::Lambda(variable, numbers[0])
+            }
+            _ => Shape::Leaf(node),
+        };
+        let next = shapes.len();
+        let number = *shapes.entry(shape).or_insert(next);
+        subterms.push((node, number, free.is_empty()));
+        results.push((number, free));
+    }
+    (subterms, used)
+}
+
+// rebuild the program from its subterms in post-order, with the replacement for every
+// subterm numbered `number`
+fn replace_subterm(subterms: &[(&Node, usize, bool)], number: usize, replacement: &Node) -> Node {
+    let mut results = vec![];
+    for &(subterm, subterm_number, _) in subterms {
+        let node = rebuild(subterm, &mut results);
+        if subterm_number == number {
+            results.push(replacement.clone());
+        } else {
+            results.push(node);
+        }
+    }
+    results.pop().unwrap()
+}
+
+// a lambda of the program, in prefix order
+#[derive(Default)]
+struct Binder {
+    // occurrences of the id, the binder included
+    uses: usize,
+    // binders whose variables are used inside this one, or the other way around
+    conflicts: HashSet<usize>,
+    // free variables of the program used inside this binder
+    free: HashSet<BigInt>,
+}
+
+// alpha-rename every binder to the smallest id it can take without capturing a variable;
+// the most used binders choose first, so they get the one-character ids below 94
+pub fn renumber(node: &Node) -> Node {
+    let binders = collect_binders(node);
+    let mut order: Vec<usize> = (0..binders.len()).collect();
+    order.sort_by_key(|&index| std::cmp::Reverse(binders[index].uses));
+    let mut ids: Vec<Option<BigInt>> = vec![None; binders.len()];
+    for index in order {
+        let taken: HashSet<&BigInt> = binders[index]
+            .conflicts
+            .iter()
+            .filter_map(|&other| ids[other].as_ref())
+            .chain(binders[index].free.iter())
+            .collect();
+        let mut id = BigInt::from(0);
+        while taken.contains(&id) {
+            id += 1;
+        }
+        ids[index] = Some(id);
+    }
+    let ids: Vec<BigInt> = ids.into_iter().map(Option::unwrap).collect();
+    rename(node, &ids)
+}
+
+// the binders of the program in prefix order; walks with a work stack where `None` marks the
+// end of a lambda body, and `scope` holds the original id and index of every lambda around
+fn collect_binders(node: &Node) -> Vec<Binder> {
+    let mut stack = vec![Some(node)];
+    let mut scope: Vec<(&BigInt, usize)> = vec![];
+    let mut binders: Vec<Binder> = vec![];
+    while let Some(node) = stack.pop() {
+        match node {
+            None => {
+                scope.pop();
+            }
+            Some(Node::Variable(variable)) => {
+                match scope.iter().rposition(|&(id, _)| id == variable) {
+                    Some(position) => {
+                        let binder = scope[position].1;
+                        binders[binder].uses += 1;
+                        // every binder between the use and its own one must keep another id
+                        for &(_, inner) in &scope[position + 1..] {
+                            binders[binder].conflicts.insert(inner);
+                            binders[inner].conflicts.insert(binder);
+                        }
+                    }
+                    None => {
+                        for &(_, inner) in &scope {
+                            binders[inner].free.insert(variable.clone());
+                        }
+                    }
+                }
+            }
+            Some(Node::Lambda(variable, body)) => {
+                scope.push((variable, binders.len()));
+                binders.push(Binder {
+                    uses: 1,
+                    ..Binder::default()
+                });
+                stack.push(None);
+                stack.push(Some(body));
+            }
+            Some(node) => stack.extend(node.children().into_iter().rev().map(Some)),
+        }
+    }
+    binders
+}
+
+// the program with the binder of prefix index `i` renamed to `ids[i]`, walking with a work
+// stack; `true` marks a node whose children are renamed
+fn rename(node: &Node, ids: &[BigInt]) -> Node {
+    let mut stack = vec![(node, false)];
+    let mut scope: Vec<(&BigInt, usize)> = vec![];
+    let mut next = 0;
+    let mut results = vec![];
+    while let Some((node, renamed)) = stack.pop() {
+        if !renamed {
+            if let Node::Lambda(variable, _) = node {
+                scope.push((variable, next));
+                next += 1;
+            }
+            stack.push((node, true));
+            stack.extend(
+                node.children()
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, false)),
+            );
+            continue;
+        }
+        let node = match node {
+            Node::Variable(variable) => match scope.iter().rev().find(|&&(id, _)| id == variable) {
+                Some(&(_, binder)) => Node::Variable(ids[binder].clone()),
+                None => node.clone(),
+            },
+            Node::Lambda(_, _) => {
+                let (_, binder) = scope.pop().unwrap();
+                Node::Lambda(ids[binder].clone(), Box::new(results.pop().unwrap()))
+            }
+            _ => rebuild(node, &mut results),
+        };
+        results.push(node);
+    }
+    results.pop().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    #[test]
+    fn test_passes() {
+        let cases = [
+            (Pass::ConstantFolding, "B. S4% B+ I# I$", "B. S4% I&"),
+            (Pass::ConstantFolding, "B. S4% U$ I4%34", "S4%4%34"),
+            (Pass::ConstantFolding, "B* I~~~ I~~~", "I~~}!!\""),
+            // ICFP has no negative integer literals
+            (Pass::ConstantFolding, "B- I\" I#", "B- I\" I#"),
+            (Pass::ConstantFolding, "B/ I# I!", "B/ I# I!"),
+            (Pass::DeadBranches, "? B< I# I$ S4 S5", "? B< I# I$ S4 S5"),
+            (Pass::DeadBranches, "? F S4 S5", "S5"),
+            (Pass::Inlining, "B$ L# B+ v# I$ I#", "B+ I# I$"),
+            (
+                Pass::Inlining,
+                "B$ L# B. v# v# S4%34444",
+                "B. S4%34444 S4%34444",
+            ),
+            (
+                Pass::Inlining,
+                "B$ L# B. v# v# S4%34444444444",
+                "B$ L# B. v# v# S4%34444444444",
+            ),
+            (Pass::Inlining, "B! L# I$ B/ I# I!", "B! L# I$ B/ I# I!"),
+            (Pass::EtaReduction, "L# B$ v\" v#", "v\""),
+            (Pass::EtaReduction, "L# B$ v# v#", "L# B$ v# v#"),
+            (
+                Pass::CommonSubexpressions,
+                "B. B. S4%34444 S4%34444 S4%34444",
+                "B$ L! B. B. v! v! v! S4%34444",
+            ),
+            (Pass::CommonSubexpressions, "B. S4% S4%", "B. S4% S4%"),
+        ];
+        for (pass, input, expected) in cases {
+            assert_eq!(
+                pass.run(&parse(input)).to_string(),
+                expected,
+                "{:?} {}",
+                pass,
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_renumber() {
+        let cases = [
+            ("L~~ L~~~ B+ v~~ v~~~", "L! L\" B+ v! v\""),
+            // siblings and unused shadowed variables share ids
+            ("B$ L% v% L& v&", "B$ L! v! L! v!"),
+            ("L$ L% v%", "L! L! v!"),
+            // the most used variable gets the smallest id
+            ("L# L$ B+ v# B+ v$ v$", "L\" L! B+ v\" B+ v! v!"),
+            // free variables are never captured
+            ("L# B+ v# v!", "L\" B+ v\" v!"),
+            ("L# B+ v# v\"", "L! B+ v! v\""),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(renumber(&parse(input)).to_string(), expected, "{}", input);
+        }
+        let node = parse("B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%");
+        let renumbered = renumber(&node);
+        assert!(size(&renumbered) <= size(&node));
+        assert_eq!(
+            Evaluator::new(renumbered).evaluate(),
+            Ok(Node::Integer(BigInt::from(16)))
+        );
+    }
+
+    #[test]
+    fn test_renumber_deep() {
+        // 100000 nested lambdas where only the innermost variable is used
+        let input = format!("{}v~~", "L~~ ".repeat(100_000));
+        let expected = format!("{}v!", "L! ".repeat(100_000));
+        assert_eq!(renumber(&parse(&input)).to_string(), expected);
+    }
+
+    #[test]
+    fn test_optimize() {
+        let node = parse("B$ L# ? B> I$ I# B. v# v# S! B. S4%34444 B. S4%34444 S4%34444");
+        let optimized = Optimizer::new().optimize(&node).unwrap();
+        assert_eq!(
+            optimized.to_string(),
+            "B$ L# B. v# v# S4%344444%344444%34444"
+        );
+        assert_eq!(
+            Evaluator::new(optimized).evaluate(),
+            Evaluator::new(node.clone()).evaluate()
+        );
+        // binding the repeated string saves characters but costs a beta reduction
+        let node = parse("B. B. S4%34444 S4%34444 S4%34444");
+        let optimizer = Optimizer::new().with_passes(&[Pass::CommonSubexpressions]);
+        let optimized = optimizer.optimize(&node).unwrap();
+        assert_eq!(optimized.to_string(), "B$ L! B. B. v! v! v! S4%34444");
+        let optimizer = optimizer.with_objective(Objective::Cost);
+        assert_eq!(optimizer.optimize(&node).unwrap(), node);
+    }
+
+    #[test]
+    fn test_optimize_keeps_behaviour() {
+        // eta-reducing would turn a lambda into a division by zero
+        let node = parse("L# B$ B/ I# I! v#");
+        assert_eq!(Pass::EtaReduction.run(&node).to_string(), "B/ I# I!");
+        assert_eq!(Optimizer::new().optimize(&node).unwrap(), node);
+        assert!(Optimizer::new().optimize(&parse("B/ I# I!")).is_err());
+    }
+
+    #[test]
+    fn test_optimize_deep() {
+        // a 100000 deep chain of concatenations of the same string to a variable
+        let input = format!("L# {}v#", "B. S4%34444 ".repeat(100_000));
+        let node = parse(&input);
+        for pass in [
+            Pass::ConstantFolding,
+            Pass::DeadBranches,
+            Pass::Inlining,
+            Pass::EtaReduction,
+        ] {
+            assert_eq!(pass.run(&node).to_string(), input, "{:?}", pass);
+        }
+        let expected = format!("B$ L! L# {}v# S4%34444", "B. v! ".repeat(100_000));
+        let extracted = Pass::CommonSubexpressions.run(&node);
+        assert_eq!(extracted.to_string(), expected);
+    }
+}
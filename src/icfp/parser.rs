@@ -1,24 +1,129 @@
+use std::fmt;
+
 use num_bigint::BigInt;
 
 use crate::icfp::util::{
     convert_integer_to_bigint, deconvert_integer_from_bigint, deconvert_string,
 };
 
+use super::arena::{BinaryOp, UnaryOp};
+use super::error::EvalError;
 use super::tokenizer::Token;
 
-#[derive(Debug, PartialEq, Clone, Eq, Hash)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub enum Node {
     Integer(BigInt),
     String(String),
     Boolean(bool),
-    Variable(usize),
+    Variable(BigInt),
     UnaryOperator(String, Box<Node>),
     BinaryOperator(String, Box<Node>, Box<Node>),
     If(Box<Node>, Box<Node>, Box<Node>),
-    Lambda(usize, Box<Node>),
+    Lambda(BigInt, Box<Node>),
+}
+
+// drop children with a work stack, the derived drop recurses once per nesting level
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        take_children(self, &mut stack);
+        while let Some(mut node) = stack.pop() {
+            take_children(&mut node, &mut stack);
+        }
+    }
+}
+
+// copy with a work stack for the same reason; `true` marks a node whose children are copied
+impl Clone for Node {
+    fn clone(&self) -> Node {
+        let mut stack = vec![(self, false)];
+        let mut copies = vec![];
+        while let Some((node, copied)) = stack.pop() {
+            let copy = match (node, copied) {
+                (Node::UnaryOperator(_, operand) | Node::Lambda(_, operand), false) => {
+                    stack.push((node, true));
+                    stack.push((operand, false));
+                    continue;
+                }
+                (Node::BinaryOperator(_, left, right), false) => {
+                    stack.push((node, true));
+                    stack.push((right, false));
+                    stack.push((left, false));
+                    continue;
+                }
+                (Node::If(condition, then_branch, else_branch), false) => {
+                    stack.push((node, true));
+                    stack.push((else_branch, false));
+                    stack.push((then_branch, false));
+                    stack.push((condition, false));
+                    continue;
+                }
+                (Node::UnaryOperator(operator, _), true) => {
+                    let operand = copies.pop().unwrap();
+                    Node::UnaryOperator(operator.clone(), Box::new(operand))
+                }
+                (Node::Lambda(arity, _), true) => {
+                    let body = copies.pop().unwrap();
+                    Node::Lambda(arity.clone(), Box::new(body))
+                }
+                (Node::BinaryOperator(operator, _, _), true) => {
+                    let right = copies.pop().unwrap();
+                    let left = copies.pop().unwrap();
+                    Node::BinaryOperator(operator.clone(), Box::new(left), Box::new(right))
+                }
+                (Node::If(_, _, _), true) => {
+                    let else_branch = copies.pop().unwrap();
+                    let then_branch = copies.pop().unwrap();
+                    let condition = copies.pop().unwrap();
+                    Node::If(
+                        Box::new(condition),
+                        Box::new(then_branch),
+                        Box::new(else_branch),
+                    )
+                }
+                (Node::Integer(value), _) => Node::Integer(value.clone()),
+                (Node::String(value), _) => Node::String(value.clone()),
+                (Node::Boolean(value), _) => Node::Boolean(*value),
+                (Node::Variable(index), _) => Node::Variable(index.clone()),
+            };
+            copies.push(copy);
+        }
+        copies.pop().unwrap()
+    }
+}
+
+fn take_children(node: &mut Node, stack: &mut Vec<Node>) {
+    let mut take = |child: &mut Box<Node>| {
+        stack.push(std::mem::replace(child.as_mut(), Node::Boolean(false)));
+    };
+    match node {
+        Node::UnaryOperator(_, operand) | Node::Lambda(_, operand) => take(operand),
+        Node::BinaryOperator(_, left, right) => {
+            take(left);
+            take(right);
+        }
+        Node::If(condition, then_branch, else_branch) => {
+            take(condition);
+            take(then_branch);
+            take(else_branch);
+        }
+        _ => {}
+    }
 }
 
 impl Node {
+    // the operands in source order, lambdas have their body as only child
+    pub fn children(&self) -> Vec<&Node> {
+        match self {
+            Node::UnaryOperator(_, operand) | Node::Lambda(_, operand) => vec![operand],
+            Node::BinaryOperator(_, left, right) => vec![left, right],
+            Node::If(condition, then_branch, else_branch) => {
+                vec![condition, then_branch, else_branch]
+            }
+            _ => vec![],
+        }
+    }
+
     pub fn dump_tree(&self, indent: usize) {
         match self {
             Node::Integer(value) => println!("{:indent$}Integer({})", "", value, indent = indent),
@@ -107,35 +212,35 @@ impl Node {
 
     pub fn to_string(&self) -> String {
         let mut tokens: Vec<Token> = vec![];
-        fn traverse(node: &Node, tokens: &mut Vec<Token>) {
+        // walk with an explicit stack so that deeply nested terms do not overflow
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
             match node {
-                Node::Integer(value) => tokens.push(Token::Integer(BigInt::from(value.clone()))),
+                Node::Integer(value) => tokens.push(Token::Integer(value.clone())),
                 Node::String(value) => tokens.push(Token::String(value.clone())),
                 Node::Boolean(value) => tokens.push(Token::Boolean(*value)),
-                Node::Variable(value) => tokens.push(Token::Variable(*value)),
+                Node::Variable(value) => tokens.push(Token::Variable(value.clone())),
                 Node::UnaryOperator(operator, operand) => {
                     tokens.push(Token::UnaryOperator(operator.clone()));
-                    traverse(operand, tokens);
+                    stack.push(operand);
                 }
                 Node::BinaryOperator(operator, left, right) => {
                     tokens.push(Token::BinaryOperator(operator.clone()));
-                    traverse(left, tokens);
-                    traverse(right, tokens);
+                    stack.push(right);
+                    stack.push(left);
                 }
                 Node::If(condition, then_branch, else_branch) => {
                     tokens.push(Token::If);
-                    traverse(condition, tokens);
-                    traverse(then_branch, tokens);
-                    traverse(else_branch, tokens);
+                    stack.push(else_branch);
+                    stack.push(then_branch);
+                    stack.push(condition);
                 }
                 Node::Lambda(arity, body) => {
                     tokens.push(Token::Lambda(arity.clone()));
-                    traverse(body, tokens);
+                    stack.push(body);
                 }
-                _ => {}
             }
         }
-        traverse(self, &mut tokens);
         tokens
             .iter()
             .map(|token| token.to_string())
@@ -157,9 +262,124 @@ impl Node {
     }
 }
 
+// an operator that is still waiting for some of its operands
+enum Frame {
+    Unary(String),
+    Binary(String),
+    If,
+    Lambda(BigInt),
+}
+
+impl Frame {
+    fn arity(&self) -> usize {
+        match self {
+            Frame::Unary(_) | Frame::Lambda(_) => 1,
+            Frame::Binary(_) => 2,
+            Frame::If => 3,
+        }
+    }
+
+    fn build(self, operands: Vec<Node>) -> Node {
+        let mut operands = operands.into_iter().map(Box::new);
+        let mut next = || operands.next().unwrap();
+        match self {
+            Frame::Unary(operator) => Node::UnaryOperator(operator, next()),
+            Frame::Binary(operator) => Node::BinaryOperator(operator, next(), next()),
+            Frame::If => Node::If(next(), next(), next()),
+            Frame::Lambda(arity) => Node::Lambda(arity, next()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Diagnostic {
+    // positions are token indices
+    MissingOperands {
+        operator: String,
+        expected: usize,
+        actual: usize,
+        position: usize,
+    },
+    UnexpectedToken {
+        token: Token,
+        position: usize,
+    },
+    UnexpectedEndOfInput {
+        position: usize,
+    },
+    UnknownOperator {
+        operator: String,
+        position: usize,
+    },
+    // tokens left over after a complete program
+    TrailingTokens {
+        count: usize,
+        position: usize,
+    },
+}
+
+impl Diagnostic {
+    // errors make the program unparseable, warnings leave a usable tree
+    pub fn is_error(&self) -> bool {
+        !matches!(
+            self,
+            Diagnostic::UnknownOperator { .. } | Diagnostic::TrailingTokens { .. }
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = if self.is_error() { "error" } else { "warning" };
+        write!(f, "{}: ", severity)?;
+        match self {
+            Diagnostic::MissingOperands {
+                operator,
+                expected,
+                actual,
+                position,
+            } => write!(
+                f,
+                "`{}` expects {} operand{}, got {} at token {}",
+                operator,
+                expected,
+                if *expected == 1 { "" } else { "s" },
+                actual,
+                position
+            ),
+            Diagnostic::UnexpectedToken { token, position } => {
+                write!(f, "unexpected token {:?} at token {}", token, position)
+            }
+            Diagnostic::UnexpectedEndOfInput { position } => {
+                write!(f, "unexpected end of input at token {}", position)
+            }
+            Diagnostic::UnknownOperator { operator, position } => {
+                write!(f, "unknown operator `{}` at token {}", operator, position)
+            }
+            Diagnostic::TrailingTokens { count, position } => write!(
+                f,
+                "{} trailing token{} after the program at token {}",
+                count,
+                if *count == 1 { "" } else { "s" },
+                position
+            ),
+        }
+    }
+}
+
+// stands in for a missing or unparseable operand when recovering
+pub const PLACEHOLDER: &str = "<missing>";
+
+fn placeholder() -> Node {
+    Node::String(PLACEHOLDER.to_string())
+}
+
 pub struct Parser<'a> {
     tokens: &'a [Token],
     position: usize,
+    // insert placeholders for missing or unexpected operands instead of failing
+    recover: bool,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
@@ -167,82 +387,141 @@ impl<'a> Parser<'a> {
         Self {
             tokens,
             position: 0,
+            recover: false,
+            diagnostics: vec![],
         }
     }
 
-    pub fn parse(&mut self) -> Node {
-        self.parse_node()
+    pub fn with_recovery(mut self) -> Self {
+        self.recover = true;
+        self
     }
 
-    fn parse_node(&mut self) -> Node {
-        match &self.tokens[self.position] {
-            Token::Integer(value) => {
-                self.position += 1;
-                Node::Integer(value.clone())
-            }
-            Token::String(ref value) => {
-                self.position += 1;
-                Node::String(value.clone())
-            }
-            Token::Boolean(value) => {
-                self.position += 1;
-                Node::Boolean(value.clone())
-            }
-            Token::Variable(value) => {
-                self.position += 1;
-                Node::Variable(value.clone())
-            }
-            Token::UnaryOperator(_) => self.parse_unary(),
-            Token::BinaryOperator(_) => self.parse_binary(),
-            Token::If => self.parse_if(),
-            Token::Lambda(_) => self.parse_lambda(),
-            _ => panic!("Expected integer or unary operator"),
-        }
+    // problems found by the last call to parse, in the order they were found
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
     }
 
-    fn parse_unary(&mut self) -> Node {
-        let operator = match self.tokens[self.position] {
-            Token::UnaryOperator(ref operator) => operator.clone(),
-            _ => panic!("Expected unary operator"),
-        };
-        self.position += 1;
-        let operand = Box::new(self.parse_node());
-        Node::UnaryOperator(operator, operand)
+    pub fn parse(&mut self) -> Result<Node, EvalError> {
+        self.diagnostics.clear();
+        let node = self.parse_node()?;
+        if self.position < self.tokens.len() {
+            self.diagnostics.push(Diagnostic::TrailingTokens {
+                count: self.tokens.len() - self.position,
+                position: self.position,
+            });
+        }
+        Ok(node)
     }
 
-    fn parse_binary(&mut self) -> Node {
-        let operator = match self.tokens[self.position] {
-            Token::BinaryOperator(ref operator) => operator.clone(),
-            _ => panic!("Expected binary operator"),
-        };
-        self.position += 1;
-        let left = Box::new(self.parse_node());
-        let right = Box::new(self.parse_node());
-        Node::BinaryOperator(operator, left, right)
+    // parse with an explicit stack of pending operators instead of recursion,
+    // so that arbitrarily deep programs can be parsed
+    fn parse_node(&mut self) -> Result<Node, EvalError> {
+        // operators waiting for operands, with their token index
+        let mut stack: Vec<(Frame, Vec<Node>, usize)> = vec![];
+        loop {
+            let Some(token) = self.tokens.get(self.position) else {
+                return self.finish(stack);
+            };
+            let frame = match token {
+                Token::UnaryOperator(operator) => {
+                    self.check_operator(UnaryOp::parse(operator).is_some());
+                    Some(Frame::Unary(operator.clone()))
+                }
+                Token::BinaryOperator(operator) => {
+                    self.check_operator(BinaryOp::parse(operator).is_some());
+                    Some(Frame::Binary(operator.clone()))
+                }
+                Token::If => Some(Frame::If),
+                Token::Lambda(arity) => Some(Frame::Lambda(arity.clone())),
+                _ => None,
+            };
+            if let Some(frame) = frame {
+                stack.push((frame, vec![], self.position));
+                self.position += 1;
+                continue;
+            }
+            let mut node = match token {
+                Token::Integer(value) => Node::Integer(value.clone()),
+                Token::String(value) => Node::String(value.clone()),
+                Token::Boolean(value) => Node::Boolean(*value),
+                Token::Variable(value) => Node::Variable(value.clone()),
+                token => {
+                    self.diagnostics.push(Diagnostic::UnexpectedToken {
+                        token: token.clone(),
+                        position: self.position,
+                    });
+                    if !self.recover {
+                        return Err(EvalError::UnexpectedToken {
+                            token: token.clone(),
+                            position: self.position,
+                        });
+                    }
+                    placeholder()
+                }
+            };
+            self.position += 1;
+            // hand the finished node to the operators waiting for it
+            loop {
+                let Some((frame, mut operands, position)) = stack.pop() else {
+                    return Ok(node);
+                };
+                operands.push(node);
+                if operands.len() < frame.arity() {
+                    stack.push((frame, operands, position));
+                    break;
+                }
+                node = frame.build(operands);
+            }
+        }
     }
 
-    fn parse_if(&mut self) -> Node {
-        self.position += 1;
-        let condition = Box::new(self.parse_node());
-        let then_branch = Box::new(self.parse_node());
-        let else_branch = Box::new(self.parse_node());
-        Node::If(condition, then_branch, else_branch)
+    fn check_operator(&mut self, known: bool) {
+        if !known {
+            self.diagnostics.push(Diagnostic::UnknownOperator {
+                operator: self.tokens[self.position].to_string(),
+                position: self.position,
+            });
+        }
     }
 
-    fn parse_lambda(&mut self) -> Node {
-        let arity = match &self.tokens[self.position] {
-            Token::Lambda(arity) => arity.clone(),
-            _ => panic!("Expected lambda"),
-        };
-        self.position += 1;
-        let body = Box::new(self.parse_node());
-        Node::Lambda(arity, body)
+    // the input ended: report the operators that lack operands, innermost first
+    fn finish(&mut self, mut stack: Vec<(Frame, Vec<Node>, usize)>) -> Result<Node, EvalError> {
+        if stack.is_empty() {
+            self.diagnostics.push(Diagnostic::UnexpectedEndOfInput {
+                position: self.position,
+            });
+        }
+        for (depth, (frame, operands, position)) in stack.iter().rev().enumerate() {
+            // outer operators count the unfinished operand below them
+            let actual = operands.len() + (depth > 0) as usize;
+            if actual < frame.arity() {
+                self.diagnostics.push(Diagnostic::MissingOperands {
+                    operator: self.tokens[*position].to_string(),
+                    expected: frame.arity(),
+                    actual,
+                    position: *position,
+                });
+            }
+        }
+        if !self.recover {
+            return Err(EvalError::UnexpectedEndOfInput {
+                position: self.position,
+            });
+        }
+        let mut node = None;
+        while let Some((frame, mut operands, _)) = stack.pop() {
+            operands.extend(node.take());
+            operands.resize_with(frame.arity(), placeholder);
+            node = Some(frame.build(operands));
+        }
+        Ok(node.unwrap_or_else(placeholder))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::icfp::transpiler::Transpiler;
+    use crate::icfp::{tokenizer::Tokenizer, transpiler::Transpiler};
 
     use super::*;
 
@@ -253,7 +532,7 @@ mod tests {
             Token::Integer(BigInt::from(3)),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse_unary();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::UnaryOperator("-".to_string(), Box::new(Node::Integer(BigInt::from(3))))
@@ -268,7 +547,7 @@ mod tests {
             Token::Integer(BigInt::from(3)),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse_unary();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::UnaryOperator(
@@ -289,7 +568,7 @@ mod tests {
             Token::Integer(BigInt::from(4)),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse_binary();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::BinaryOperator(
@@ -310,7 +589,7 @@ mod tests {
             Token::Integer(BigInt::from(5)),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse_binary();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::BinaryOperator(
@@ -336,7 +615,7 @@ mod tests {
             Token::String("no".to_string()),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::If(
@@ -354,17 +633,17 @@ mod tests {
     #[test]
     fn test_parse_lambda() {
         let tokens = vec![
-            Token::Lambda(1),
+            Token::Lambda(BigInt::from(1)),
             Token::BinaryOperator("+".to_string()),
             Token::Integer(BigInt::from(1)),
             Token::Integer(BigInt::from(2)),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::Lambda(
-                1,
+                BigInt::from(1),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
                     Box::new(Node::Integer(BigInt::from(1))),
@@ -376,16 +655,16 @@ mod tests {
         let tokens = vec![
             Token::BinaryOperator("$".to_string()),
             Token::BinaryOperator("$".to_string()),
-            Token::Lambda(2),
-            Token::Lambda(3),
-            Token::Variable(2),
+            Token::Lambda(BigInt::from(2)),
+            Token::Lambda(BigInt::from(3)),
+            Token::Variable(BigInt::from(2)),
             Token::BinaryOperator(".".to_string()),
             Token::String("Hello".to_string()),
             Token::String(" World!".to_string()),
             Token::Integer(BigInt::from(42)),
         ];
         let mut parser = Parser::new(&tokens);
-        let node = parser.parse();
+        let node = parser.parse().unwrap();
         assert_eq!(
             node,
             Node::BinaryOperator(
@@ -393,8 +672,11 @@ mod tests {
                 Box::new(Node::BinaryOperator(
                     "$".to_string(),
                     Box::new(Node::Lambda(
-                        2,
-                        Box::new(Node::Lambda(3, Box::new(Node::Variable(2))))
+                        BigInt::from(2),
+                        Box::new(Node::Lambda(
+                            BigInt::from(3),
+                            Box::new(Node::Variable(BigInt::from(2)))
+                        ))
                     )),
                     Box::new(Node::BinaryOperator(
                         ".".to_string(),
@@ -406,4 +688,125 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_parse_errors() {
+        let tokens = vec![
+            Token::BinaryOperator("+".to_string()),
+            Token::Integer(BigInt::from(3)),
+        ];
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(
+            parser.parse(),
+            Err(EvalError::UnexpectedEndOfInput { position: 2 })
+        );
+
+        let tokens = vec![
+            Token::UnaryOperator("-".to_string()),
+            Token::Unknown("+".to_string()),
+        ];
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(
+            parser.parse(),
+            Err(EvalError::UnexpectedToken {
+                token: Token::Unknown("+".to_string()),
+                position: 1,
+            })
+        );
+    }
+
+    fn parse_with_recovery(input: &str) -> (Node, Vec<String>) {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        let mut parser = Parser::new(&tokens).with_recovery();
+        let node = parser.parse().unwrap();
+        let diagnostics = parser.diagnostics().iter().map(|d| d.to_string()).collect();
+        (node, diagnostics)
+    }
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let tokens = Tokenizer::new("B+ B* I$").tokenize().unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert_eq!(
+            parser.parse(),
+            Err(EvalError::UnexpectedEndOfInput { position: 3 })
+        );
+        assert_eq!(
+            parser.diagnostics(),
+            &[
+                Diagnostic::MissingOperands {
+                    operator: "B*".to_string(),
+                    expected: 2,
+                    actual: 1,
+                    position: 1,
+                },
+                Diagnostic::MissingOperands {
+                    operator: "B+".to_string(),
+                    expected: 2,
+                    actual: 1,
+                    position: 0,
+                },
+            ]
+        );
+
+        // warnings do not stop the parse
+        let tokens = Tokenizer::new("B@ I# I$ I% S#").tokenize().unwrap();
+        let mut parser = Parser::new(&tokens);
+        assert!(parser.parse().is_ok());
+        assert_eq!(
+            parser.diagnostics(),
+            &[
+                Diagnostic::UnknownOperator {
+                    operator: "B@".to_string(),
+                    position: 0,
+                },
+                Diagnostic::TrailingTokens {
+                    count: 2,
+                    position: 3,
+                },
+            ]
+        );
+        assert!(parser.diagnostics().iter().all(|d| !d.is_error()));
+    }
+
+    #[test]
+    fn test_parse_recovery() {
+        let (node, diagnostics) = parse_with_recovery("B. S# ? B= I# I#");
+        let missing = placeholder().to_string();
+        assert_eq!(
+            node.to_string(),
+            format!("B. S# ? B= I# I# {} {}", missing, missing)
+        );
+        assert_eq!(
+            diagnostics,
+            vec!["error: `?` expects 3 operands, got 1 at token 2"]
+        );
+        match &node {
+            Node::BinaryOperator(_, _, right) => match right.as_ref() {
+                Node::If(_, _, else_branch) => assert_eq!(**else_branch, placeholder()),
+                node => panic!("expected an if, got {:?}", node),
+            },
+            node => panic!("expected a concatenation, got {:?}", node),
+        }
+
+        let (node, diagnostics) = parse_with_recovery("U- + I#");
+        assert_eq!(
+            node,
+            Node::UnaryOperator("-".to_string(), Box::new(placeholder()))
+        );
+        assert_eq!(
+            diagnostics,
+            vec![
+                "error: unexpected token Unknown(\"+\") at token 1",
+                "warning: 1 trailing token after the program at token 2",
+            ]
+        );
+
+        let (node, diagnostics) = parse_with_recovery("");
+        assert_eq!(node, placeholder());
+        assert_eq!(
+            diagnostics,
+            vec!["error: unexpected end of input at token 0"]
+        );
+    }
 }
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use num_bigint::BigInt;
+
+use super::parser::Node;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Counts {
+    pub beta_reductions: usize,
+    pub applications: usize,
+    // bits of the integer operands handled by arithmetic and comparisons
+    pub bigint_work: usize,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.beta_reductions += other.beta_reductions;
+        self.applications += other.applications;
+        self.bigint_work += other.bigint_work;
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == Counts::default()
+    }
+}
+
+// a lambda or an operator of the original program
+#[derive(Debug, Clone)]
+pub struct Site {
+    // L<id> for lambdas, the operator token otherwise, e.g. B+ or ?
+    pub label: String,
+    // index of the node in the token stream of the program
+    pub position: usize,
+    // the innermost lambda around this site
+    pub parent: Option<usize>,
+    pub counts: Counts,
+}
+
+impl Site {
+    fn name(&self) -> String {
+        format!("{}@{}", self.label, self.position)
+    }
+}
+
+// a node of the profiled program, compared by address; the borrow keeps the program in place
+// for as long as the profiler refers to it
+#[derive(Clone, Copy)]
+struct NodeRef<'a>(&'a Node);
+
+impl PartialEq for NodeRef<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for NodeRef<'_> {}
+
+impl Hash for NodeRef<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.0, state)
+    }
+}
+
+// a node of the call tree: `site` was entered from `parent`, or is an operator applied there
+#[derive(Debug, Clone)]
+struct Call {
+    parent: usize,
+    site: usize,
+    weight: usize,
+}
+
+// the call tree starts at this node, which stands for the code outside of any lambda
+const ROOT: usize = 0;
+
+// attributes evaluation cost to the lambdas and operators of a program while it is evaluated
+pub struct Profiler<'a> {
+    profile: Profile,
+    // lambdas are keyed by their body, which is what a closure keeps, and that body can be an
+    // operator itself
+    lambdas: HashMap<NodeRef<'a>, usize>,
+    operators: HashMap<NodeRef<'a>, usize>,
+    children: HashMap<(usize, usize), usize>,
+    // the call tree node of the lambda being evaluated
+    current: usize,
+}
+
+impl<'a> Profiler<'a> {
+    pub fn new(node: &'a Node) -> Profiler<'a> {
+        let mut profiler = Profiler {
+            profile: Profile {
+                sites: vec![],
+                calls: vec![Call {
+                    parent: ROOT,
+                    site: usize::MAX,
+                    weight: 0,
+                }],
+            },
+            lambdas: HashMap::new(),
+            operators: HashMap::new(),
+            children: HashMap::new(),
+            current: ROOT,
+        };
+        // nodes are visited in prefix order, so the visit count is the token position
+        let mut stack = vec![(node, None)];
+        let mut position = 0;
+        while let Some((node, parent)) = stack.pop() {
+            let label = match node {
+                Node::Lambda(arity, _) => Some(format!("L{}", arity)),
+                Node::UnaryOperator(operator, _) => Some(format!("U{}", operator)),
+                Node::BinaryOperator(operator, _, _) => Some(format!("B{}", operator)),
+                Node::If(_, _, _) => Some("?".to_string()),
+                _ => None,
+            };
+            let mut inner = parent;
+            if let Some(label) = label {
+                let site = profiler.profile.sites.len();
+                match node {
+                    Node::Lambda(_, body) => {
+                        profiler.lambdas.insert(NodeRef(body), site);
+                        inner = Some(site);
+                    }
+                    _ => {
+                        profiler.operators.insert(NodeRef(node), site);
+                    }
+                }
+                profiler.profile.sites.push(Site {
+                    label,
+                    position,
+                    parent,
+                    counts: Counts::default(),
+                });
+            }
+            position += 1;
+            match node {
+                Node::Lambda(_, body) | Node::UnaryOperator(_, body) => stack.push((body, inner)),
+                Node::BinaryOperator(_, left, right) => {
+                    stack.push((right, inner));
+                    stack.push((left, inner));
+                }
+                Node::If(condition, then_branch, else_branch) => {
+                    stack.push((else_branch, inner));
+                    stack.push((then_branch, inner));
+                    stack.push((condition, inner));
+                }
+                _ => {}
+            }
+        }
+        profiler
+    }
+
+    // the application `redex` entered a closure whose lambda has this body; a tail call replaces
+    // the caller on the call stack, otherwise the callee is pushed. returns the call tree node to
+    // go back to once the body is evaluated
+    pub fn enter(&mut self, redex: &Node, body: &Node, tail_call: bool) -> usize {
+        self.record_application(redex, &[]);
+        let caller = self.current;
+        let Some(&site) = self.lambdas.get(&NodeRef(body)) else {
+            return caller;
+        };
+        self.profile.sites[site].counts.beta_reductions += 1;
+        let parent = if tail_call {
+            self.profile.calls[caller].parent
+        } else {
+            caller
+        };
+        self.current = self.call(parent, site);
+        self.profile.calls[self.current].weight += 1;
+        caller
+    }
+
+    // the body entered by `enter` is evaluated, return to the caller it reported
+    pub fn leave(&mut self, caller: usize) {
+        self.current = caller;
+    }
+
+    // an operator or an if was applied, with these integer operands
+    pub fn record_application(&mut self, node: &Node, operands: &[&BigInt]) {
+        let Some(&site) = self.operators.get(&NodeRef(node)) else {
+            return;
+        };
+        let counts = &mut self.profile.sites[site].counts;
+        counts.applications += 1;
+        counts.bigint_work += operands
+            .iter()
+            .map(|operand| operand.bits() as usize)
+            .sum::<usize>();
+        let call = self.call(self.current, site);
+        self.profile.calls[call].weight += 1;
+    }
+
+    fn call(&mut self, parent: usize, site: usize) -> usize {
+        let calls = &mut self.profile.calls;
+        *self.children.entry((parent, site)).or_insert_with(|| {
+            calls.push(Call {
+                parent,
+                site,
+                weight: 0,
+            });
+            calls.len() - 1
+        })
+    }
+
+    pub fn finish(self) -> Profile {
+        self.profile
+    }
+}
+
+// the counts collected by a profiler
+pub struct Profile {
+    sites: Vec<Site>,
+    calls: Vec<Call>,
+}
+
+impl Profile {
+    pub fn sites(&self) -> &[Site] {
+        &self.sites
+    }
+
+    // the cost of each lambda, including the operators directly inside its body;
+    // the last entry, labelled <top>, collects operators outside of any lambda
+    pub fn lambda_counts(&self) -> Vec<(String, Counts)> {
+        let mut totals = vec![Counts::default(); self.sites.len() + 1];
+        for (index, site) in self.sites.iter().enumerate() {
+            let owner = if site.label.starts_with('L') {
+                index
+            } else {
+                site.parent.unwrap_or(self.sites.len())
+            };
+            totals[owner].add(&site.counts);
+        }
+        let mut result = vec![];
+        for (index, counts) in totals.into_iter().enumerate() {
+            let name = match self.sites.get(index) {
+                Some(site) if site.label.starts_with('L') => site.name(),
+                Some(_) => continue,
+                None => "<top>".to_string(),
+            };
+            if !counts.is_empty() {
+                result.push((name, counts));
+            }
+        }
+        result.sort_by_key(|(_, counts)| {
+            std::cmp::Reverse((
+                counts.beta_reductions + counts.applications,
+                counts.bigint_work,
+            ))
+        });
+        result
+    }
+
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{:>12} {:>12} {:>14}  {}\n",
+            "betas", "operations", "bigint bits", "lambda"
+        );
+        for (name, counts) in self.lambda_counts() {
+            report += &format!(
+                "{:>12} {:>12} {:>14}  {}\n",
+                counts.beta_reductions, counts.applications, counts.bigint_work, name
+            );
+        }
+        report
+    }
+
+    // one line per call stack in the folded stack format of flamegraph.pl and inferno, built
+    // from the lambdas entered at run time and weighted by beta reductions plus operator
+    // applications; a tail call replaces its caller, like it does on a real stack
+    pub fn folded(&self) -> String {
+        let mut lines = vec![];
+        for (index, call) in self.calls.iter().enumerate() {
+            if call.weight == 0 {
+                continue;
+            }
+            let mut stack = vec![];
+            let mut current = index;
+            while current != ROOT {
+                stack.push(self.sites[self.calls[current].site].name());
+                current = self.calls[current].parent;
+            }
+            if stack.is_empty() {
+                stack.push("<top>".to_string());
+            }
+            stack.reverse();
+            lines.push(format!("{} {}\n", stack.join(";"), call.weight));
+        }
+        lines.concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::{lazy_evaluator::LazyEvaluator, parser::Parser, tokenizer::Tokenizer};
+
+    use super::*;
+
+    fn profile(input: &str) -> Profile {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let mut evaluator = LazyEvaluator::call_by_name(&node).with_profiler();
+        evaluator.evaluate().unwrap();
+        evaluator.take_profile().unwrap()
+    }
+
+    #[test]
+    fn test_sites() {
+        let tokens = Tokenizer::new("B$ L# B+ v# I# I$").tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let profile = Profiler::new(&node).finish();
+        let sites: Vec<_> = profile
+            .sites()
+            .iter()
+            .map(|site| (site.label.as_str(), site.position, site.parent))
+            .collect();
+        assert_eq!(
+            sites,
+            vec![("B$", 0, None), ("L2", 1, None), ("B+", 2, Some(1))]
+        );
+    }
+
+    #[test]
+    fn test_profile() {
+        // the Y combinator example from the language notes
+        let profile = profile("B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%");
+        let counts = profile.lambda_counts();
+        let total: usize = counts.iter().map(|(_, c)| c.beta_reductions).sum();
+        assert_eq!(total, 109);
+        // the recursive step L# at token 17 does most of the work
+        assert_eq!(counts[0].0, "L2@17");
+        assert!(counts[0].1.applications > 0);
+        assert!(counts[0].1.bigint_work > 0);
+        // every beta reduction is also an application at its redex
+        let applied: usize = profile
+            .sites()
+            .iter()
+            .filter(|site| site.label == "B$")
+            .map(|site| site.counts.applications)
+            .sum();
+        assert_eq!(applied, total);
+
+        let report = profile.report();
+        assert!(report.lines().nth(1).unwrap().ends_with("L2@17"));
+
+        // stacks follow the calls at run time: the recursive step is called from the doubling
+        // lambda L$, which calls itself through it, while lexically they are unrelated
+        let folded = profile.folded();
+        assert!(folded
+            .lines()
+            .any(|line| line.starts_with("L3@24;L2@17;?@18 ")));
+        assert!(folded
+            .lines()
+            .any(|line| line.starts_with("L3@24;L3@24;L2@17;B-@32 ")));
+        let weight: usize = folded
+            .lines()
+            .map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        let applications: usize = counts.iter().map(|(_, c)| c.applications).sum();
+        assert_eq!(weight, total + applications);
+    }
+
+    #[test]
+    fn test_profile_tail_calls() {
+        // a loop counting down in tail position keeps a flat stack
+        let profile = profile("B$ B$ L# B$ v# v# L# L$ ? B= v$ I! S B! B$ v# v# B- v$ I\" I+");
+        let folded = profile.folded();
+        let depth = folded
+            .lines()
+            .map(|line| line.split(';').count())
+            .max()
+            .unwrap();
+        assert!(depth <= 3, "{}", folded);
+    }
+}
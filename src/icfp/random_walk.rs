@@ -0,0 +1,328 @@
+use std::time::{Duration, Instant};
+
+use super::builder::{
+    app, app_by_value, concat, drop, if_, int, lam, lt, mul, rem, self_apply, string, sub, take,
+};
+use super::error::EvalError;
+use super::evaluator::{Evaluator, Strategy, BETA_REDUCTION_LIMIT};
+use super::optimizer::renumber;
+use super::parser::Node;
+use super::util::BASE94;
+
+// the longest path the server accepts
+pub const MAX_MOVES: usize = 1_000_000;
+// a full search simulates millions of moves for every seed, give up after this long
+const TIME_LIMIT: Duration = Duration::from_secs(60);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+const MOVES: [char; 4] = ['U', 'D', 'L', 'R'];
+// Lehmer generators modulo the prime 2^31 - 1, the multipliers are primitive roots
+const MODULUS: u64 = 2_147_483_647;
+const MULTIPLIERS: [u64; 4] = [16807, 48271, 69621, 39373];
+
+// a lambdaman grid: walls are #, pills are . and lambdaman starts at L
+pub struct Grid {
+    width: usize,
+    height: usize,
+    walls: Vec<bool>,
+    pills: Vec<bool>,
+    start: usize,
+}
+
+impl Grid {
+    pub fn parse(text: &str) -> Option<Grid> {
+        let lines: Vec<&str> = text.lines().filter(|line| !line.is_empty()).collect();
+        let width = lines.first()?.len();
+        let height = lines.len();
+        let mut walls = vec![];
+        let mut pills = vec![];
+        let mut start = None;
+        for line in lines {
+            if line.len() != width {
+                return None;
+            }
+            for c in line.chars() {
+                match c {
+                    '#' => {}
+                    '.' => {}
+                    'L' => start = Some(walls.len()),
+                    _ => return None,
+                }
+                walls.push(c == '#');
+                pills.push(c == '.');
+            }
+        }
+        Some(Grid {
+            width,
+            height,
+            walls,
+            pills,
+            start: start?,
+        })
+    }
+
+    // the number of moves after which every pill is eaten, moving into a wall or off the
+    // grid leaves lambdaman in place
+    pub fn simulate(&self, moves: impl Iterator<Item = char>) -> Option<usize> {
+        let mut pills = self.pills.clone();
+        let mut left = pills.iter().filter(|&&pill| pill).count();
+        let mut position = self.start;
+        if left == 0 {
+            return Some(0);
+        }
+        for (count, c) in moves.enumerate() {
+            let (row, column) = (position / self.width, position % self.width);
+            let next = match c {
+                'U' if row > 0 => position - self.width,
+                'D' if row + 1 < self.height => position + self.width,
+                'L' if column > 0 => position - 1,
+                'R' if column + 1 < self.width => position + 1,
+                _ => position,
+            };
+            if self.walls[next] {
+                continue;
+            }
+            position = next;
+            if pills[position] {
+                pills[position] = false;
+                left -= 1;
+                if left == 0 {
+                    return Some(count + 1);
+                }
+            }
+        }
+        None
+    }
+}
+
+// a walk taking `steps` random directions, each `repeat` times in a row: the direction is
+// the state modulo 4 and the state is multiplied by `multiplier` modulo MODULUS
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Walk {
+    pub multiplier: u64,
+    pub seed: u64,
+    pub repeat: usize,
+    pub steps: usize,
+}
+
+impl Walk {
+    pub fn moves(&self) -> impl Iterator<Item = char> + '_ {
+        let mut state = self.seed;
+        (0..self.steps).flat_map(move |_| {
+            let c = MOVES[(state % 4) as usize];
+            state = state * self.multiplier % MODULUS;
+            std::iter::repeat_n(c, self.repeat)
+        })
+    }
+
+    // B. S<solve lambdamanN > B$ B$ B$ L! B$ v! v! L<walk> L<state> L<steps> ... seed steps,
+    // where the walk calls itself by value so states are not recomputed
+    pub fn program(&self, problem: usize) -> Node {
+        let (multiplier, repeat) = (self.multiplier, self.repeat);
+        let moves: String = MOVES
+            .iter()
+            .flat_map(|&c| std::iter::repeat_n(c, repeat))
+            .collect();
+        let walk = self_apply(|walk| {
+            lam(|state| {
+                lam(|steps| {
+                    let direction = rem(state.clone(), int(4));
+                    let offset = match repeat {
+                        1 => direction,
+                        _ => mul(direction, int(repeat)),
+                    };
+                    let next = rem(mul(state, int(multiplier)), int(MODULUS));
+                    let rest = app_by_value(app_by_value(walk, next), sub(steps.clone(), int(1)));
+                    if_(
+                        lt(steps, int(1)),
+                        string(""),
+                        concat(take(int(repeat), drop(offset, string(&moves))), rest),
+                    )
+                })
+            })
+        });
+        let program = concat(
+            string(&format!("solve lambdaman{} ", problem)),
+            app(app(walk, int(self.seed)), int(self.steps)),
+        );
+        renumber(&program.build())
+    }
+
+    // evaluates the program for a few steps against `moves` and extrapolates the beta
+    // reductions of the whole walk, which grow by the same amount with every step
+    pub fn check(&self, problem: usize) -> Result<usize, EvalError> {
+        let short = |steps: usize| -> Result<usize, EvalError> {
+            let walk = Walk { steps, ..*self };
+            let mut evaluator =
+                Evaluator::with_strategy(walk.program(problem), Strategy::CallByName);
+            let expected: String = format!("solve lambdaman{} ", problem)
+                .chars()
+                .chain(walk.moves())
+                .collect();
+            match evaluator.evaluate()? {
+                Node::String(ref result) if *result == expected => Ok(evaluator.beta_reductions()),
+                result => Err(EvalError::UnexpectedResult {
+                    expected: "the simulated walk".to_string(),
+                    actual: result.name(),
+                }),
+            }
+        };
+        let (first, second) = (short(100)?, short(200)?);
+        let per_step = (second - first) / 100;
+        let total = first + per_step * self.steps.saturating_sub(100);
+        if total > BETA_REDUCTION_LIMIT {
+            return Err(EvalError::BetaReductionLimitExceeded {
+                limit: BETA_REDUCTION_LIMIT,
+            });
+        }
+        Ok(total)
+    }
+}
+
+// tries every repeat, multiplier and seed in turn with the native simulator, and returns
+// the first walk that eats every pill within the move limit; progress is reported on stderr
+// every few seconds
+pub struct Searcher<'a> {
+    grid: &'a Grid,
+    max_moves: usize,
+    max_seed: u64,
+    repeats: Vec<usize>,
+    time_limit: Duration,
+}
+
+impl<'a> Searcher<'a> {
+    pub fn new(grid: &'a Grid) -> Searcher<'a> {
+        Searcher {
+            grid,
+            max_moves: MAX_MOVES,
+            // seeds below 94^2 encode in two characters
+            max_seed: (BASE94 * BASE94 - 1) as u64,
+            repeats: vec![1, 2, 3],
+            time_limit: TIME_LIMIT,
+        }
+    }
+
+    pub fn with_max_moves(mut self, max_moves: usize) -> Searcher<'a> {
+        self.max_moves = max_moves;
+        self
+    }
+
+    pub fn with_max_seed(mut self, max_seed: u64) -> Searcher<'a> {
+        self.max_seed = max_seed;
+        self
+    }
+
+    pub fn with_repeats(mut self, repeats: &[usize]) -> Searcher<'a> {
+        self.repeats = repeats.to_vec();
+        self
+    }
+
+    // the search returns None once it has run this long
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Searcher<'a> {
+        self.time_limit = time_limit;
+        self
+    }
+
+    pub fn search(&self) -> Option<Walk> {
+        let start = Instant::now();
+        let mut reported = start;
+        for &repeat in &self.repeats {
+            for multiplier in MULTIPLIERS {
+                for seed in 1..=self.max_seed {
+                    if start.elapsed() > self.time_limit {
+                        eprintln!("no walk found within {:?}", self.time_limit);
+                        return None;
+                    }
+                    if reported.elapsed() > PROGRESS_INTERVAL {
+                        eprintln!(
+                            "repeat {}, multiplier {}, seed {} of {}, {:.0?} elapsed",
+                            repeat,
+                            multiplier,
+                            seed,
+                            self.max_seed,
+                            start.elapsed()
+                        );
+                        reported = Instant::now();
+                    }
+                    let walk = Walk {
+                        multiplier,
+                        seed,
+                        repeat,
+                        steps: self.max_moves / repeat,
+                    };
+                    if let Some(moves) = self.grid.simulate(walk.moves()) {
+                        // stop the walk at the step that eats the last pill
+                        return Some(Walk {
+                            steps: moves.div_ceil(repeat),
+                            ..walk
+                        });
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate() {
+        let grid = Grid::parse("###.#...\n...L..##\n.#######\n").unwrap();
+        let path = "LLLDURRRUDRRURR";
+        assert_eq!(grid.simulate(path.chars()), Some(path.len()));
+        assert_eq!(grid.simulate("LLL".chars()), None);
+        // walls and the border stop lambdaman
+        assert_eq!(grid.simulate("DDLLLLLDURRRURDRRRURR".chars()), Some(21));
+        assert!(Grid::parse("#.\n#").is_none());
+    }
+
+    #[test]
+    fn test_search_time_limit() {
+        // the pill on the right is walled in
+        let grid = Grid::parse("L..#.\n...##\n").unwrap();
+        let start = Instant::now();
+        let searcher = Searcher::new(&grid).with_time_limit(Duration::from_millis(200));
+        assert_eq!(searcher.search(), None);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_search() {
+        let text = "\
+#########
+#.......#
+#.#.#.#.#
+#...L...#
+#.#.#.#.#
+#.......#
+#########";
+        let grid = Grid::parse(text).unwrap();
+        let walk = Searcher::new(&grid).with_max_moves(2000).search().unwrap();
+        assert!(walk.steps * walk.repeat <= 2000);
+        let moves: String = walk.moves().collect();
+        assert_eq!(grid.simulate(moves.chars()), Some(moves.len()));
+        assert!(walk.check(3).unwrap() < BETA_REDUCTION_LIMIT);
+
+        let walk = Walk {
+            multiplier: 48271,
+            seed: 7,
+            repeat: 2,
+            steps: 50,
+        };
+        let program = walk.program(4);
+        let expected: String = "solve lambdaman4 ".chars().chain(walk.moves()).collect();
+        assert_eq!(
+            Evaluator::with_strategy(program, Strategy::CallByName).evaluate(),
+            Ok(Node::String(expected))
+        );
+        // a million steps need fewer beta reductions than the server allows
+        let walk = Walk {
+            steps: MAX_MOVES,
+            repeat: 1,
+            ..walk
+        };
+        assert!(walk.check(4).is_ok());
+    }
+}
@@ -0,0 +1,699 @@
+use num_bigint::BigInt;
+
+use super::builtin::y_combinator;
+use super::error::EvalError;
+use super::parser::Node;
+use super::util::STRING_ASCII;
+
+// a small language with named variables that compiles to ICFP:
+//
+//   let rec fact n = if n == 0 then 1 else n * fact (n - 1) in
+//   let greet = fn name -> "Hello " ++ name in   # comments run to the end of the line
+//   greet (int_to_string (fact 5))
+//
+// application is call-by-name `$`, `++` concatenates strings, and `take`, `drop`,
+// `string_to_int` and `int_to_string` are the ICFP string primitives
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Integer(BigInt),
+    String(String),
+    Boolean(bool),
+    Name(String),
+    Lambda(Vec<String>, Box<Expr>),
+    Apply(Box<Expr>, Box<Expr>),
+    // operators are the ICFP ones, e.g. `.` for `++` and `=` for `==`
+    Unary(String, Box<Expr>),
+    Binary(String, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Let(String, Box<Expr>, Box<Expr>),
+    // let rec name parameters = value in body
+    LetRec(String, Vec<String>, Box<Expr>, Box<Expr>),
+}
+
+// name, ICFP operator and number of arguments of the primitive functions
+const BUILTINS: [(&str, &str, usize); 4] = [
+    ("take", "T", 2),
+    ("drop", "D", 2),
+    ("string_to_int", "#", 1),
+    ("int_to_string", "$", 1),
+];
+
+const KEYWORDS: [&str; 9] = [
+    "let", "rec", "in", "fn", "if", "then", "else", "true", "false",
+];
+
+// longer symbols first, so that `->` is not read as `-`
+const SYMBOLS: [&str; 19] = [
+    "->", "++", "==", "!=", "<=", ">=", "&&", "||", "(", ")", "=", "+", "-", "*", "/", "%", "<",
+    ">", "!",
+];
+
+#[derive(Debug, PartialEq, Clone)]
+enum Lexeme {
+    Integer(BigInt),
+    String(String),
+    Name(String),
+    Symbol(&'static str),
+    End,
+}
+
+struct Lexed {
+    lexeme: Lexeme,
+    line: usize,
+    column: usize,
+}
+
+fn syntax_error(message: String, line: usize, column: usize) -> EvalError {
+    EvalError::SyntaxError {
+        message,
+        line,
+        column,
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<Lexed>, EvalError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut lexemes = vec![];
+    let (mut index, mut line, mut column) = (0, 1, 1);
+    while index < chars.len() {
+        let c = chars[index];
+        let (start_line, start_column) = (line, column);
+        let start = index;
+        let lexeme = match c {
+            '\n' => {
+                index += 1;
+                line += 1;
+                column = 1;
+                continue;
+            }
+            c if c.is_whitespace() => {
+                index += 1;
+                column += 1;
+                continue;
+            }
+            '#' => {
+                while index < chars.len() && chars[index] != '\n' {
+                    index += 1;
+                }
+                continue;
+            }
+            '0'..='9' => {
+                while index < chars.len() && chars[index].is_ascii_digit() {
+                    index += 1;
+                }
+                let digits: String = chars[start..index].iter().collect();
+                Lexeme::Integer(digits.parse().unwrap())
+            }
+            'a'..='z' | 'A'..='Z' | '_' => {
+                while index < chars.len()
+                    && (chars[index].is_ascii_alphanumeric() || matches!(chars[index], '_' | '\''))
+                {
+                    index += 1;
+                }
+                Lexeme::Name(chars[start..index].iter().collect())
+            }
+            '"' => {
+                index += 1;
+                let mut value = String::new();
+                loop {
+                    let c = match chars.get(index) {
+                        Some('"') => break,
+                        Some('\\') => {
+                            index += 1;
+                            match chars.get(index) {
+                                Some('n') => '\n',
+                                Some(&c @ ('"' | '\\')) => c,
+                                _ => {
+                                    return Err(syntax_error(
+                                        "unknown escape sequence".to_string(),
+                                        line,
+                                        column + index - start,
+                                    ))
+                                }
+                            }
+                        }
+                        Some(&c) => c,
+                        None => {
+                            return Err(syntax_error(
+                                "unterminated string".to_string(),
+                                start_line,
+                                start_column,
+                            ))
+                        }
+                    };
+                    if !STRING_ASCII.contains(c) {
+                        return Err(syntax_error(
+                            format!("{:?} cannot be written in an ICFP string", c),
+                            line,
+                            column + index - start,
+                        ));
+                    }
+                    value.push(c);
+                    index += 1;
+                }
+                index += 1;
+                Lexeme::String(value)
+            }
+            _ => {
+                let rest: String = chars[index..chars.len().min(index + 2)].iter().collect();
+                match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+                    Some(symbol) => {
+                        index += symbol.len();
+                        Lexeme::Symbol(symbol)
+                    }
+                    None => {
+                        return Err(syntax_error(
+                            format!("unexpected character {:?}", c),
+                            line,
+                            column,
+                        ))
+                    }
+                }
+            }
+        };
+        column += index - start;
+        lexemes.push(Lexed {
+            lexeme,
+            line: start_line,
+            column: start_column,
+        });
+    }
+    lexemes.push(Lexed {
+        lexeme: Lexeme::End,
+        line,
+        column,
+    });
+    Ok(lexemes)
+}
+
+pub struct SurfaceParser {
+    lexemes: Vec<Lexed>,
+    position: usize,
+    // names bound around the current expression, to report unbound names where they appear
+    scope: Vec<String>,
+}
+
+impl SurfaceParser {
+    pub fn new(source: &str) -> Result<SurfaceParser, EvalError> {
+        Ok(SurfaceParser {
+            lexemes: lex(source)?,
+            position: 0,
+            scope: vec![],
+        })
+    }
+
+    pub fn parse(&mut self) -> Result<Expr, EvalError> {
+        let expr = self.parse_expr()?;
+        if self.peek() != &Lexeme::End {
+            return Err(self.unexpected("the end of the program"));
+        }
+        Ok(expr)
+    }
+
+    fn peek(&self) -> &Lexeme {
+        &self.lexemes[self.position].lexeme
+    }
+
+    fn advance(&mut self) -> Lexeme {
+        let lexeme = self.peek().clone();
+        if lexeme != Lexeme::End {
+            self.position += 1;
+        }
+        lexeme
+    }
+
+    fn unexpected(&self, expected: &str) -> EvalError {
+        let lexed = &self.lexemes[self.position];
+        let found = match &lexed.lexeme {
+            Lexeme::Integer(value) => value.to_string(),
+            Lexeme::String(value) => format!("{:?}", value),
+            Lexeme::Name(name) => name.clone(),
+            Lexeme::Symbol(symbol) => symbol.to_string(),
+            Lexeme::End => "the end of the program".to_string(),
+        };
+        syntax_error(
+            format!("expected {}, found {}", expected, found),
+            lexed.line,
+            lexed.column,
+        )
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Lexeme::Name(name) if name == keyword)
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), EvalError> {
+        if !self.is_keyword(keyword) {
+            return Err(self.unexpected(&format!("`{}`", keyword)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), EvalError> {
+        if self.peek() != &Lexeme::Symbol(symbol_of(symbol)) {
+            return Err(self.unexpected(&format!("`{}`", symbol)));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn name(&mut self) -> Result<String, EvalError> {
+        match self.peek() {
+            Lexeme::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    // names up to, but not including, the symbol that ends them
+    fn parameters(&mut self) -> Result<Vec<String>, EvalError> {
+        let mut parameters = vec![];
+        while matches!(self.peek(), Lexeme::Name(_)) {
+            parameters.push(self.name()?);
+        }
+        Ok(parameters)
+    }
+
+    // parse with the names bound for the duration of `parse`
+    fn scoped(
+        &mut self,
+        names: &[String],
+        parse: impl FnOnce(&mut Self) -> Result<Expr, EvalError>,
+    ) -> Result<Expr, EvalError> {
+        self.scope.extend(names.iter().cloned());
+        let result = parse(self);
+        self.scope.truncate(self.scope.len() - names.len());
+        result
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr, EvalError> {
+        if self.is_keyword("let") {
+            self.advance();
+            let recursive = self.is_keyword("rec");
+            if recursive {
+                self.advance();
+            }
+            let name = self.name()?;
+            let parameters = self.parameters()?;
+            self.expect_symbol("=")?;
+            let mut bound = parameters.clone();
+            if recursive {
+                bound.insert(0, name.clone());
+            }
+            let value = self.scoped(&bound, |parser| parser.parse_expr())?;
+            self.expect_keyword("in")?;
+            let body = self.scoped(std::slice::from_ref(&name), |parser| parser.parse_expr())?;
+            return Ok(match (recursive, parameters.is_empty()) {
+                (true, _) => Expr::LetRec(name, parameters, Box::new(value), Box::new(body)),
+                (false, true) => Expr::Let(name, Box::new(value), Box::new(body)),
+                (false, false) => Expr::Let(
+                    name,
+                    Box::new(Expr::Lambda(parameters, Box::new(value))),
+                    Box::new(body),
+                ),
+            });
+        }
+        if self.is_keyword("fn") {
+            self.advance();
+            let parameters = self.parameters()?;
+            if parameters.is_empty() {
+                return Err(self.unexpected("a parameter"));
+            }
+            self.expect_symbol("->")?;
+            let body = self.scoped(&parameters, |parser| parser.parse_expr())?;
+            return Ok(Expr::Lambda(parameters, Box::new(body)));
+        }
+        if self.is_keyword("if") {
+            self.advance();
+            let condition = self.parse_expr()?;
+            self.expect_keyword("then")?;
+            let then_branch = self.parse_expr()?;
+            self.expect_keyword("else")?;
+            let else_branch = self.parse_expr()?;
+            return Ok(Expr::If(
+                Box::new(condition),
+                Box::new(then_branch),
+                Box::new(else_branch),
+            ));
+        }
+        self.parse_binary(0)
+    }
+
+    // precedence climbing over the infix operators, loosest level first
+    fn parse_binary(&mut self, level: usize) -> Result<Expr, EvalError> {
+        const LEVELS: [&[&str]; 6] = [
+            &["||"],
+            &["&&"],
+            &["==", "!=", "<", ">", "<=", ">="],
+            &["++"],
+            &["+", "-"],
+            &["*", "/", "%"],
+        ];
+        if level == LEVELS.len() {
+            return self.parse_unary();
+        }
+        let mut left = self.parse_binary(level + 1)?;
+        loop {
+            let symbol = match self.peek() {
+                Lexeme::Symbol(symbol) if LEVELS[level].contains(symbol) => *symbol,
+                _ => return Ok(left),
+            };
+            self.advance();
+            // concatenation is right associative, the rest are left associative
+            let right = if symbol == "++" {
+                self.parse_binary(level)?
+            } else {
+                self.parse_binary(level + 1)?
+            };
+            let binary = |operator: &str, left, right| {
+                Expr::Binary(operator.to_string(), Box::new(left), Box::new(right))
+            };
+            let not = |expr| Expr::Unary("!".to_string(), Box::new(expr));
+            left = match symbol {
+                "||" => binary("|", left, right),
+                "&&" => binary("&", left, right),
+                "==" => binary("=", left, right),
+                "!=" => not(binary("=", left, right)),
+                "<=" => not(binary(">", left, right)),
+                ">=" => not(binary("<", left, right)),
+                "++" => binary(".", left, right),
+                operator => binary(operator, left, right),
+            };
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, EvalError> {
+        match self.peek() {
+            Lexeme::Symbol(symbol @ ("-" | "!")) => {
+                let operator = symbol.to_string();
+                self.advance();
+                let operand = self.parse_unary()?;
+                Ok(Expr::Unary(operator, Box::new(operand)))
+            }
+            _ => self.parse_application(),
+        }
+    }
+
+    fn parse_application(&mut self) -> Result<Expr, EvalError> {
+        let mut function = self.parse_atom()?;
+        while self.starts_atom() {
+            let argument = self.parse_atom()?;
+            function = Expr::Apply(Box::new(function), Box::new(argument));
+        }
+        Ok(function)
+    }
+
+    fn starts_atom(&self) -> bool {
+        match self.peek() {
+            Lexeme::Integer(_) | Lexeme::String(_) | Lexeme::Symbol("(") => true,
+            Lexeme::Name(name) => {
+                !KEYWORDS.contains(&name.as_str()) || name == "true" || name == "false"
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Expr, EvalError> {
+        let lexed = &self.lexemes[self.position];
+        let (line, column) = (lexed.line, lexed.column);
+        match self.peek().clone() {
+            Lexeme::Integer(value) => {
+                self.advance();
+                Ok(Expr::Integer(value))
+            }
+            Lexeme::String(value) => {
+                self.advance();
+                Ok(Expr::String(value))
+            }
+            Lexeme::Name(name) if name == "true" || name == "false" => {
+                self.advance();
+                Ok(Expr::Boolean(name == "true"))
+            }
+            Lexeme::Name(name) if !KEYWORDS.contains(&name.as_str()) => {
+                if !self.scope.contains(&name) && builtin(&name).is_none() {
+                    return Err(syntax_error(
+                        format!("unbound name `{}`", name),
+                        line,
+                        column,
+                    ));
+                }
+                self.advance();
+                Ok(Expr::Name(name))
+            }
+            Lexeme::Symbol("(") => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+}
+
+fn symbol_of(symbol: &str) -> &'static str {
+    SYMBOLS
+        .iter()
+        .find(|candidate| **candidate == symbol)
+        .unwrap()
+}
+
+fn builtin(name: &str) -> Option<(&'static str, usize)> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _, _)| *builtin == name)
+        .map(|&(_, operator, arity)| (operator, arity))
+}
+
+// parse and compile a program of the surface language
+pub fn compile_source(source: &str) -> Result<Node, EvalError> {
+    let expr = SurfaceParser::new(source)?.parse()?;
+    Ok(compile(&expr))
+}
+
+pub fn compile(expr: &Expr) -> Node {
+    Compiler { scope: vec![] }.compile(expr)
+}
+
+struct Compiler {
+    // each binder is numbered by its nesting depth, which keeps the ids short
+    scope: Vec<String>,
+}
+
+impl Compiler {
+    fn compile(&mut self, expr: &Expr) -> Node {
+        match expr {
+            Expr::Integer(value) => Node::Integer(value.clone()),
+            Expr::String(value) => Node::String(value.clone()),
+            Expr::Boolean(value) => Node::Boolean(*value),
+            Expr::Name(name) => match self.scope.iter().rposition(|bound| bound == name) {
+                Some(depth) => Node::Variable(BigInt::from(depth)),
+                None => self.compile_application(expr, &[]),
+            },
+            Expr::Lambda(parameters, body) => self.lambda(parameters, body),
+            Expr::Apply(_, _) => {
+                let mut arguments = vec![];
+                let mut function = expr;
+                while let Expr::Apply(left, right) = function {
+                    arguments.push(right.as_ref());
+                    function = left;
+                }
+                arguments.reverse();
+                self.compile_application(function, &arguments)
+            }
+            Expr::Unary(operator, operand) => {
+                Node::UnaryOperator(operator.clone(), Box::new(self.compile(operand)))
+            }
+            Expr::Binary(operator, left, right) => Node::BinaryOperator(
+                operator.clone(),
+                Box::new(self.compile(left)),
+                Box::new(self.compile(right)),
+            ),
+            Expr::If(condition, then_branch, else_branch) => Node::If(
+                Box::new(self.compile(condition)),
+                Box::new(self.compile(then_branch)),
+                Box::new(self.compile(else_branch)),
+            ),
+            Expr::Let(name, value, body) => {
+                let value = self.compile(value);
+                let function = self.lambda(std::slice::from_ref(name), body);
+                apply(function, value)
+            }
+            Expr::LetRec(name, parameters, value, body) => {
+                // Y (λname. λparameters. value)
+                let mut names = vec![name.clone()];
+                names.extend(parameters.iter().cloned());
+                let function = self.lambda(&names, value);
+                let value = apply(y_combinator(), function);
+                let function = self.lambda(std::slice::from_ref(name), body);
+                apply(function, value)
+            }
+        }
+    }
+
+    fn lambda(&mut self, parameters: &[String], body: &Expr) -> Node {
+        let depth = self.scope.len();
+        self.scope.extend(parameters.iter().cloned());
+        let mut node = self.compile(body);
+        self.scope.truncate(depth);
+        for index in (0..parameters.len()).rev() {
+            node = Node::Lambda(BigInt::from(depth + index), Box::new(node));
+        }
+        node
+    }
+
+    // primitives applied to all of their arguments become ICFP operators,
+    // otherwise they are wrapped in lambdas
+    fn compile_application(&mut self, function: &Expr, arguments: &[&Expr]) -> Node {
+        let primitive = match function {
+            Expr::Name(name) if !self.scope.contains(name) => builtin(name),
+            _ => None,
+        };
+        let (mut node, rest) = match primitive {
+            Some((operator, arity)) if arguments.len() >= arity => {
+                let operands: Vec<Node> = arguments[..arity]
+                    .iter()
+                    .map(|argument| self.compile(argument))
+                    .collect();
+                (primitive_node(operator, operands), &arguments[arity..])
+            }
+            Some((operator, arity)) => {
+                let depth = self.scope.len();
+                let operands = (0..arity)
+                    .map(|index| Node::Variable(BigInt::from(depth + index)))
+                    .collect();
+                let mut node = primitive_node(operator, operands);
+                for index in (0..arity).rev() {
+                    node = Node::Lambda(BigInt::from(depth + index), Box::new(node));
+                }
+                (node, arguments)
+            }
+            None => (self.compile(function), arguments),
+        };
+        for argument in rest {
+            node = apply(node, self.compile(argument));
+        }
+        node
+    }
+}
+
+fn primitive_node(operator: &str, mut operands: Vec<Node>) -> Node {
+    let operator = operator.to_string();
+    match operands.len() {
+        1 => Node::UnaryOperator(operator, Box::new(operands.pop().unwrap())),
+        _ => {
+            let right = operands.pop().unwrap();
+            let left = operands.pop().unwrap();
+            Node::BinaryOperator(operator, Box::new(left), Box::new(right))
+        }
+    }
+}
+
+fn apply(function: Node, argument: Node) -> Node {
+    Node::BinaryOperator("$".to_string(), Box::new(function), Box::new(argument))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::icfp::evaluator::Evaluator;
+
+    use super::*;
+
+    fn evaluate(source: &str) -> Node {
+        let node = compile_source(source).unwrap();
+        Evaluator::new(node)
+            .evaluate()
+            .map_err(|e| format!("{}: {}", source, e))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compile() {
+        assert_eq!(
+            compile_source("let x = 3 in fn y -> x + y")
+                .unwrap()
+                .to_string(),
+            "B$ L! L\" B+ v! v\" I$"
+        );
+        assert_eq!(
+            compile_source("1 + 2 * 3 - 4 ++ \"a\" ++ \"b\"").unwrap(),
+            compile_source("((1 + (2 * 3)) - 4) ++ (\"a\" ++ \"b\")").unwrap()
+        );
+        // saturated primitives become operators
+        assert_eq!(
+            compile_source("take 2 \"abc\"").unwrap().to_string(),
+            format!("BT I# {}", Node::String("abc".to_string()).to_string())
+        );
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let cases = [
+            ("1 + 2 * 3", Node::Integer(BigInt::from(7))),
+            ("-7 / 2", Node::Integer(BigInt::from(-3))),
+            (
+                "let rec fact n = if n == 0 then 1 else n * fact (n - 1) in fact 10",
+                Node::Integer(BigInt::from(3628800)),
+            ),
+            (
+                "let greet = fn name -> \"Hello \" ++ name in # a comment\n greet \"World!\"",
+                Node::String("Hello World!".to_string()),
+            ),
+            (
+                "let x = 1 in let f y = x + y in let x = 10 in f x",
+                Node::Integer(BigInt::from(11)),
+            ),
+            (
+                "3 <= 3 && 2 != 3 || false",
+                Node::Boolean(true),
+            ),
+            (
+                "let rec repeat s n = if n == 0 then \"\" else s ++ repeat s (n - 1) in repeat \"ab\" 3",
+                Node::String("ababab".to_string()),
+            ),
+            (
+                "let first = take 1 in first (drop 2 \"hello\")",
+                Node::String("l".to_string()),
+            ),
+            (
+                "int_to_string (string_to_int \"test\")",
+                Node::String("test".to_string()),
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(evaluate(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = [
+            ("let x = 1 in y", "unbound name `y` at line 1, column 14"),
+            (
+                "let f x = x in\nf (1 +",
+                "expected an expression, found the end of the program at line 2, column 7",
+            ),
+            (
+                "if true then 1",
+                "expected `else`, found the end of the program at line 1, column 15",
+            ),
+            (
+                "\"日本\"",
+                "'日' cannot be written in an ICFP string at line 1, column 2",
+            ),
+            ("1 $ 2", "unexpected character '$' at line 1, column 3"),
+            (
+                "1 2 )",
+                "expected the end of the program, found ) at line 1, column 5",
+            ),
+        ];
+        for (source, expected) in cases {
+            let error = compile_source(source).unwrap_err();
+            assert_eq!(error.to_string(), expected, "{}", source);
+        }
+    }
+}
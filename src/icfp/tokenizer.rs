@@ -3,9 +3,9 @@ use std::vec::IntoIter;
 
 use num_bigint::BigInt;
 
+use super::error::EvalError;
 use super::util::{
-    convert_integer, convert_integer_to_bigint, convert_string, deconvert_integer,
-    deconvert_integer_from_bigint, deconvert_string,
+    convert_integer_to_bigint, convert_string, deconvert_integer_from_bigint, deconvert_string,
 };
 
 pub type PeekableIter<T> = Peekable<IntoIter<T>>;
@@ -18,8 +18,8 @@ pub enum Token {
     UnaryOperator(String),
     BinaryOperator(String),
     If,
-    Lambda(usize),
-    Variable(usize),
+    Lambda(BigInt),
+    Variable(BigInt),
     Unknown(String),
 }
 
@@ -38,169 +38,210 @@ impl Token {
             Token::UnaryOperator(value) => format!("U{}", value),
             Token::BinaryOperator(value) => format!("B{}", value),
             Token::If => "?".to_string(),
-            Token::Lambda(value) => format!("L{}", deconvert_integer(*value)),
-            Token::Variable(value) => format!("v{}", deconvert_integer(*value)),
+            Token::Lambda(value) => format!("L{}", deconvert_integer_from_bigint(value.clone())),
+            Token::Variable(value) => format!("v{}", deconvert_integer_from_bigint(value.clone())),
             Token::Unknown(value) => format!("U{}", value),
         }
     }
 }
 
+// byte offsets of a token in the source, `end` is exclusive
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 pub struct Tokenizer {
     input: PeekableIter<char>,
+    position: usize,
+    // byte offset of the token being read
+    start: usize,
+    // reject unknown indicators and bodies that do not fit their indicator
+    strict: bool,
 }
 
 impl Tokenizer {
     pub fn new(input: &str) -> Tokenizer {
         Tokenizer {
             input: input.chars().collect::<Vec<char>>().into_iter().peekable(),
+            position: 0,
+            start: 0,
+            strict: false,
         }
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    pub fn strict(input: &str) -> Tokenizer {
+        Tokenizer {
+            strict: true,
+            ..Tokenizer::new(input)
+        }
+    }
+
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, EvalError> {
+        let tokens = self.tokenize_spanned()?;
+        Ok(tokens.into_iter().map(|spanned| spanned.token).collect())
+    }
+
+    pub fn tokenize_spanned(&mut self) -> Result<Vec<SpannedToken>, EvalError> {
         let mut tokens = vec![];
         while let Some(&c) = self.input.peek() {
-            match c {
-                'I' => tokens.push(self.tokenize_integer()),
-                'T' | 'F' => tokens.push(self.tokenize_boolean()),
-                'S' => tokens.push(self.tokenize_string()),
-                'U' => tokens.push(self.tokenize_unary_operator()),
-                'B' => tokens.push(self.tokenize_binary_operator()),
-                '?' => tokens.push(self.tokenize_if()),
-                'L' => tokens.push(self.tokenize_lambda()),
-                'v' => tokens.push(self.tokenize_variable()),
+            self.start = self.position;
+            let token = match c {
+                'I' => self.tokenize_integer()?,
+                'T' | 'F' => self.tokenize_boolean()?,
+                'S' => self.tokenize_string()?,
+                'U' => self.tokenize_unary_operator()?,
+                'B' => self.tokenize_binary_operator()?,
+                '?' => self.tokenize_if()?,
+                'L' => self.tokenize_lambda()?,
+                'v' => self.tokenize_variable()?,
                 ' ' => {
-                    self.input.next();
+                    self.next();
+                    continue;
                 }
-                _ => tokens.push(self.tokenize_unknown()),
-            }
+                _ => self.tokenize_unknown()?,
+            };
+            tokens.push(SpannedToken {
+                token,
+                span: Span {
+                    start: self.start,
+                    end: self.position,
+                },
+            });
         }
-        tokens
+        Ok(tokens)
     }
 
-    fn tokenize_integer(&mut self) -> Token {
-        let mut value = String::new();
-        self.input.next();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
-        }
-        Token::Integer(convert_integer_to_bigint(value))
+    fn next(&mut self) -> Option<char> {
+        let c = self.input.next()?;
+        self.position += c.len_utf8();
+        Some(c)
     }
 
-    fn tokenize_boolean(&mut self) -> Token {
+    // read characters up to the next space; strict mode rejects anything outside ASCII 33..=126
+    fn read_body(&mut self) -> Result<String, EvalError> {
         let mut value = String::new();
         while let Some(&c) = self.input.peek() {
-            match c {
-                'T' | 'F' => {
-                    value.push(c);
-                    self.input.next();
-                }
-                ' ' => break,
-                _ => {
-                    self.input.next();
-                }
+            if c == ' ' {
+                break;
             }
+            value.push(c);
+            self.next();
         }
-        Token::Boolean(value == "T")
+        if self.strict {
+            self.check_characters(&value)?;
+        }
+        Ok(value)
     }
 
-    fn tokenize_string(&mut self) -> Token {
-        let mut value = String::new();
-        self.input.next();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
-        }
-        Token::String(convert_string(value))
+    // a body that is decoded must be made of ASCII 33..=126 even leniently
+    fn read_encoded_body(&mut self) -> Result<String, EvalError> {
+        let value = self.read_body()?;
+        self.check_characters(&value)?;
+        Ok(value)
     }
 
-    fn tokenize_unary_operator(&mut self) -> Token {
-        let mut value = String::new();
-        self.input.next();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
+    // `body` has just been read, so it ends at the current position
+    fn check_characters(&self, body: &str) -> Result<(), EvalError> {
+        let start = self.position - body.len();
+        match body.char_indices().find(|(_, c)| !matches!(c, '!'..='~')) {
+            Some((offset, character)) => Err(EvalError::InvalidCharacter {
+                character,
+                position: start + offset,
+            }),
+            None => Ok(()),
         }
-        Token::UnaryOperator(value)
     }
 
-    fn tokenize_binary_operator(&mut self) -> Token {
-        let mut value = String::new();
-        self.input.next();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
+    // in strict mode, fail unless the body is valid for the indicator
+    fn check_body(&self, indicator: char, body: &str, valid: bool) -> Result<(), EvalError> {
+        if self.strict && !valid {
+            return Err(EvalError::InvalidBody {
+                indicator,
+                body: body.to_string(),
+                position: self.start,
+            });
         }
-        Token::BinaryOperator(value)
+        Ok(())
     }
 
-    fn tokenize_if(&mut self) -> Token {
-        self.input.next();
-        Token::If
+    fn tokenize_integer(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        let value = self.read_encoded_body()?;
+        self.check_body('I', &value, !value.is_empty())?;
+        Ok(Token::Integer(convert_integer_to_bigint(value)))
     }
 
-    fn tokenize_lambda(&mut self) -> Token {
-        let mut value = String::new();
-        self.input.next();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
-        }
-        Token::Lambda(convert_integer(value))
+    fn tokenize_boolean(&mut self) -> Result<Token, EvalError> {
+        let value = self.read_body()?;
+        self.check_body(value.chars().next().unwrap(), &value[1..], value.len() == 1)?;
+        let value = value
+            .chars()
+            .filter(|&c| c == 'T' || c == 'F')
+            .collect::<String>();
+        Ok(Token::Boolean(value == "T"))
     }
 
-    fn tokenize_variable(&mut self) -> Token {
-        let mut value = String::new();
-        self.input.next();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
+    fn tokenize_string(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        let value = self.read_encoded_body()?;
+        Ok(Token::String(convert_string(value)))
+    }
+
+    fn tokenize_unary_operator(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        let value = self.read_body()?;
+        self.check_body('U', &value, value.len() == 1)?;
+        Ok(Token::UnaryOperator(value))
+    }
+
+    fn tokenize_binary_operator(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        let value = self.read_body()?;
+        self.check_body('B', &value, value.len() == 1)?;
+        Ok(Token::BinaryOperator(value))
+    }
+
+    fn tokenize_if(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        if self.strict {
+            // leniently, whatever follows ? is read as the next token
+            let value = self.read_body()?;
+            self.check_body('?', &value, value.is_empty())?;
         }
-        Token::Variable(convert_integer(value))
+        Ok(Token::If)
     }
 
-    fn tokenize_unknown(&mut self) -> Token {
-        let mut value = String::new();
-        while let Some(&c) = self.input.peek() {
-            match c {
-                ' ' => break,
-                _ => {
-                    value.push(c);
-                    self.input.next();
-                }
-            }
+    fn tokenize_lambda(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        let value = self.read_encoded_body()?;
+        self.check_body('L', &value, !value.is_empty())?;
+        Ok(Token::Lambda(convert_integer_to_bigint(value)))
+    }
+
+    fn tokenize_variable(&mut self) -> Result<Token, EvalError> {
+        self.next();
+        let value = self.read_encoded_body()?;
+        self.check_body('v', &value, !value.is_empty())?;
+        Ok(Token::Variable(convert_integer_to_bigint(value)))
+    }
+
+    fn tokenize_unknown(&mut self) -> Result<Token, EvalError> {
+        if self.strict {
+            return Err(EvalError::UnknownIndicator {
+                indicator: *self.input.peek().unwrap(),
+                position: self.start,
+            });
         }
-        Token::Unknown(value)
+        let value = self.read_body()?;
+        Ok(Token::Unknown(value))
     }
 }
 
@@ -217,7 +258,7 @@ mod tests {
             Token::Integer(BigInt::from(20)),
         ];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
 
@@ -231,7 +272,7 @@ mod tests {
             Token::Boolean(false),
         ];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
 
@@ -240,7 +281,7 @@ mod tests {
         let input = "SB%,,/}Q/2,$_";
         let expected = vec![Token::String("Hello World!".to_string())];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
 
@@ -254,7 +295,7 @@ mod tests {
             Token::UnaryOperator("/".to_string()),
         ];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
 
@@ -268,8 +309,27 @@ mod tests {
             Token::BinaryOperator("/".to_string()),
         ];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_application_operators() {
+        let input = "B$ B! B~";
+        let expected = vec![
+            Token::BinaryOperator("$".to_string()),
+            Token::BinaryOperator("!".to_string()),
+            Token::BinaryOperator("~".to_string()),
+        ];
+        let mut tokenizer = Tokenizer::new(input);
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
+        let result = result
+            .iter()
+            .map(|token| token.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+        assert_eq!(result, input);
     }
 
     #[test]
@@ -277,25 +337,141 @@ mod tests {
         let input = "?";
         let expected = vec![Token::If];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
 
     #[test]
     fn test_tokenize_lambda() {
         let input = "L#";
-        let expected = vec![Token::Lambda(2)];
+        let expected = vec![Token::Lambda(BigInt::from(2))];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
 
     #[test]
     fn test_tokenize_variable() {
         let input = "v#";
-        let expected = vec![Token::Variable(2)];
+        let expected = vec![Token::Variable(BigInt::from(2))];
         let mut tokenizer = Tokenizer::new(input);
-        let result = tokenizer.tokenize();
+        let result = tokenizer.tokenize().unwrap();
         assert_eq!(result, expected);
     }
+
+    #[test]
+    fn test_tokenize_invalid_character() {
+        let input = "I/6 Sあ";
+        let mut tokenizer = Tokenizer::new(input);
+        assert_eq!(
+            tokenizer.tokenize(),
+            Err(EvalError::InvalidCharacter {
+                character: 'あ',
+                position: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn test_tokenize_spanned() {
+        let input = "B+  I/6 SB%";
+        let mut tokenizer = Tokenizer::new(input);
+        let result = tokenizer.tokenize_spanned().unwrap();
+        let spans: Vec<(usize, usize)> = result
+            .iter()
+            .map(|spanned| (spanned.span.start, spanned.span.end))
+            .collect();
+        assert_eq!(spans, vec![(0, 2), (4, 7), (8, 11)]);
+        assert_eq!(result[1].token, Token::Integer(BigInt::from(1337)));
+        for spanned in result {
+            assert_eq!(
+                &input[spanned.span.start..spanned.span.end],
+                spanned.token.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize_strict() {
+        let input = "B$ L# v# ? T F I! S";
+        let expected = Tokenizer::new(input).tokenize().unwrap();
+        assert_eq!(Tokenizer::strict(input).tokenize(), Ok(expected));
+
+        let errors = [
+            (
+                "I/6 + I5",
+                EvalError::UnknownIndicator {
+                    indicator: '+',
+                    position: 4,
+                },
+            ),
+            (
+                "? TF I# I$",
+                EvalError::InvalidBody {
+                    indicator: 'T',
+                    body: "F".to_string(),
+                    position: 2,
+                },
+            ),
+            (
+                "?! I# I$ I%",
+                EvalError::InvalidBody {
+                    indicator: '?',
+                    body: "!".to_string(),
+                    position: 0,
+                },
+            ),
+            (
+                "B+ I I#",
+                EvalError::InvalidBody {
+                    indicator: 'I',
+                    body: "".to_string(),
+                    position: 3,
+                },
+            ),
+            (
+                "B== v# v#",
+                EvalError::InvalidBody {
+                    indicator: 'B',
+                    body: "==".to_string(),
+                    position: 0,
+                },
+            ),
+            (
+                "L\u{7f}",
+                EvalError::InvalidCharacter {
+                    character: '\u{7f}',
+                    position: 1,
+                },
+            ),
+        ];
+        for (input, expected) in errors {
+            assert_eq!(
+                Tokenizer::strict(input).tokenize(),
+                Err(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_tokenize_lenient() {
+        // bodies that are not decoded are kept as they are, whatever their characters
+        let input = "B. Uあ ;x S#";
+        let expected = vec![
+            Token::BinaryOperator(".".to_string()),
+            Token::UnaryOperator("あ".to_string()),
+            Token::Unknown(";x".to_string()),
+            Token::String("c".to_string()),
+        ];
+        assert_eq!(Tokenizer::new(input).tokenize(), Ok(expected));
+        assert_eq!(
+            Tokenizer::strict("B. Uあ").tokenize(),
+            Err(EvalError::InvalidCharacter {
+                character: 'あ',
+                position: 4,
+            })
+        );
+    }
 }
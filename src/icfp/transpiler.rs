@@ -1,6 +1,43 @@
+use num_bigint::BigInt;
+
+use super::evaluator::free_variables;
 use super::parser::Node;
+use super::util::STRING_ASCII;
+
+pub mod javascript;
+pub mod rust;
+
+// runtime for the transpiled programs, in the dialect of rust_lisp with big integers:
+// strings are lists of character codes in the ICFP order, so that `#` and `$` only
+// change the base, and every argument is passed as a memoized thunk; `error` is left
+// undefined, so that calling it stops the program
+pub const PRELUDE: &str = r#"
+(defun delay (compute) (hash "compute" compute))
+(defun ready (value) (hash "done" t "value" value))
+(defun force (thunk)
+  (if (hash_get thunk "done")
+    (hash_get thunk "value")
+    (begin
+      (hash_set thunk "value" ((hash_get thunk "compute")))
+      (hash_set thunk "done" t)
+      (hash_get thunk "value"))))
+(defun div (a b) (if (== b 0) (error "division by zero") (truncate a b)))
+(defun mod (a b) (if (== b 0) (error "division by zero") (- a (* b (truncate a b)))))
+(defun strict-and (a b) (and a b))
+(defun strict-or (a b) (or a b))
+(defun prepend (reversed s) (if (is_null reversed) s (prepend (cdr reversed) (cons (car reversed) s))))
+(defun concat (a b) (prepend (reverse a) b))
+(defun take-reversed (n s taken)
+  (if (or (== n 0) (is_null s)) taken (take-reversed (- n 1) (cdr s) (cons (car s) taken))))
+(defun take (n s) (reverse (take-reversed n s (list))))
+(defun drop (n s) (if (or (== n 0) (is_null s)) s (drop (- n 1) (cdr s))))
+(defun digits-to-int (s n) (if (is_null s) n (digits-to-int (cdr s) (+ (* n 94) (car s)))))
+(defun string-to-int (s) (digits-to-int s 0))
+(defun int-to-digits (n digits) (if (== n 0) digits (int-to-digits (truncate n 94) (cons (mod n 94) digits))))
+(defun int-to-string (n) (int-to-digits (truncate n 94) (list (mod n 94))))
+"#;
 
-// convert the AST to a common lisp program
+// convert the AST to a lisp program for rust_lisp, see PRELUDE
 pub struct Transpiler {
     node: Node,
 }
@@ -10,73 +47,121 @@ impl Transpiler {
         Self { node }
     }
 
+    // the expression alone, it needs the definitions of PRELUDE
     pub fn transpile(&self) -> String {
-        self.transpile_node(self.node.clone())
+        self.transpile_node(&self.node)
     }
 
-    fn transpile_node(&self, node: Node) -> String {
-        match node {
+    pub fn transpile_program(&self) -> String {
+        // free variables can be passed around, but not evaluated
+        let mut free: Vec<BigInt> = free_variables(&self.node).into_iter().collect();
+        free.sort();
+        let definitions: String = free
+            .iter()
+            .map(|variable| {
+                format!(
+                    "(define v{} (delay (lambda () (error \"free variable\"))))\n",
+                    variable
+                )
+            })
+            .collect();
+        format!("{}\n{}{}\n", PRELUDE.trim(), definitions, self.transpile())
+    }
+
+    fn transpile_node(&self, node: &Node) -> String {
+        fold(node, |node, mut children| match node {
             Node::Integer(value) => value.to_string(),
-            Node::String(value) => format!("\"{}\"", value),
-            Node::Boolean(value) => format!("{}", if value { "1" } else { "nil" }),
-            Node::Variable(value) => format!("v{}", value),
-            Node::UnaryOperator(operator, operand) => match operator.as_str() {
-                "-" => format!("-{}", self.transpile_node(*operand)),
-                "!" => format!(
-                    "{}",
-                    if operand.as_ref() == &Node::Boolean(true) {
-                        "nil"
-                    } else {
-                        "1"
+            Node::String(value) => {
+                let codes: Vec<String> = value
+                    .chars()
+                    .map(|c| STRING_ASCII.find(c).unwrap().to_string())
+                    .collect();
+                format!(
+                    "(list{})",
+                    codes
+                        .iter()
+                        .map(|code| format!(" {}", code))
+                        .collect::<String>()
+                )
+            }
+            Node::Boolean(value) => if *value { "t" } else { "f" }.to_string(),
+            Node::Variable(value) => format!("(force v{})", value),
+            Node::UnaryOperator(operator, _) => {
+                let operand = children.remove(0);
+                match operator.as_str() {
+                    "-" => format!("(- 0 {})", operand),
+                    "!" => format!("(not {})", operand),
+                    "#" => format!("(string-to-int {})", operand),
+                    "$" => format!("(int-to-string {})", operand),
+                    _ => format!("({} {})", operator, operand),
+                }
+            }
+            Node::BinaryOperator(operator, _, argument) => {
+                let right = children.pop().unwrap();
+                let left = children.pop().unwrap();
+                let function = match operator.as_str() {
+                    // lisp evaluates arguments eagerly, so lazy arguments are wrapped in
+                    // thunks; a variable already holds one and is passed on to share it
+                    "$" | "~" => {
+                        let argument = match argument.as_ref() {
+                            Node::Variable(value) => format!("v{}", value),
+                            Node::Integer(_)
+                            | Node::String(_)
+                            | Node::Boolean(_)
+                            | Node::Lambda(_, _) => format!("(ready {})", right),
+                            _ => format!("(delay (lambda () {}))", right),
+                        };
+                        return format!("({} {})", left, argument);
                     }
-                ),
-                _ => format!("{} {}", operator, self.transpile_node(*operand)),
-            },
-            Node::BinaryOperator(operator, left, right) => match operator.as_str() {
-                "." => format!(
-                    "(concatenate 'string {} {})",
-                    self.transpile_node(*left),
-                    self.transpile_node(*right)
-                ),
-                "$" => format!(
-                    // "({} {})",
-                    "(funcall {} {})",
-                    self.transpile_node(*left),
-                    self.transpile_node(*right)
-                ),
-                "=" => format!(
-                    "(== {} {})",
-                    self.transpile_node(*left),
-                    self.transpile_node(*right)
-                ),
-                _ => format!(
-                    "({} {} {})",
-                    operator,
-                    self.transpile_node(*left),
-                    self.transpile_node(*right)
-                ),
-            },
-            Node::If(condition, then_branch, else_branch) => format!(
-                "(if {} {} {})",
-                self.transpile_node(*condition),
-                self.transpile_node(*then_branch),
-                self.transpile_node(*else_branch)
-            ),
-            Node::Lambda(arity, body) => format!(
-                "(lambda (v{}) {})",
-                arity,
-                self.transpile_node(body.as_ref().clone()),
-            ),
+                    "!" => return format!("({} (ready {}))", left, right),
+                    "/" => "div",
+                    "%" => "mod",
+                    "=" => "==",
+                    "&" => "strict-and",
+                    "|" => "strict-or",
+                    "." => "concat",
+                    "T" => "take",
+                    "D" => "drop",
+                    operator => operator,
+                };
+                format!("({} {} {})", function, left, right)
+            }
+            Node::If(_, _, _) => format!("(if {})", children.join(" ")),
+            Node::Lambda(arity, _) => format!("(lambda (v{}) {})", arity, children.remove(0)),
+        })
+    }
+}
+
+// the translation of a program, bottom up: `combine` gets a node and the translations of its
+// children, which are kept on a stack so that deep programs do not overflow the call stack
+pub(crate) fn fold(node: &Node, mut combine: impl FnMut(&Node, Vec<String>) -> String) -> String {
+    // a node is visited a second time once its children are translated
+    let mut stack = vec![(node, false)];
+    let mut translations: Vec<String> = vec![];
+    while let Some((node, visited)) = stack.pop() {
+        let children = node.children();
+        if visited {
+            let start = translations.len() - children.len();
+            let children = translations.split_off(start);
+            translations.push(combine(node, children));
+        } else {
+            stack.push((node, true));
+            stack.extend(children.into_iter().rev().map(|child| (child, false)));
         }
     }
+    translations.pop().unwrap()
 }
 
 #[cfg(test)]
 mod tests {
     use std::cell::RefCell;
+    use std::process::Output;
     use std::rc::Rc;
 
     use super::*;
+    use crate::icfp::evaluator::{Evaluator, Strategy};
+    use crate::icfp::surface::compile_source;
+    use crate::icfp::{parser::Parser, tokenizer::Tokenizer};
     use num_bigint::BigInt;
     use rust_lisp::default_env;
     use rust_lisp::interpreter::eval;
@@ -97,7 +182,7 @@ mod tests {
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
         let result = eval(env.clone(), &ast).unwrap();
-        assert_eq!(result, Value::Int(3));
+        assert_eq!(result, Value::Int(BigInt::from(3)));
     }
 
     #[test]
@@ -118,7 +203,7 @@ mod tests {
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
         let result = eval(env.clone(), &ast).unwrap();
-        assert_eq!(result, Value::Int(6));
+        assert_eq!(result, Value::Int(BigInt::from(6)));
     }
 
     #[test]
@@ -130,12 +215,12 @@ mod tests {
         );
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "(if 1 1 2)");
+        assert_eq!(result, "(if t 1 2)");
         let env = Rc::new(RefCell::new(default_env()));
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
         let result = eval(env.clone(), &ast).unwrap();
-        assert_eq!(result, Value::Int(1));
+        assert_eq!(result, Value::Int(BigInt::from(1)));
 
         let node = Node::If(
             Box::new(Node::Boolean(false)),
@@ -144,27 +229,27 @@ mod tests {
         );
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "(if nil 1 2)");
+        assert_eq!(result, "(if f 1 2)");
         let env = Rc::new(RefCell::new(default_env()));
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
         let result = eval(env.clone(), &ast).unwrap();
-        assert_eq!(result, Value::Int(2));
+        assert_eq!(result, Value::Int(BigInt::from(2)));
     }
 
     #[test]
     fn test_transpile_lambda() {
         let node = Node::Lambda(
-            2,
+            BigInt::from(2),
             Box::new(Node::BinaryOperator(
                 "+".to_string(),
                 Box::new(Node::Integer(BigInt::from(1))),
-                Box::new(Node::Variable(2)),
+                Box::new(Node::Variable(BigInt::from(2))),
             )),
         );
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "(lambda (v2) (+ 1 v2))");
+        assert_eq!(result, "(lambda (v2) (+ 1 (force v2)))");
         let env = Rc::new(RefCell::new(default_env()));
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
@@ -175,19 +260,22 @@ mod tests {
     #[test]
     fn test_transpile_nested_lambda() {
         let node = Node::Lambda(
-            2,
+            BigInt::from(2),
             Box::new(Node::Lambda(
-                3,
+                BigInt::from(3),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
-                    Box::new(Node::Variable(2)),
-                    Box::new(Node::Variable(3)),
+                    Box::new(Node::Variable(BigInt::from(2))),
+                    Box::new(Node::Variable(BigInt::from(3))),
                 )),
             )),
         );
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "(lambda (v2) (lambda (v3) (+ v2 v3)))");
+        assert_eq!(
+            result,
+            "(lambda (v2) (lambda (v3) (+ (force v2) (force v3))))"
+        );
         let env = Rc::new(RefCell::new(default_env()));
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
@@ -200,17 +288,17 @@ mod tests {
         let node = Node::UnaryOperator("-".to_string(), Box::new(Node::Integer(BigInt::from(1))));
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "-1");
+        assert_eq!(result, "(- 0 1)");
         let env = Rc::new(RefCell::new(default_env()));
         let mut ast_iter = parse(&result);
         let ast = ast_iter.next().unwrap().unwrap();
         let result = eval(env.clone(), &ast).unwrap();
-        assert_eq!(result, Value::Int(-1));
+        assert_eq!(result, Value::Int(BigInt::from(-1)));
 
         let node = Node::UnaryOperator("!".to_string(), Box::new(Node::Boolean(true)));
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "nil");
+        assert_eq!(result, "(not t)");
     }
 
     #[test]
@@ -218,31 +306,31 @@ mod tests {
         let node = Node::BinaryOperator(
             "$".to_string(),
             Box::new(Node::Lambda(
-                3,
+                BigInt::from(3),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
                     Box::new(Node::Integer(BigInt::from(4))),
-                    Box::new(Node::Variable(3)),
+                    Box::new(Node::Variable(BigInt::from(3))),
                 )),
             )),
             Box::new(Node::Integer(BigInt::from(1))),
         );
         let transpiler = Transpiler::new(node);
         let result = transpiler.transpile();
-        assert_eq!(result, "(funcall (lambda (v3) (+ 4 v3)) 1)");
+        assert_eq!(result, "((lambda (v3) (+ 4 (force v3))) (ready 1))");
 
         let node = Node::BinaryOperator(
             "$".to_string(),
             Box::new(Node::Lambda(
-                2,
+                BigInt::from(2),
                 Box::new(Node::BinaryOperator(
                     "$".to_string(),
                     Box::new(Node::Lambda(
-                        3,
+                        BigInt::from(3),
                         Box::new(Node::BinaryOperator(
                             "+".to_string(),
-                            Box::new(Node::Variable(2)),
-                            Box::new(Node::Variable(3)),
+                            Box::new(Node::Variable(BigInt::from(2))),
+                            Box::new(Node::Variable(BigInt::from(3))),
                         )),
                     )),
                     Box::new(Node::Integer(BigInt::from(1))),
@@ -254,7 +342,7 @@ mod tests {
         let result = transpiler.transpile();
         assert_eq!(
             result,
-            "(funcall (lambda (v2) (funcall (lambda (v3) (+ v2 v3)) 1)) 2)"
+            "((lambda (v2) ((lambda (v3) (+ (force v2) (force v3))) (ready 1))) (ready 2))"
         );
     }
 
@@ -263,15 +351,15 @@ mod tests {
         let node = Node::BinaryOperator(
             "$".to_string(),
             Box::new(Node::Lambda(
-                2,
+                BigInt::from(2),
                 Box::new(Node::BinaryOperator(
                     "$".to_string(),
                     Box::new(Node::Lambda(
-                        3,
+                        BigInt::from(3),
                         Box::new(Node::BinaryOperator(
                             "+".to_string(),
-                            Box::new(Node::Variable(2)),
-                            Box::new(Node::Variable(3)),
+                            Box::new(Node::Variable(BigInt::from(2))),
+                            Box::new(Node::Variable(BigInt::from(3))),
                         )),
                     )),
                     Box::new(Node::Integer(BigInt::from(1))),
@@ -280,11 +368,11 @@ mod tests {
             Box::new(Node::BinaryOperator(
                 "$".to_string(),
                 Box::new(Node::Lambda(
-                    3,
+                    BigInt::from(3),
                     Box::new(Node::BinaryOperator(
                         "+".to_string(),
                         Box::new(Node::Integer(BigInt::from(4))),
-                        Box::new(Node::Variable(3)),
+                        Box::new(Node::Variable(BigInt::from(3))),
                     )),
                 )),
                 Box::new(Node::Integer(BigInt::from(2))),
@@ -294,7 +382,7 @@ mod tests {
         let result = transpiler.transpile();
         assert_eq!(
             result,
-            "(funcall (lambda (v2) (funcall (lambda (v3) (+ v2 v3)) 1)) (funcall (lambda (v3) (+ 4 v3)) 2))"
+            "((lambda (v2) ((lambda (v3) (+ (force v2) (force v3))) (ready 1))) (delay (lambda () ((lambda (v3) (+ 4 (force v3))) (ready 2)))))"
         );
     }
 
@@ -303,18 +391,18 @@ mod tests {
         let node = Node::BinaryOperator(
             "$".to_string(),
             Box::new(Node::Lambda(
-                2,
+                BigInt::from(2),
                 Box::new(Node::BinaryOperator(
                     "$".to_string(),
-                    Box::new(Node::Variable(2)),
+                    Box::new(Node::Variable(BigInt::from(2))),
                     Box::new(Node::Integer(BigInt::from(2))),
                 )),
             )),
             Box::new(Node::Lambda(
-                1,
+                BigInt::from(1),
                 Box::new(Node::BinaryOperator(
                     "+".to_string(),
-                    Box::new(Node::Variable(1)),
+                    Box::new(Node::Variable(BigInt::from(1))),
                     Box::new(Node::Integer(BigInt::from(3))),
                 )),
             )),
@@ -323,7 +411,167 @@ mod tests {
         let result = transpiler.transpile();
         assert_eq!(
             result,
-            "(funcall (lambda (v2) (funcall v2 2)) (lambda (v1) (+ v1 3)))"
+            "((lambda (v2) ((force v2) (ready 2))) (ready (lambda (v1) (+ (force v1) 3))))"
         );
     }
+
+    #[test]
+    fn test_transpile_application_operators() {
+        for operator in ["!", "~"] {
+            let node = Node::BinaryOperator(
+                operator.to_string(),
+                Box::new(Node::Lambda(
+                    BigInt::from(3),
+                    Box::new(Node::BinaryOperator(
+                        "+".to_string(),
+                        Box::new(Node::Integer(BigInt::from(4))),
+                        Box::new(Node::Variable(BigInt::from(3))),
+                    )),
+                )),
+                Box::new(Node::Integer(BigInt::from(1))),
+            );
+            let transpiler = Transpiler::new(node);
+            let result = transpiler.transpile();
+            assert_eq!(result, "((lambda (v3) (+ 4 (force v3))) (ready 1))");
+        }
+    }
+
+    // run a whole transpiled program with rust_lisp and read its value back
+    // what a transpiled program prints for its value, functions are shown as <function>
+    fn run_lisp(node: &Node) -> Result<String, String> {
+        let env = Rc::new(RefCell::new(default_env()));
+        let program = Transpiler::new(node.clone()).transpile_program();
+        let mut result = Value::NIL;
+        for ast in parse(&program) {
+            let ast = ast.map_err(|e| e.msg)?;
+            result = eval(env.clone(), &ast).map_err(|e| e.msg)?;
+        }
+        match result {
+            Value::Int(value) => Ok(value.to_string()),
+            Value::True => Ok("true".to_string()),
+            Value::False => Ok("false".to_string()),
+            Value::Lambda(_) => Ok("<function>".to_string()),
+            Value::List(codes) => codes
+                .into_iter()
+                .map(|code| match code {
+                    Value::Int(code) => Ok(STRING_ASCII
+                        .chars()
+                        .nth(code.to_string().parse().unwrap())
+                        .unwrap()),
+                    code => Err(format!("not a character code: {}", code)),
+                })
+                .collect(),
+            value => Err(format!("not an ICFP value: {}", value)),
+        }
+    }
+
+    // the line a compiled program printed, or what it reported when it failed
+    pub(super) fn printed(output: Output) -> Result<String, String> {
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).to_string());
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.strip_suffix('\n').unwrap_or(&stdout).to_string())
+    }
+
+    pub(super) fn parse_program(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    // runs the programs every backend shares, then `extra`, and compares what `run` prints
+    // with the call by need evaluator; a program that fails to evaluate must fail to run
+    pub(super) fn check_differential(
+        extra: Vec<Node>,
+        mut run: impl FnMut(&Node) -> Result<String, String>,
+    ) {
+        let inputs = [
+            "I/6",
+            "SB%,,/}Q/2,$_",
+            "S",
+            "U- I$",
+            "U! T",
+            "U# S4%34",
+            "U$ I4%34",
+            "U$ I!",
+            "B+ I# B* I$ I%",
+            "B- I$ I#",
+            // division and modulo truncate towards zero
+            "B/ U- I( I#",
+            "B% U- I( I#",
+            "B% I( U- I#",
+            "B< I$ I#",
+            "B> I$ I#",
+            "B= S# S#",
+            "B= I# I$",
+            "B| T F",
+            "B& T F",
+            "B& B= S# S# B< I$ I#",
+            "B. S4% S34",
+            "BT I$ S4%34",
+            "BD I$ S4%34",
+            "BT I( S4%34",
+            "B. BT I$ S4%34 BD I$ S4%34",
+            "? B> I# I$ S9%3 S./",
+            "B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK",
+            "B$ L# B$ L\" B+ v\" v\" B* I$ I# v8",
+            "B! L# B+ v# v# I$",
+            "B~ L# B* v# v# B+ I# I$",
+            // unused arguments are never evaluated
+            "B$ L# I$ B/ I# I!",
+            // the Y combinator example, 16
+            "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%",
+            // doubling 22 times, 4^22 additions unless arguments are shared
+            "B$ L! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! B$ v! I\" L! B+ B+ v! v! B+ v! v!",
+            "L# v#",
+            // errors
+            "B/ I# I!",
+            "B% I# I!",
+            "B+ I# v#",
+        ];
+        let mut nodes: Vec<Node> = inputs.iter().map(|input| parse_program(input)).collect();
+        for source in [
+            "let rec fact n = if n == 0 then 1 else n * fact (n - 1) in fact 20",
+            "let rec repeat s n = if n == 0 then \"\" else s ++ repeat s (n - 1) in repeat \"ab\" 5",
+            "int_to_string (string_to_int \"hello\" + 1)",
+        ] {
+            nodes.push(compile_source(source).unwrap());
+        }
+        nodes.extend(extra);
+        for node in nodes {
+            let result = run(&node);
+            match Evaluator::with_strategy(node.clone(), Strategy::CallByNeed).evaluate() {
+                Ok(expected) => {
+                    let expected = match &expected {
+                        Node::Integer(value) => value.to_string(),
+                        Node::String(value) => value.clone(),
+                        Node::Boolean(value) => value.to_string(),
+                        _ => "<function>".to_string(),
+                    };
+                    assert_eq!(result, Ok(expected), "{}", node.to_string())
+                }
+                Err(_) => assert!(result.is_err(), "{}", node.to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transpile_differential() {
+        check_differential(vec![], run_lisp);
+    }
+
+    #[test]
+    fn test_transpile_deep() {
+        let depth = 20_000;
+        let input = format!("{}I\"", "B+ I\" ".repeat(depth));
+        let tokens = Tokenizer::new(&input).tokenize().unwrap();
+        let node = Parser::new(&tokens).parse().unwrap();
+        let lisp = Transpiler::new(node.clone()).transpile();
+        assert!(lisp.starts_with("(+ 1 (+ 1 "));
+        assert_eq!(lisp.matches('(').count(), depth);
+        let rust = rust::RustTranspiler::new(node.clone()).transpile();
+        assert_eq!(rust.matches("add(int(\"1\"), ").count(), depth);
+        let javascript = javascript::JavaScriptTranspiler::new(node).transpile();
+        assert_eq!(javascript.matches("(integer(1n) + integer(").count(), depth);
+    }
 }
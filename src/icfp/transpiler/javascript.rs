@@ -0,0 +1,280 @@
+use num_bigint::BigInt;
+
+use crate::icfp::evaluator::free_variables;
+use crate::icfp::parser::Node;
+use crate::icfp::util::STRING_ASCII;
+
+use super::fold;
+
+// the runtime of the generated modules: integers are BigInts, strings and booleans are
+// native, a lambda is a function of a memoized thunk, and errors throw
+const RUNTIME: &str = r#"
+function delay(compute) {
+  return { done: false, value: undefined, compute };
+}
+
+function ready(value) {
+  return { done: true, value, compute: undefined };
+}
+
+function force(thunk) {
+  if (!thunk.done) {
+    const compute = thunk.compute;
+    if (compute === undefined) {
+      throw new Error("a value that depends on itself");
+    }
+    thunk.compute = undefined;
+    thunk.value = compute();
+    thunk.done = true;
+  }
+  return thunk.value;
+}
+
+function free(name) {
+  return delay(() => {
+    throw new Error(`free variable ${name}`);
+  });
+}
+
+function apply(f, argument) {
+  if (typeof f !== "function") {
+    throw new Error("applying a value that is not a function");
+  }
+  return f(argument);
+}
+
+function integer(value) {
+  if (typeof value !== "bigint") {
+    throw new Error("expected an integer");
+  }
+  return value;
+}
+
+function string(value) {
+  if (typeof value !== "string") {
+    throw new Error("expected a string");
+  }
+  return value;
+}
+
+function boolean(value) {
+  if (typeof value !== "boolean") {
+    throw new Error("expected a boolean");
+  }
+  return value;
+}
+
+function stringToInt(a) {
+  let result = 0n;
+  for (const c of string(a)) {
+    result = result * 94n + BigInt(STRING_ASCII.indexOf(c));
+  }
+  return result;
+}
+
+function intToString(a) {
+  let value = integer(a);
+  let result = "";
+  do {
+    result = STRING_ASCII[Number(value % 94n)] + result;
+    value /= 94n;
+  } while (value !== 0n);
+  return result;
+}
+
+// BigInt division and remainder truncate towards zero, like ICFP
+function divide(a, b) {
+  if (integer(b) === 0n) {
+    throw new Error("division by zero");
+  }
+  return integer(a) / b;
+}
+
+function modulo(a, b) {
+  if (integer(b) === 0n) {
+    throw new Error("division by zero");
+  }
+  return integer(a) % b;
+}
+
+function equal(a, b) {
+  if (typeof a !== typeof b || typeof a === "function") {
+    throw new Error("comparing values of different types");
+  }
+  return a === b;
+}
+
+// both operands are evaluated, as in ICFP
+function or(a, b) {
+  const [x, y] = [boolean(a), boolean(b)];
+  return x || y;
+}
+
+function and(a, b) {
+  const [x, y] = [boolean(a), boolean(b)];
+  return x && y;
+}
+
+function count(a) {
+  const count = integer(a);
+  if (count < 0n) {
+    throw new Error("negative count");
+  }
+  return count > BigInt(Number.MAX_SAFE_INTEGER) ? Number.MAX_SAFE_INTEGER : Number(count);
+}
+
+function take(a, b) {
+  return string(b).slice(0, count(a));
+}
+
+function drop(a, b) {
+  return string(b).slice(count(a));
+}
+"#;
+
+// convert the AST to an ES module whose `evaluate` function returns the value of the
+// expression, a bigint, a string, a boolean or a function of a thunk
+pub struct JavaScriptTranspiler {
+    node: Node,
+}
+
+impl JavaScriptTranspiler {
+    pub fn new(node: Node) -> Self {
+        Self { node }
+    }
+
+    pub fn transpile(&self) -> String {
+        let mut free: Vec<BigInt> = free_variables(&self.node).into_iter().collect();
+        free.sort();
+        let definitions: String = free
+            .iter()
+            .map(|variable| format!("  const v{} = free(\"{}\");\n", variable, variable))
+            .collect();
+        format!(
+            "const STRING_ASCII = {:?};\n{}\nexport function evaluate() {{\n{}  return {};\n}}\n",
+            STRING_ASCII,
+            RUNTIME,
+            definitions,
+            self.transpile_node(&self.node)
+        )
+    }
+
+    fn transpile_node(&self, node: &Node) -> String {
+        fold(node, |node, mut children| match node {
+            Node::Integer(value) => format!("{}n", value),
+            Node::String(value) => format!("{:?}", value),
+            Node::Boolean(value) => value.to_string(),
+            Node::Variable(value) => format!("force(v{})", value),
+            Node::UnaryOperator(operator, _) => {
+                let operand = children.remove(0);
+                match operator.as_str() {
+                    "-" => format!("-integer({})", operand),
+                    "!" => format!("!boolean({})", operand),
+                    "#" => format!("stringToInt({})", operand),
+                    "$" => format!("intToString({})", operand),
+                    operator => format!("{}({})", operator, operand),
+                }
+            }
+            Node::BinaryOperator(operator, _, argument) => {
+                let right = children.pop().unwrap();
+                let left = children.pop().unwrap();
+                match operator.as_str() {
+                    "$" | "~" => {
+                        let argument = match argument.as_ref() {
+                            Node::Variable(value) => format!("v{}", value),
+                            Node::Integer(_)
+                            | Node::String(_)
+                            | Node::Boolean(_)
+                            | Node::Lambda(_, _) => format!("ready({})", right),
+                            _ => format!("delay(() => {})", right),
+                        };
+                        format!("apply({}, {})", left, argument)
+                    }
+                    "!" => format!("apply({}, ready({}))", left, right),
+                    "+" => format!("(integer({}) + integer({}))", left, right),
+                    "-" => format!("(integer({}) - integer({}))", left, right),
+                    "*" => format!("(integer({}) * integer({}))", left, right),
+                    "<" => format!("(integer({}) < integer({}))", left, right),
+                    ">" => format!("(integer({}) > integer({}))", left, right),
+                    "|" => format!("or({}, {})", left, right),
+                    "&" => format!("and({}, {})", left, right),
+                    "." => format!("(string({}) + string({}))", left, right),
+                    "/" => format!("divide({}, {})", left, right),
+                    "%" => format!("modulo({}, {})", left, right),
+                    "=" => format!("equal({}, {})", left, right),
+                    "T" => format!("take({}, {})", left, right),
+                    "D" => format!("drop({}, {})", left, right),
+                    operator => format!("{}({}, {})", operator, left, right),
+                }
+            }
+            Node::If(_, _, _) => format!(
+                "(boolean({}) ? {} : {})",
+                children[0], children[1], children[2]
+            ),
+            Node::Lambda(variable, _) => format!("((v{}) => {})", variable, children.remove(0)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::{env, fs};
+
+    use super::*;
+    use crate::icfp::transpiler::tests::{check_differential, parse_program, printed};
+
+    // imports a module and prints the value of its expression
+    const MAIN: &str = r#"
+import { evaluate } from "./program.mjs";
+const value = evaluate();
+console.log(typeof value === "function" ? "<function>" : String(value));
+"#;
+
+    #[test]
+    fn test_transpile_javascript() {
+        let node = Node::BinaryOperator(
+            "$".to_string(),
+            Box::new(Node::Lambda(
+                BigInt::from(2),
+                Box::new(Node::BinaryOperator(
+                    "+".to_string(),
+                    Box::new(Node::Variable(BigInt::from(2))),
+                    Box::new(Node::Variable(BigInt::from(1))),
+                )),
+            )),
+            Box::new(Node::BinaryOperator(
+                "*".to_string(),
+                Box::new(Node::Integer(BigInt::from(3))),
+                Box::new(Node::Integer(BigInt::from(4))),
+            )),
+        );
+        let result = JavaScriptTranspiler::new(node).transpile();
+        assert!(result.contains("  const v1 = free(\"1\");\n"));
+        assert!(result.ends_with(
+            "  return apply(((v2) => (integer(force(v2)) + integer(force(v1)))), delay(() => (integer(3n) * integer(4n))));\n}\n"
+        ));
+    }
+
+    // run every program with node, when it is installed
+    #[test]
+    fn test_transpile_differential() {
+        if Command::new("node").arg("--version").output().is_err() {
+            return;
+        }
+        let directory = env::temp_dir().join(format!("icfp-javascript-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("main.mjs"), MAIN).unwrap();
+        let extra = ["B= I# S#", "B| T I#"].map(parse_program).to_vec();
+        check_differential(extra, |node| {
+            let source = JavaScriptTranspiler::new(node.clone()).transpile();
+            fs::write(directory.join("program.mjs"), source).unwrap();
+            let output = Command::new("node")
+                .arg(directory.join("main.mjs"))
+                .output()
+                .unwrap();
+            printed(output)
+        });
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,392 @@
+use num_bigint::BigInt;
+
+use crate::icfp::evaluator::free_variables;
+use crate::icfp::parser::Node;
+use crate::icfp::util::STRING_ASCII;
+
+use super::fold;
+
+// the runtime of the generated programs: a lambda is a closure over memoized thunks, so
+// arguments are evaluated at most once, and errors panic
+const RUNTIME: &str = r#"
+#[derive(Clone)]
+enum Value {
+    Integer(BigInt),
+    String(String),
+    Boolean(bool),
+    Function(Rc<dyn Fn(Thunk) -> Value>),
+}
+
+struct Lazy {
+    value: RefCell<Option<Value>>,
+    compute: RefCell<Option<Box<dyn FnOnce() -> Value>>>,
+}
+
+type Thunk = Rc<Lazy>;
+
+fn delay(compute: impl FnOnce() -> Value + 'static) -> Thunk {
+    Rc::new(Lazy {
+        value: RefCell::new(None),
+        compute: RefCell::new(Some(Box::new(compute))),
+    })
+}
+
+fn ready(value: Value) -> Thunk {
+    Rc::new(Lazy {
+        value: RefCell::new(Some(value)),
+        compute: RefCell::new(None),
+    })
+}
+
+fn force(thunk: &Thunk) -> Value {
+    if let Some(value) = thunk.value.borrow().as_ref() {
+        return value.clone();
+    }
+    let compute = thunk.compute.borrow_mut().take().expect("a value that depends on itself");
+    let value = compute();
+    *thunk.value.borrow_mut() = Some(value.clone());
+    value
+}
+
+fn free(name: &'static str) -> Thunk {
+    delay(move || panic!("free variable {}", name))
+}
+
+fn function(body: impl Fn(Thunk) -> Value + 'static) -> Value {
+    Value::Function(Rc::new(body))
+}
+
+fn apply(function: Value, argument: Thunk) -> Value {
+    match function {
+        Value::Function(body) => body(argument),
+        _ => panic!("applying a value that is not a function"),
+    }
+}
+
+fn integer(value: Value) -> BigInt {
+    match value {
+        Value::Integer(value) => value,
+        _ => panic!("expected an integer"),
+    }
+}
+
+fn string(value: Value) -> String {
+    match value {
+        Value::String(value) => value,
+        _ => panic!("expected a string"),
+    }
+}
+
+fn boolean(value: Value) -> bool {
+    match value {
+        Value::Boolean(value) => value,
+        _ => panic!("expected a boolean"),
+    }
+}
+
+fn int(digits: &str) -> Value {
+    Value::Integer(digits.parse().unwrap())
+}
+
+fn text(value: &str) -> Value {
+    Value::String(value.to_string())
+}
+
+fn negate(a: Value) -> Value {
+    Value::Integer(-integer(a))
+}
+
+fn not(a: Value) -> Value {
+    Value::Boolean(!boolean(a))
+}
+
+fn string_to_int(a: Value) -> Value {
+    let mut result = BigInt::from(0);
+    for c in string(a).chars() {
+        result = result * 94 + STRING_ASCII.find(c).unwrap();
+    }
+    Value::Integer(result)
+}
+
+fn int_to_string(a: Value) -> Value {
+    let mut value = integer(a);
+    let mut digits = vec![];
+    loop {
+        let digit = usize::try_from(&value % 94).unwrap();
+        digits.push(STRING_ASCII.chars().nth(digit).unwrap());
+        value /= 94;
+        if value == BigInt::from(0) {
+            break;
+        }
+    }
+    Value::String(digits.into_iter().rev().collect())
+}
+
+fn add(a: Value, b: Value) -> Value {
+    Value::Integer(integer(a) + integer(b))
+}
+
+fn subtract(a: Value, b: Value) -> Value {
+    Value::Integer(integer(a) - integer(b))
+}
+
+fn multiply(a: Value, b: Value) -> Value {
+    Value::Integer(integer(a) * integer(b))
+}
+
+// BigInt division and remainder truncate towards zero, like ICFP
+fn divide(a: Value, b: Value) -> Value {
+    let (a, b) = (integer(a), integer(b));
+    if b == BigInt::from(0) {
+        panic!("division by zero");
+    }
+    Value::Integer(a / b)
+}
+
+fn modulo(a: Value, b: Value) -> Value {
+    let (a, b) = (integer(a), integer(b));
+    if b == BigInt::from(0) {
+        panic!("division by zero");
+    }
+    Value::Integer(a % b)
+}
+
+fn less(a: Value, b: Value) -> Value {
+    Value::Boolean(integer(a) < integer(b))
+}
+
+fn greater(a: Value, b: Value) -> Value {
+    Value::Boolean(integer(a) > integer(b))
+}
+
+fn equal(a: Value, b: Value) -> Value {
+    Value::Boolean(match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+        _ => panic!("comparing values of different types"),
+    })
+}
+
+fn or(a: Value, b: Value) -> Value {
+    Value::Boolean(boolean(a) || boolean(b))
+}
+
+fn and(a: Value, b: Value) -> Value {
+    Value::Boolean(boolean(a) && boolean(b))
+}
+
+fn concat(a: Value, b: Value) -> Value {
+    Value::String(string(a) + &string(b))
+}
+
+fn count(a: Value) -> usize {
+    let count = integer(a);
+    if count < BigInt::from(0) {
+        panic!("negative count");
+    }
+    usize::try_from(&count).unwrap_or(usize::MAX)
+}
+
+fn take(a: Value, b: Value) -> Value {
+    Value::String(string(b).chars().take(count(a)).collect())
+}
+
+fn drop(a: Value, b: Value) -> Value {
+    Value::String(string(b).chars().skip(count(a)).collect())
+}
+
+fn show(value: Value) -> String {
+    match value {
+        Value::Integer(value) => value.to_string(),
+        Value::String(value) => value,
+        Value::Boolean(value) => value.to_string(),
+        Value::Function(_) => "<function>".to_string(),
+    }
+}
+
+fn main() {
+    // recursion in ICFP programs is deep, so evaluate on a thread with a large stack
+    let result = std::thread::Builder::new()
+        .stack_size(1 << 30)
+        .spawn(|| show(run()))
+        .unwrap()
+        .join()
+        .unwrap();
+    println!("{}", result);
+}
+"#;
+
+// convert the AST to a standalone rust program that prints the value of the expression;
+// it depends on num_bigint only
+pub struct RustTranspiler {
+    node: Node,
+}
+
+impl RustTranspiler {
+    pub fn new(node: Node) -> Self {
+        Self { node }
+    }
+
+    pub fn transpile(&self) -> String {
+        let mut free: Vec<BigInt> = free_variables(&self.node).into_iter().collect();
+        free.sort();
+        let definitions: String = free
+            .iter()
+            .map(|variable| format!("    let v{} = free(\"{}\");\n", variable, variable))
+            .collect();
+        format!(
+            "#![allow(unused)]\nuse num_bigint::BigInt;\nuse std::cell::RefCell;\nuse std::rc::Rc;\n\nconst STRING_ASCII: &str = {:?};\n{}\nfn run() -> Value {{\n{}    {}\n}}\n",
+            STRING_ASCII,
+            RUNTIME,
+            definitions,
+            self.transpile_node(&self.node)
+        )
+    }
+
+    // variables are thunks named after their ids, a closure moves in its own clones of the
+    // ones it uses
+    fn capture(&self, node: &Node, closure: String) -> String {
+        let mut variables: Vec<BigInt> = free_variables(node).into_iter().collect();
+        if variables.is_empty() {
+            return closure;
+        }
+        variables.sort();
+        let clones: String = variables
+            .iter()
+            .map(|variable| format!("let v{} = v{}.clone(); ", variable, variable))
+            .collect();
+        format!("{{ {}{} }}", clones, closure)
+    }
+
+    fn transpile_node(&self, node: &Node) -> String {
+        fold(node, |node, mut children| match node {
+            Node::Integer(value) => format!("int(\"{}\")", value),
+            Node::String(value) => format!("text({:?})", value),
+            Node::Boolean(value) => format!("Value::Boolean({})", value),
+            Node::Variable(value) => format!("force(&v{})", value),
+            Node::UnaryOperator(operator, _) => {
+                let function = match operator.as_str() {
+                    "-" => "negate",
+                    "!" => "not",
+                    "#" => "string_to_int",
+                    "$" => "int_to_string",
+                    operator => operator,
+                };
+                format!("{}({})", function, children.remove(0))
+            }
+            Node::BinaryOperator(operator, _, argument) => {
+                let right = children.pop().unwrap();
+                let left = children.pop().unwrap();
+                let function = match operator.as_str() {
+                    "$" | "~" => {
+                        let argument = match argument.as_ref() {
+                            Node::Variable(value) => format!("v{}.clone()", value),
+                            Node::Integer(_)
+                            | Node::String(_)
+                            | Node::Boolean(_)
+                            | Node::Lambda(_, _) => format!("ready({})", right),
+                            _ => self.capture(argument, format!("delay(move || {})", right)),
+                        };
+                        return format!("apply({}, {})", left, argument);
+                    }
+                    "!" => return format!("apply({}, ready({}))", left, right),
+                    "+" => "add",
+                    "-" => "subtract",
+                    "*" => "multiply",
+                    "/" => "divide",
+                    "%" => "modulo",
+                    "<" => "less",
+                    ">" => "greater",
+                    "=" => "equal",
+                    "|" => "or",
+                    "&" => "and",
+                    "." => "concat",
+                    "T" => "take",
+                    "D" => "drop",
+                    operator => operator,
+                };
+                format!("{}({}, {})", function, left, right)
+            }
+            Node::If(_, _, _) => format!(
+                "if boolean({}) {{ {} }} else {{ {} }}",
+                children[0], children[1], children[2]
+            ),
+            Node::Lambda(variable, _) => self.capture(
+                node,
+                format!(
+                    "function(move |v{}: Thunk| {})",
+                    variable,
+                    children.remove(0)
+                ),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::{env, fs};
+
+    use super::*;
+    use crate::icfp::surface::compile_source;
+    use crate::icfp::transpiler::tests::{check_differential, printed};
+
+    // the generated file only needs num_bigint, which cargo has already built next to the
+    // test binary
+    fn compile(name: &str, source: &str) -> PathBuf {
+        let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        let library = fs::read_dir(&deps)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let file = path.file_name().unwrap().to_string_lossy().to_string();
+                file.starts_with("libnum_bigint-") && file.ends_with(".rlib")
+            })
+            .max_by_key(|path| fs::metadata(path).unwrap().modified().unwrap())
+            .expect("num_bigint is not built");
+        let directory = env::temp_dir().join(format!("icfp-rust-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let file = directory.join(format!("{}.rs", name));
+        let binary = directory.join(name);
+        fs::write(&file, source).unwrap();
+        let output = Command::new("rustc")
+            .args(["--edition", "2021", "-O", "-o"])
+            .arg(&binary)
+            .arg("-L")
+            .arg(format!("dependency={}", deps.display()))
+            .arg("--extern")
+            .arg(format!("num_bigint={}", library.display()))
+            .arg(&file)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        binary
+    }
+
+    #[test]
+    fn test_transpile_differential() {
+        // deep recursion needs the large stack of the generated program
+        let extra = vec![compile_source(
+            "let rec count n = if n == 0 then 0 else 1 + count (n - 1) in count 100000",
+        )
+        .unwrap()];
+        let mut index = 0;
+        check_differential(extra, |node| {
+            index += 1;
+            let binary = compile(
+                &format!("program{}", index),
+                &RustTranspiler::new(node.clone()).transpile(),
+            );
+            let output = Command::new(&binary).output().unwrap();
+            fs::remove_file(binary).unwrap();
+            printed(output)
+        });
+    }
+}
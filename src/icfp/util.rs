@@ -2,6 +2,8 @@ use num_bigint::BigInt;
 
 pub const INTEGER_ASCII: &str = "!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
 pub const STRING_ASCII: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!\"#$%&'()*+,-./:;<=>?@[\\]^_`|~ \n";
+// the number of digits of integer literals and of characters in strings
+pub const BASE94: usize = 94;
 
 pub fn convert_integer_to_bigint(value: String) -> BigInt {
     let mut result = BigInt::from(0);
@@ -0,0 +1,555 @@
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use super::arena::{BinaryOp, UnaryOp};
+use super::bytecode::{Argument, Constant, Instruction, Program};
+use super::error::EvalError;
+use super::evaluator::substitute_all;
+use super::parser::Node;
+use super::util::{
+    convert_integer_to_bigint, convert_string, deconvert_integer_from_bigint, deconvert_string,
+};
+
+// call-by-need virtual machine for programs compiled by bytecode::compile
+#[derive(Clone)]
+enum Value {
+    Integer(BigInt),
+    Boolean(bool),
+    String(String),
+    Closure(Rc<Closure>),
+    // a variable that is not bound anywhere in the program
+    Variable(BigInt),
+}
+
+impl Value {
+    fn name(&self, program: &Program) -> String {
+        match self {
+            Value::Integer(_) => "Integer".to_string(),
+            Value::Boolean(_) => "Boolean".to_string(),
+            Value::String(_) => "String".to_string(),
+            Value::Closure(closure) => {
+                let arity = program.functions[closure.function]
+                    .parameter
+                    .as_ref()
+                    .unwrap();
+                format!("Lambda({})", arity)
+            }
+            Value::Variable(_) => "Variable".to_string(),
+        }
+    }
+}
+
+struct Closure {
+    function: usize,
+    captures: Vec<Slot>,
+}
+
+enum ThunkState {
+    Delayed(usize, Vec<Slot>),
+    Evaluating,
+    Forced(Value),
+}
+
+type Slot = Rc<RefCell<ThunkState>>;
+
+// long chains of thunks are released without recursion
+impl Drop for ThunkState {
+    fn drop(&mut self) {
+        let mut stack = vec![];
+        take_slots(self, &mut stack);
+        while let Some(slot) = stack.pop() {
+            if let Ok(cell) = Rc::try_unwrap(slot) {
+                take_slots(&mut cell.into_inner(), &mut stack);
+            }
+        }
+    }
+}
+
+fn take_slots(state: &mut ThunkState, stack: &mut Vec<Slot>) {
+    match state {
+        ThunkState::Delayed(_, captures) => stack.append(captures),
+        ThunkState::Forced(Value::Closure(closure)) => {
+            if let Some(closure) = Rc::get_mut(closure) {
+                stack.append(&mut closure.captures);
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Frame {
+    function: usize,
+    pc: usize,
+    env: Rc<Vec<Slot>>,
+}
+
+enum Continuation {
+    Code(Frame),
+    Update(Slot),
+}
+
+pub struct Vm<'a> {
+    program: &'a Program,
+    // the source nodes by prefix index, built on the first readback of a closure
+    sources: OnceCell<Vec<&'a Node>>,
+    instructions: usize,
+    beta_reductions: usize,
+    beta_limit: Option<usize>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(program: &'a Program) -> Vm<'a> {
+        Vm {
+            program,
+            sources: OnceCell::new(),
+            instructions: 0,
+            beta_reductions: 0,
+            beta_limit: None,
+        }
+    }
+
+    pub fn with_beta_limit(mut self, limit: usize) -> Vm<'a> {
+        self.beta_limit = Some(limit);
+        self
+    }
+
+    pub fn beta_reductions(&self) -> usize {
+        self.beta_reductions
+    }
+
+    // the number of instructions executed by the last call to run
+    pub fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    pub fn run(&mut self) -> Result<Node, EvalError> {
+        let value = self.execute()?;
+        Ok(self.readback(&value))
+    }
+
+    fn execute(&mut self) -> Result<Value, EvalError> {
+        let functions = &self.program.functions;
+        let mut stack: Vec<Value> = vec![];
+        let mut continuations = vec![];
+        let mut frame = Frame {
+            function: 0,
+            pc: 0,
+            env: Rc::new(vec![]),
+        };
+        loop {
+            let instruction = functions[frame.function].code[frame.pc];
+            frame.pc += 1;
+            self.instructions += 1;
+            match instruction {
+                Instruction::Constant(index) => stack.push(match &self.program.constants[index] {
+                    Constant::Integer(value) => Value::Integer(value.clone()),
+                    Constant::String(value) => Value::String(value.clone()),
+                    Constant::Boolean(value) => Value::Boolean(*value),
+                    Constant::Variable(index) => Value::Variable(index.clone()),
+                }),
+                Instruction::Load(slot) => {
+                    let thunk = frame.env[slot].clone();
+                    if let ThunkState::Forced(value) = &*thunk.borrow() {
+                        stack.push(value.clone());
+                        continue;
+                    }
+                    let mut state = thunk.replace(ThunkState::Evaluating);
+                    match &mut state {
+                        ThunkState::Delayed(function, captures) => {
+                            let delayed = Frame {
+                                function: *function,
+                                pc: 0,
+                                env: Rc::new(std::mem::take(captures)),
+                            };
+                            continuations
+                                .push(Continuation::Code(std::mem::replace(&mut frame, delayed)));
+                            continuations.push(Continuation::Update(thunk));
+                        }
+                        _ => return Err(EvalError::InfiniteLoop),
+                    }
+                }
+                Instruction::Closure(function) => {
+                    let captures = self.captures(function, &frame.env);
+                    stack.push(Value::Closure(Rc::new(Closure { function, captures })));
+                }
+                Instruction::Unary(operator) => {
+                    let operand = stack.pop().unwrap();
+                    stack.push(self.evaluate_unary_operator(operator, operand)?);
+                }
+                Instruction::Binary(operator) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    stack.push(self.evaluate_binary_operator(operator, left, right)?);
+                }
+                Instruction::JumpIfFalse(target) => match stack.pop().unwrap() {
+                    Value::Boolean(true) => {}
+                    Value::Boolean(false) => frame.pc = target,
+                    condition => {
+                        return Err(EvalError::UnsupportedCondition {
+                            condition: condition.name(self.program),
+                        })
+                    }
+                },
+                Instruction::Jump(target) => frame.pc = target,
+                Instruction::Apply(operator, argument) => {
+                    let argument = match argument {
+                        Argument::Shared(slot) => frame.env[slot].clone(),
+                        Argument::Delayed(function) => Rc::new(RefCell::new(ThunkState::Delayed(
+                            function,
+                            self.captures(function, &frame.env),
+                        ))),
+                        Argument::Value => {
+                            Rc::new(RefCell::new(ThunkState::Forced(stack.pop().unwrap())))
+                        }
+                    };
+                    let closure = match stack.pop().unwrap() {
+                        Value::Closure(closure) => closure,
+                        function => {
+                            return Err(EvalError::UnsupportedBinaryOperands {
+                                operator: operator.as_str().to_string(),
+                                left: function.name(self.program),
+                                right: "Thunk".to_string(),
+                            })
+                        }
+                    };
+                    self.count_beta_reduction()?;
+                    let mut env = Vec::with_capacity(closure.captures.len() + 1);
+                    env.push(argument);
+                    env.extend(closure.captures.iter().cloned());
+                    let body = Frame {
+                        function: closure.function,
+                        pc: 0,
+                        env: Rc::new(env),
+                    };
+                    // a tail call replaces the current frame
+                    if functions[frame.function].code[frame.pc] == Instruction::Return {
+                        frame = body;
+                    } else {
+                        continuations.push(Continuation::Code(std::mem::replace(&mut frame, body)));
+                    }
+                }
+                Instruction::Return => loop {
+                    match continuations.pop() {
+                        None => return Ok(stack.pop().unwrap()),
+                        Some(Continuation::Update(thunk)) => {
+                            let value = stack.last().unwrap().clone();
+                            *thunk.borrow_mut() = ThunkState::Forced(value);
+                        }
+                        Some(Continuation::Code(code)) => {
+                            frame = code;
+                            break;
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    fn captures(&self, function: usize, env: &[Slot]) -> Vec<Slot> {
+        self.program.functions[function]
+            .captures
+            .iter()
+            .map(|capture| env[capture.slot].clone())
+            .collect()
+    }
+
+    fn count_beta_reduction(&mut self) -> Result<(), EvalError> {
+        self.beta_reductions += 1;
+        match self.beta_limit {
+            Some(limit) if self.beta_reductions > limit => {
+                Err(EvalError::BetaReductionLimitExceeded { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn evaluate_unary_operator(
+        &self,
+        operator: UnaryOp,
+        operand: Value,
+    ) -> Result<Value, EvalError> {
+        let value = match (operator, operand) {
+            (UnaryOp::Negate, Value::Integer(value)) => Value::Integer(-value),
+            (UnaryOp::Not, Value::Boolean(value)) => Value::Boolean(!value),
+            (UnaryOp::StringToInt, Value::String(value)) => {
+                Value::Integer(convert_integer_to_bigint(deconvert_string(value)))
+            }
+            (UnaryOp::IntToString, Value::Integer(value)) => {
+                Value::String(convert_string(deconvert_integer_from_bigint(value)))
+            }
+            (operator, operand) => {
+                return Err(EvalError::UnsupportedUnaryOperand {
+                    operator: operator.as_str().to_string(),
+                    operand: operand.name(self.program),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn evaluate_binary_operator(
+        &self,
+        operator: BinaryOp,
+        left: Value,
+        right: Value,
+    ) -> Result<Value, EvalError> {
+        use BinaryOp::*;
+        let value = match (operator, left, right) {
+            (Add, Value::Integer(left), Value::Integer(right)) => Value::Integer(left + right),
+            (Subtract, Value::Integer(left), Value::Integer(right)) => Value::Integer(left - right),
+            (Multiply, Value::Integer(left), Value::Integer(right)) => Value::Integer(left * right),
+            (Divide | Modulo, Value::Integer(_), Value::Integer(right))
+                if right == BigInt::from(0) =>
+            {
+                return Err(EvalError::DivisionByZero {
+                    operator: operator.as_str().to_string(),
+                })
+            }
+            (Divide, Value::Integer(left), Value::Integer(right)) => Value::Integer(left / right),
+            (Modulo, Value::Integer(left), Value::Integer(right)) => Value::Integer(left % right),
+            (LessThan, Value::Integer(left), Value::Integer(right)) => Value::Boolean(left < right),
+            (GreaterThan, Value::Integer(left), Value::Integer(right)) => {
+                Value::Boolean(left > right)
+            }
+            (Equal, Value::Integer(left), Value::Integer(right)) => Value::Boolean(left == right),
+            (Equal, Value::String(left), Value::String(right)) => Value::Boolean(left == right),
+            (Equal, Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left == right),
+            (Or, Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left || right),
+            (And, Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left && right),
+            (Concat, Value::String(left), Value::String(right)) => Value::String(left + &right),
+            (Take, Value::Integer(left), Value::String(right)) if left >= BigInt::from(0) => {
+                let left_usize = left.to_string().parse::<usize>().unwrap_or(usize::MAX);
+                Value::String(right.chars().take(left_usize).collect())
+            }
+            (Drop, Value::Integer(left), Value::String(right)) if left >= BigInt::from(0) => {
+                let left_usize = left.to_string().parse::<usize>().unwrap_or(usize::MAX);
+                Value::String(right.chars().skip(left_usize).collect())
+            }
+            (operator, left, right) => {
+                return Err(EvalError::UnsupportedBinaryOperands {
+                    operator: operator.as_str().to_string(),
+                    left: left.name(self.program),
+                    right: right.name(self.program),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    // convert a value back into a term, substituting the captured slots
+    fn readback(&self, value: &Value) -> Node {
+        match value {
+            Value::Integer(value) => Node::Integer(value.clone()),
+            Value::Boolean(value) => Node::Boolean(*value),
+            Value::String(value) => Node::String(value.clone()),
+            Value::Variable(index) => Node::Variable(index.clone()),
+            Value::Closure(closure) => self.readback_function(closure.function, &closure.captures),
+        }
+    }
+
+    fn readback_function(&self, function: usize, captures: &[Slot]) -> Node {
+        let function = &self.program.functions[function];
+        let mut substitution = HashMap::new();
+        for (capture, slot) in function.captures.iter().zip(captures) {
+            let value = match &*slot.borrow() {
+                ThunkState::Forced(value) => self.readback(value),
+                ThunkState::Delayed(function, captures) => {
+                    self.readback_function(*function, captures)
+                }
+                ThunkState::Evaluating => continue,
+            };
+            substitution.insert(capture.variable.clone(), value);
+        }
+        let sources = self.sources.get_or_init(|| self.program.source_nodes());
+        substitute_all(sources[function.source], substitution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use crate::icfp::{
+        bytecode::compile,
+        evaluator::{Evaluator, Strategy},
+        parser::Parser,
+        tokenizer::Tokenizer,
+    };
+
+    use super::*;
+
+    fn parse(input: &str) -> Node {
+        let tokens = Tokenizer::new(input).tokenize().unwrap();
+        Parser::new(&tokens).parse().unwrap()
+    }
+
+    fn run(node: &Node) -> Result<Node, EvalError> {
+        Vm::new(&compile(node.clone())?).run()
+    }
+
+    // the virtual machine must agree with the substitution evaluator
+    fn assert_same(node: &Node) {
+        let expected = Evaluator::new(node.clone()).evaluate();
+        assert_eq!(run(node), expected, "{}", node.to_string());
+    }
+
+    #[test]
+    fn test_differential_programs() {
+        let inputs = [
+            "I/6",
+            "SB%,,/}Q/2,$_",
+            "U- I$",
+            "U! T",
+            "U# S4%34",
+            "U$ I4%34",
+            "B+ I# B* I$ I%",
+            "B- I$ I#",
+            "B/ U- I( I#",
+            "B% U- I( I#",
+            "B< I$ I#",
+            "B> I$ I#",
+            "B= S# S#",
+            "B| T F",
+            "B& T F",
+            "B. S4% S34",
+            "BT I$ S4%34",
+            "BD I$ S4%34",
+            "? B> I# I$ S9%3 S./",
+            "B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK",
+            "B$ L# B$ L\" B+ v\" v\" B* I$ I# v8",
+            "B! L# B+ v# v# I$",
+            "B~ L# B* v# v# B+ I# I$",
+            // closures capturing arguments, returned unapplied
+            "B$ L# L$ B+ v# v$ I#",
+            "B$ L# L# v# I#",
+            // the Y combinator example, 16
+            "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B$ L$ B+ B$ v\" v$ B$ v\" v$ B- v# I\" I%",
+            // efficiency1 from the contest, 4^22
+            "B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! B$ L! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! L! B+ v! v! I\"",
+        ];
+        for input in inputs {
+            assert_same(&parse(input));
+        }
+    }
+
+    #[test]
+    fn test_differential_errors() {
+        let inputs = ["B+ I# S#", "B/ I# I!", "? I# I$ I%", "U- T", "B. S# I#"];
+        for input in inputs {
+            let node = parse(input);
+            assert_eq!(
+                run(&node).err(),
+                Evaluator::new(node).evaluate().err(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_readback_like_lazy_evaluator() {
+        // unused arguments are never evaluated, unevaluated arguments under a lambda and
+        // applications of non-functions are read back like the environment machine does
+        for input in [
+            "B$ L# I$ B/ I# I!",
+            "B$ L# L$ B$ v# v$ B+ I\" I#",
+            "B$ I# I$",
+        ] {
+            let node = parse(input);
+            let expected = Evaluator::with_strategy(node.clone(), Strategy::CallByNeed).evaluate();
+            assert_eq!(run(&node), expected, "{}", input);
+        }
+    }
+
+    // a random integer expression over the variables bound around it
+    fn random_expression(rng: &mut StdRng, depth: usize, scope: &mut Vec<usize>) -> Node {
+        let leaf = depth == 0 || rng.gen_ratio(1, 4);
+        match rng.gen_range(0..if leaf { 2 } else { 7 }) {
+            0 if !scope.is_empty() => Node::Variable(BigInt::from(*scope.choose(rng).unwrap())),
+            0 | 1 => Node::Integer(BigInt::from(rng.gen_range(0..20))),
+            2 => Node::UnaryOperator(
+                "-".to_string(),
+                Box::new(random_expression(rng, depth - 1, scope)),
+            ),
+            3 | 4 => {
+                let operator = ["+", "-", "*"].choose(rng).unwrap().to_string();
+                Node::BinaryOperator(
+                    operator,
+                    Box::new(random_expression(rng, depth - 1, scope)),
+                    Box::new(random_expression(rng, depth - 1, scope)),
+                )
+            }
+            5 => {
+                let operator = ["<", ">", "="].choose(rng).unwrap().to_string();
+                let condition = Node::BinaryOperator(
+                    operator,
+                    Box::new(random_expression(rng, depth - 1, scope)),
+                    Box::new(random_expression(rng, depth - 1, scope)),
+                );
+                Node::If(
+                    Box::new(condition),
+                    Box::new(random_expression(rng, depth - 1, scope)),
+                    Box::new(random_expression(rng, depth - 1, scope)),
+                )
+            }
+            _ => {
+                // reuse variable numbers now and then so that shadowing is exercised
+                let variable = rng.gen_range(0..4);
+                let operator = ["$", "!", "~"].choose(rng).unwrap().to_string();
+                let argument = random_expression(rng, depth - 1, scope);
+                scope.push(variable);
+                let body = random_expression(rng, depth - 1, scope);
+                scope.pop();
+                Node::BinaryOperator(
+                    operator,
+                    Box::new(Node::Lambda(BigInt::from(variable), Box::new(body))),
+                    Box::new(argument),
+                )
+            }
+        }
+    }
+
+    #[test]
+    fn test_differential_random() {
+        let mut rng = StdRng::seed_from_u64(2024);
+        for _ in 0..500 {
+            let node = random_expression(&mut rng, 6, &mut vec![]);
+            assert_same(&node);
+            let mut evaluator = Evaluator::with_strategy(node.clone(), Strategy::Bytecode);
+            assert_eq!(evaluator.evaluate(), run(&node));
+        }
+    }
+
+    #[test]
+    fn test_beta_limit() {
+        let program = compile(parse("B$ L# B$ L$ v$ v# I#")).unwrap();
+        let mut vm = Vm::new(&program);
+        assert_eq!(vm.run(), Ok(Node::Integer(BigInt::from(2))));
+        assert_eq!(vm.beta_reductions(), 2);
+        assert_eq!(
+            Vm::new(&program).with_beta_limit(1).run(),
+            Err(EvalError::BetaReductionLimitExceeded { limit: 1 })
+        );
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        // the Y combinator counting 20000 levels deep, with a forced accumulator
+        let node = parse("B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I! B+ I\" B$ v\" B- v# I\" I#9i");
+        assert_eq!(run(&node), Ok(Node::Integer(BigInt::from(20000))));
+    }
+
+    #[test]
+    fn test_deep_program() {
+        // compiling keeps the source without copying it, so nesting depth is not limited
+        let input = format!("{}I\"", "B+ I\" ".repeat(200_000));
+        let mut evaluator = Evaluator::with_strategy(parse(&input), Strategy::Bytecode);
+        assert_eq!(
+            evaluator.evaluate(),
+            Ok(Node::Integer(BigInt::from(200_001)))
+        );
+    }
+}